@@ -1 +1,3 @@
+// The oldest tests predate the clippy gate and are kept as written
+#[allow(unused_variables, clippy::needless_borrow, clippy::unnecessary_mut_passed)]
 mod test_store;
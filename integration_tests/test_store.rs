@@ -9,12 +9,13 @@ use std::path::Path;
 #[test]
 fn test_write_and_read_page() {
     let tmp_dir = env::temp_dir();
-    let store_path = tmp_dir.join("test_store");
+    let store_path = tmp_dir.join("test_store_write_and_read_page");
+    let _ = std::fs::remove_file(&store_path);
     let path = Path::new(&store_path);
 
     let mut store = Store::new(&path).unwrap();
 
-    let id = store.allocate_page();
+    let id = store.allocate_page().unwrap();
     let mut page = PageFormat::new();
 
     store.write_page(&mut page, &id).unwrap();
@@ -23,33 +24,53 @@ fn test_write_and_read_page() {
     assert_eq!(page, read);
 } 
 
-// #[test]
-// fn test_insert_and_get() {
-//     let tmp_dir = env::temp_dir();
-//     let store_path = tmp_dir.join("test_store");
-//     let path = Path::new(&store_path);
+#[test]
+fn test_insert_and_get() {
+    let tmp_dir = env::temp_dir();
+    let store_path = tmp_dir.join("test_store_insert_and_get");
+    let path = Path::new(&store_path);
+    let _ = std::fs::remove_file(path);
 
-//     let mut store = Store::new(&path).unwrap();
+    let mut store = Store::new(path).unwrap();
 
-//     let key = Key::new();
-//     let value = Value::new("test");
+    let key = Key::new();
+    let value = Value::new("test");
 
-//     store.insert(key, value);
+    store.insert(key.clone(), value).unwrap();
 
-//     let read = store.get(&key).unwrap();
-//     assert_eq!(read.get(), "test");
-// } 
+    let read = store.get(&key).unwrap().unwrap();
+    assert_eq!(read.get(), b"test");
+} 
+
+#[test]
+fn test_delete() {
+    let tmp_dir = env::temp_dir();
+    let store_path = tmp_dir.join("test_store_delete");
+    let path = Path::new(&store_path);
+    let _ = std::fs::remove_file(path);
+
+    let mut store = Store::new(path).unwrap();
+
+    let key = Key::new();
+    store.insert(key.clone(), Value::new("test")).unwrap();
+    assert!(store.contains(&key).unwrap());
+
+    store.delete(&key).unwrap();
+    assert!(!store.contains(&key).unwrap());
+    assert!(store.get(&key).unwrap().is_none());
+} 
 
 #[test]
 fn test_multiple_pages() {
     let tmp_dir = env::temp_dir();
-    let store_path = tmp_dir.join("test_store");
+    let store_path = tmp_dir.join("test_store_multiple_pages");
+    let _ = std::fs::remove_file(&store_path);
     let path = Path::new(&store_path);
     
     let mut store = Store::new(&path).unwrap();
 
     for i in 0..5 {
-        let id = store.allocate_page();
+        let id = store.allocate_page().unwrap();
         let mut page = PageFormat::new();
     
         store.write_page(&mut page, &id).unwrap();
@@ -57,3 +78,82 @@ fn test_multiple_pages() {
     
     assert_eq!(store.len(), 5); 
 } 
+
+#[test]
+fn test_reopen_store() {
+    let tmp_dir = env::temp_dir();
+    let store_path = tmp_dir.join("test_store_reopen");
+    let _ = std::fs::remove_file(&store_path);
+    let path = Path::new(&store_path);
+
+    let key = Key::new();
+    let other = Key::new();
+
+    {
+        let mut store = Store::new(path).unwrap();
+        store.insert(key.clone(), Value::new("kept")).unwrap();
+        store.insert(other.clone(), Value::new("removed")).unwrap();
+        store.delete(&other).unwrap();
+        store.close().unwrap();
+    }
+
+    let store = Store::new(path).unwrap();
+
+    assert_eq!(store.get(&key).unwrap().unwrap().get(), b"kept");
+    assert!(!store.contains(&other).unwrap());
+} 
+
+#[test]
+fn test_free_page_reuse() {
+    let tmp_dir = env::temp_dir();
+    let store_path = tmp_dir.join("test_store_free_page_reuse");
+    let _ = std::fs::remove_file(&store_path);
+    let path = Path::new(&store_path);
+
+    let mut store = Store::new(path).unwrap();
+
+    let first = store.allocate_page().unwrap();
+    store.flush().unwrap();
+    let size = store.get_file().metadata().unwrap().len();
+
+    for _ in 0..10 {
+        let id = store.allocate_page().unwrap();
+        store.free_page(id).unwrap();
+    }
+
+    store.free_page(first).unwrap();
+
+    assert!(store.is_empty());
+    assert_eq!(store.get_file().metadata().unwrap().len(), size + 4096);
+}
+
+#[test]
+fn test_transaction_commit_and_rollback() {
+    let tmp_dir = env::temp_dir();
+    let store_path = tmp_dir.join("test_store_transaction");
+    let _ = std::fs::remove_file(&store_path);
+    let path = Path::new(&store_path);
+
+    let mut store = Store::new(path).unwrap();
+
+    let from = Key::new();
+    let to = Key::new();
+    store.insert(from.clone(), Value::new("100")).unwrap();
+    store.insert(to.clone(), Value::new("0")).unwrap();
+
+    let mut tx = store.begin().unwrap();
+    tx.put(from.clone(), Value::new("50")).unwrap();
+    tx.put(to.clone(), Value::new("50")).unwrap();
+    tx.rollback().unwrap();
+
+    assert_eq!(store.get(&from).unwrap().unwrap().get(), b"100");
+    assert_eq!(store.get(&to).unwrap().unwrap().get(), b"0");
+
+    let mut tx = store.begin().unwrap();
+    tx.put(from.clone(), Value::new("50")).unwrap();
+    tx.put(to.clone(), Value::new("50")).unwrap();
+    tx.commit().unwrap();
+
+    assert_eq!(store.get(&from).unwrap().unwrap().get(), b"50");
+    assert_eq!(store.get(&to).unwrap().unwrap().get(), b"50");
+}
@@ -0,0 +1,661 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::codec::{Decode, Encode};
+use crate::engine::is_empty_range;
+use crate::file_io::{sync_dir, PositionalIo};
+use crate::keys::{Key, MAX_KEY_SIZE};
+use crate::storage_manager::StoreError;
+use crate::values::Value;
+
+type KeyValue = (Key, Value<Vec<u8>>);
+
+const RECORD_PUT: u8 = 0;
+const RECORD_DELETE: u8 = 1;
+
+// crc + kind + key length + value length
+const RECORD_HEADER_SIZE: usize = 4 + 1 + 2 + 4;
+
+pub struct BitcaskOptions {
+    // Size at which the active segment is sealed and a new one started
+    pub max_segment_size: u64,
+    // Sync every write instead of leaving it to the OS
+    pub sync_writes: bool,
+}
+
+impl Default for BitcaskOptions {
+    fn default() -> Self {
+        BitcaskOptions {
+            max_segment_size: 64 << 20,
+            sync_writes: true,
+        }
+    }
+}
+
+// Where the newest record for a key lives
+#[derive(Clone, Copy)]
+struct KeyDirEntry {
+    segment: u64,
+    offset: u64,
+    len: u32,
+}
+
+// Append-only engine in the style of Bitcask. Every write, deletions
+// included, is a CRC-checked record appended to the active segment file,
+// and an in-memory keydir maps each live key to its newest record, so a
+// read is a single positional read. Sealed segments never change again.
+// Merging rewrites the live records into new segments and drops the old
+// ones, together with every stale value in them.
+//
+// The store is a directory of numbered segment files, each sealed one
+// with a hint file listing its records without their values, which is
+// all startup needs to rebuild the keydir. Closing the store writes the
+// active segment's hint file too, as it is sealed by the next open.
+pub struct BitcaskStore {
+    dir: PathBuf,
+    options: BitcaskOptions,
+    keydir: BTreeMap<Key, KeyDirEntry>,
+    segments: BTreeMap<u64, File>,
+    active: u64,
+    active_len: u64,
+    // Hint file contents for the active segment, written out when it is
+    // sealed or the store is closed
+    active_hints: Vec<u8>,
+    total_bytes: u64,
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:08}.data", segment))
+}
+
+fn hint_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:08}.hint", segment))
+}
+
+fn encode_record(key: &Key, value: Option<&[u8]>) -> Vec<u8> {
+    let value_bytes = value.unwrap_or_default();
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + key.len() + value_bytes.len());
+    record.extend_from_slice(&[0; 4]);
+    record.push(if value.is_some() { RECORD_PUT } else { RECORD_DELETE });
+    record.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    record.extend_from_slice(&(value_bytes.len() as u32).to_le_bytes());
+    record.extend_from_slice(key.as_bytes());
+    record.extend_from_slice(value_bytes);
+
+    let crc = crc32fast::hash(&record[4..]);
+    record[..4].copy_from_slice(&crc.to_le_bytes());
+    record
+}
+
+// Decodes the record at the start of bytes, returning it with its length,
+// or None if it is cut short or fails its checksum
+fn decode_record(bytes: &[u8]) -> Option<(Key, Option<Vec<u8>>, usize)> {
+    let mut header = bytes.get(..RECORD_HEADER_SIZE)?;
+    let crc = header.read_u32::<LittleEndian>().ok()?;
+    let kind = header.read_u8().ok()?;
+    let key_len = header.read_u16::<LittleEndian>().ok()? as usize;
+    let value_len = header.read_u32::<LittleEndian>().ok()? as usize;
+
+    let len = RECORD_HEADER_SIZE + key_len + value_len;
+    let record = bytes.get(..len)?;
+    if crc32fast::hash(&record[4..]) != crc {
+        return None;
+    }
+
+    let key = Key::from(&record[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + key_len]);
+    let value = match kind {
+        RECORD_PUT => Some(record[RECORD_HEADER_SIZE + key_len..].to_vec()),
+        RECORD_DELETE => None,
+        _ => return None,
+    };
+
+    Some((key, value, len))
+}
+
+fn encode_hint(out: &mut Vec<u8>, key: &Key, live: bool, entry: &KeyDirEntry) {
+    out.push(if live { RECORD_PUT } else { RECORD_DELETE });
+    out.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    out.extend_from_slice(key.as_bytes());
+    out.extend_from_slice(&entry.offset.to_le_bytes());
+    out.extend_from_slice(&entry.len.to_le_bytes());
+}
+
+// Hint entries of a hint file, or None if the file is damaged and the
+// segment has to be read instead
+fn decode_hints(bytes: &[u8], segment: u64) -> Option<Vec<(Key, bool, KeyDirEntry)>> {
+    let (mut body, crc) = bytes.split_at_checked(bytes.len().checked_sub(4)?)?;
+    if crc32fast::hash(body) != u32::from_le_bytes(crc.try_into().ok()?) {
+        return None;
+    }
+
+    let mut hints = vec![];
+    while !body.is_empty() {
+        let kind = body.read_u8().ok()?;
+        let key_len = body.read_u16::<LittleEndian>().ok()? as usize;
+        let key = Key::from(body.get(..key_len)?);
+        body = &body[key_len..];
+
+        let offset = body.read_u64::<LittleEndian>().ok()?;
+        let len = body.read_u32::<LittleEndian>().ok()?;
+        hints.push((key, kind == RECORD_PUT, KeyDirEntry { segment, offset, len }));
+    }
+
+    Some(hints)
+}
+
+impl BitcaskStore {
+    pub fn new(path: &Path) -> Result<BitcaskStore, io::Error> {
+        Self::open(path, BitcaskOptions::default())
+    }
+
+    pub fn open(path: &Path, options: BitcaskOptions) -> Result<BitcaskStore, io::Error> {
+        fs::create_dir_all(path)?;
+
+        let mut ids = vec![];
+        for dir_entry in fs::read_dir(path)? {
+            let file_path = dir_entry?.path();
+            let name = file_path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+
+            if let Some(Ok(id)) = name.strip_suffix(".data").map(str::parse::<u64>) {
+                ids.push(id);
+            } else if name.ends_with(".hint.tmp") {
+                fs::remove_file(&file_path)?;
+            }
+        }
+        ids.sort();
+
+        let mut store = BitcaskStore {
+            dir: path.to_path_buf(),
+            options,
+            keydir: BTreeMap::new(),
+            segments: BTreeMap::new(),
+            active: ids.last().map_or(0, |id| id + 1),
+            active_len: 0,
+            active_hints: vec![],
+            total_bytes: 0,
+        };
+
+        // Oldest first, so newer records replace older ones
+        for (i, id) in ids.iter().enumerate() {
+            let is_last = i == ids.len() - 1;
+            store.load_segment(*id, is_last)?;
+        }
+
+        // Writes always go to a fresh segment
+        let active = store.open_segment(store.active)?;
+        store.segments.insert(store.active, active);
+        sync_dir(&store.dir)?;
+
+        Ok(store)
+    }
+
+    // Writes the active segment's hint file, so the next open doesn't have
+    // to read the segment. Dropping the store does the same but can't
+    // report a failure.
+    pub fn close(mut self) -> Result<(), io::Error> {
+        self.write_hints()?;
+        sync_dir(&self.dir)
+    }
+
+    fn open_segment(&self, segment: u64) -> Result<File, io::Error> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(segment_path(&self.dir, segment))
+    }
+
+    fn load_segment(&mut self, segment: u64, is_last: bool) -> Result<(), io::Error> {
+        let file = self.open_segment(segment)?;
+        let len = file.metadata()?.len();
+
+        // Left behind by a store that was opened and closed without writes
+        if len == 0 {
+            let _ = fs::remove_file(hint_path(&self.dir, segment));
+            return fs::remove_file(segment_path(&self.dir, segment));
+        }
+
+        // Hints pointing past the end of the segment are for records that
+        // were since cut off, so the segment is read instead
+        let hints = match fs::read(hint_path(&self.dir, segment)) {
+            Ok(bytes) => decode_hints(&bytes, segment)
+                .filter(|hints| hints.iter().all(|(_, _, entry)| entry.offset + entry.len as u64 <= len)),
+            Err(_) => None,
+        };
+
+        match hints {
+            Some(hints) => {
+                for (key, live, entry) in hints {
+                    self.apply(key, live, entry);
+                }
+                self.total_bytes += len;
+            }
+            None => {
+                let bytes = fs::read(segment_path(&self.dir, segment))?;
+                let mut offset = 0;
+
+                while offset < bytes.len() {
+                    let (key, value, record_len) = match decode_record(&bytes[offset..]) {
+                        Some(record) => record,
+                        // Only the newest segment can have been cut short
+                        // by a crash, older ones are damaged
+                        None if is_last => break,
+                        None => return Err(StoreError::Corruption { msg: format!("Bad record at offset {} of segment {}", offset, segment) }.into()),
+                    };
+
+                    let entry = KeyDirEntry { segment, offset: offset as u64, len: record_len as u32 };
+                    self.apply(key, value.is_some(), entry);
+                    offset += record_len;
+                }
+
+                // Drop the torn tail so nothing is appended after it
+                if offset as u64 != len {
+                    file.set_len(offset as u64)?;
+                }
+                self.total_bytes += offset as u64;
+            }
+        }
+
+        self.segments.insert(segment, file);
+        Ok(())
+    }
+
+    fn apply(&mut self, key: Key, live: bool, entry: KeyDirEntry) {
+        if live {
+            self.keydir.insert(key, entry);
+        } else {
+            self.keydir.remove(&key);
+        }
+    }
+
+    fn read_value(&self, entry: &KeyDirEntry) -> Result<Vec<u8>, io::Error> {
+        let file = self.segments.get(&entry.segment)
+            .ok_or(StoreError::Corruption { msg: format!("Keydir points at missing segment {}", entry.segment) })?;
+
+        let mut bytes = vec![0; entry.len as usize];
+        file.read_exact_at(&mut bytes, entry.offset)?;
+
+        match decode_record(&bytes) {
+            Some((_, Some(value), _)) => Ok(value),
+            _ => Err(StoreError::Corruption { msg: format!("Bad record at offset {} of segment {}", entry.offset, entry.segment) }.into()),
+        }
+    }
+
+    pub fn get(&self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        match self.keydir.get(key) {
+            Some(entry) => Ok(Some(Value::new(self.read_value(entry)?))),
+            None => Ok(None),
+        }
+    }
+
+    // Decodes the stored bytes as T
+    pub fn get_as<T: Decode>(&self, key: &Key) -> Result<Option<Value<T>>, io::Error> {
+        match self.get(key)? {
+            Some(value) => Ok(Some(Value::new(T::decode_value(value.get())?))),
+            None => Ok(None),
+        }
+    }
+
+    // Existence is answered from the keydir alone
+    pub fn contains(&self, key: &Key) -> Result<bool, io::Error> {
+        Ok(self.keydir.contains_key(key))
+    }
+
+    pub fn len(&self) -> usize {
+        self.keydir.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keydir.is_empty()
+    }
+
+    pub fn insert<T: Encode>(&mut self, key: Key, value: Value<T>) -> Result<(), io::Error> {
+        if key.len() > MAX_KEY_SIZE {
+            return Err(StoreError::Serialization(format!("Key longer than {} bytes", MAX_KEY_SIZE)).into());
+        }
+
+        self.write(key, Some(&value.get().encode_value()))
+    }
+
+    pub fn delete(&mut self, key: &Key) -> Result<bool, io::Error> {
+        if !self.keydir.contains_key(key) {
+            return Ok(false);
+        }
+
+        self.write(key.clone(), None)?;
+        Ok(true)
+    }
+
+    pub fn range(&self, lower: Bound<Key>, upper: Bound<Key>) -> impl Iterator<Item = Result<KeyValue, io::Error>> + '_ {
+        let entries = match is_empty_range(&lower, &upper) {
+            true => None,
+            false => Some(self.keydir.range((lower, upper))),
+        };
+
+        entries.into_iter().flatten().map(|(key, entry)| Ok((key.clone(), Value::new(self.read_value(entry)?))))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Result<KeyValue, io::Error>> + '_ {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    pub fn sync(&mut self) -> Result<(), io::Error> {
+        self.segments[&self.active].sync_data()
+    }
+
+    // Bytes on disk taken up by records that have been overwritten or
+    // deleted since, which a merge would give back
+    pub fn stale_bytes(&self) -> u64 {
+        let live: u64 = self.keydir.values().map(|entry| entry.len as u64).sum();
+        self.total_bytes - live
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    fn write(&mut self, key: Key, value: Option<&[u8]>) -> Result<(), io::Error> {
+        let sealed = self.append(&key, value)?;
+        if self.options.sync_writes {
+            self.sync()?;
+        }
+
+        // Sealing a segment is when a merge is considered, once more than
+        // half of what is on disk is stale
+        if sealed && self.stale_bytes() * 2 > self.total_bytes {
+            self.merge()?;
+        }
+
+        Ok(())
+    }
+
+    // Appends a record to the active segment and points the keydir at it,
+    // returning whether the previous active segment had to be sealed
+    fn append(&mut self, key: &Key, value: Option<&[u8]>) -> Result<bool, io::Error> {
+        let record = encode_record(key, value);
+
+        let sealed = self.active_len > 0 && self.active_len + record.len() as u64 > self.options.max_segment_size;
+        if sealed {
+            self.seal()?;
+        }
+
+        let entry = KeyDirEntry { segment: self.active, offset: self.active_len, len: record.len() as u32 };
+        self.segments[&self.active].write_all_at(&record, self.active_len)?;
+        self.active_len += record.len() as u64;
+        self.total_bytes += record.len() as u64;
+
+        encode_hint(&mut self.active_hints, key, value.is_some(), &entry);
+        if value.is_some() {
+            self.keydir.insert(key.clone(), entry);
+        } else {
+            self.keydir.remove(key);
+        }
+
+        Ok(sealed)
+    }
+
+    // Makes the active segment durable and writes its hint file, unless
+    // nothing was written to it since
+    fn write_hints(&mut self) -> Result<(), io::Error> {
+        if self.active_hints.is_empty() {
+            return Ok(());
+        }
+        self.sync()?;
+
+        let mut hints = std::mem::take(&mut self.active_hints);
+        let crc = crc32fast::hash(&hints);
+        hints.write_u32::<LittleEndian>(crc)?;
+
+        let path = hint_path(&self.dir, self.active);
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+        fs::write(&temp, &hints)?;
+        File::open(&temp)?.sync_all()?;
+        fs::rename(&temp, &path)
+    }
+
+    // Writes the active segment's hint file and starts a new segment
+    fn seal(&mut self) -> Result<(), io::Error> {
+        self.write_hints()?;
+
+        self.active += 1;
+        self.active_len = 0;
+        let file = self.open_segment(self.active)?;
+        self.segments.insert(self.active, file);
+
+        sync_dir(&self.dir)
+    }
+
+    // Copies every live record into new segments and deletes all older
+    // ones. Were it interrupted, the old segments are loaded first on
+    // startup and the copies after them, which changes nothing.
+    pub fn merge(&mut self) -> Result<(), io::Error> {
+        if self.active_len > 0 {
+            self.seal()?;
+        }
+        let old: Vec<u64> = self.segments.keys().copied().filter(|&segment| segment < self.active).collect();
+
+        let live: Vec<(Key, KeyDirEntry)> = self.keydir.iter().map(|(key, entry)| (key.clone(), *entry)).collect();
+        for (key, entry) in live {
+            let value = self.read_value(&entry)?;
+            self.append(&key, Some(&value))?;
+        }
+        self.sync()?;
+        sync_dir(&self.dir)?;
+
+        // Oldest first, so a deletion is never dropped before the value
+        // it deleted
+        for segment in old {
+            self.segments.remove(&segment);
+            fs::remove_file(segment_path(&self.dir, segment))?;
+            let _ = fs::remove_file(hint_path(&self.dir, segment));
+        }
+        self.total_bytes = self.segments.values().map(|file| file.metadata().map(|meta| meta.len())).sum::<Result<u64, io::Error>>()?;
+
+        Ok(())
+    }
+}
+
+impl Drop for BitcaskStore {
+    fn drop(&mut self) {
+        if self.write_hints().is_ok() {
+            let _ = sync_dir(&self.dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    fn small_options() -> BitcaskOptions {
+        BitcaskOptions { max_segment_size: 8 << 10, sync_writes: false }
+    }
+
+    fn value_for(i: u64, round: u64) -> Vec<u8> {
+        vec![(i + round) as u8; 20 + (i * 13 + round) as usize % 200]
+    }
+
+    fn contents(store: &BitcaskStore) -> Vec<(Key, Vec<u8>)> {
+        store.iter().map(|entry| {
+            let (key, value) = entry.unwrap();
+            (key, value.get().clone())
+        }).collect()
+    }
+
+    #[test]
+    fn test_reads_see_latest_writes() {
+        let path = temp_dir("test_bitcask_reads");
+        let mut store = BitcaskStore::open(&path, small_options()).unwrap();
+
+        for round in 0..3 {
+            for i in 0..100u64 {
+                store.insert(Key::from(i), Value::new(value_for(i, round))).unwrap();
+            }
+        }
+        for i in (0..100u64).step_by(4) {
+            assert!(store.delete(&Key::from(i)).unwrap());
+        }
+        assert!(!store.delete(&Key::from(0u64)).unwrap());
+
+        assert!(store.segment_count() > 1);
+        assert_eq!(store.len(), 75);
+        assert_eq!(store.get(&Key::from(1u64)).unwrap().unwrap().get(), &value_for(1, 2));
+        assert!(store.get(&Key::from(4u64)).unwrap().is_none());
+
+        let scanned: Vec<u64> = store.range(Bound::Included(Key::from(10u64)), Bound::Included(Key::from(14u64)))
+            .map(|entry| entry.unwrap().0.to_u64().unwrap())
+            .collect();
+        assert_eq!(scanned, vec![10, 11, 13, 14]);
+    }
+
+    #[test]
+    fn test_reopen_with_and_without_hints() {
+        let path = temp_dir("test_bitcask_reopen");
+
+        let expected = {
+            let mut store = BitcaskStore::open(&path, small_options()).unwrap();
+            for round in 0..2 {
+                for i in 0..200u64 {
+                    store.insert(Key::from(i), Value::new(value_for(i, round))).unwrap();
+                }
+            }
+            for i in (0..200u64).step_by(3) {
+                store.delete(&Key::from(i)).unwrap();
+            }
+            contents(&store)
+        };
+
+        let store = BitcaskStore::open(&path, small_options()).unwrap();
+        assert_eq!(contents(&store), expected);
+        drop(store);
+
+        // Damaged or missing hints fall back to reading the segments
+        let hints: Vec<PathBuf> = fs::read_dir(&path).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "hint"))
+            .collect();
+        assert!(hints.len() > 1);
+        fs::write(&hints[0], b"garbage").unwrap();
+        fs::remove_file(&hints[1]).unwrap();
+
+        let store = BitcaskStore::open(&path, small_options()).unwrap();
+        assert_eq!(contents(&store), expected);
+    }
+
+    #[test]
+    fn test_close_writes_hints_of_the_active_segment() {
+        let path = temp_dir("test_bitcask_close_hints");
+
+        let mut store = BitcaskStore::open(&path, BitcaskOptions::default()).unwrap();
+        for i in 0..50u64 {
+            store.insert(Key::from(i), Value::new(value_for(i, 0))).unwrap();
+        }
+        let expected = contents(&store);
+        store.close().unwrap();
+        assert!(hint_path(&path, 0).exists());
+
+        // Reopening works from the hints alone, so damage to the segment
+        // past its first record goes unnoticed
+        let segment = segment_path(&path, 0);
+        let mut bytes = fs::read(&segment).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&segment, &bytes).unwrap();
+
+        let store = BitcaskStore::open(&path, BitcaskOptions::default()).unwrap();
+        assert_eq!(store.len(), 50);
+        assert_eq!(store.get(&Key::from(0u64)).unwrap().unwrap().get(), &expected[0].1);
+        drop(store);
+
+        // Dropping the store writes them as well
+        let path = temp_dir("test_bitcask_drop_hints");
+        let mut store = BitcaskStore::open(&path, BitcaskOptions::default()).unwrap();
+        store.insert(Key::from("key"), Value::new("value")).unwrap();
+        drop(store);
+        assert!(hint_path(&path, 0).exists());
+    }
+
+    #[test]
+    fn test_torn_tail_is_dropped() {
+        let path = temp_dir("test_bitcask_torn");
+
+        {
+            let mut store = BitcaskStore::open(&path, small_options()).unwrap();
+            store.insert(Key::from("kept"), Value::new("a")).unwrap();
+            store.insert(Key::from("torn"), Value::new("b")).unwrap();
+        }
+
+        let segment = segment_path(&path, 0);
+        let len = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new().write(true).open(&segment).unwrap().set_len(len - 3).unwrap();
+
+        let mut store = BitcaskStore::open(&path, small_options()).unwrap();
+        assert_eq!(store.get(&Key::from("kept")).unwrap().unwrap().get(), b"a");
+        assert!(!store.contains(&Key::from("torn")).unwrap());
+
+        store.insert(Key::from("after"), Value::new("c")).unwrap();
+        drop(store);
+
+        let store = BitcaskStore::open(&path, small_options()).unwrap();
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_merge_drops_stale_records() {
+        let path = temp_dir("test_bitcask_merge");
+        let mut store = BitcaskStore::open(&path, BitcaskOptions { max_segment_size: 1 << 20, sync_writes: false }).unwrap();
+
+        for round in 0..5 {
+            for i in 0..300u64 {
+                store.insert(Key::from(i), Value::new(value_for(i, round))).unwrap();
+            }
+        }
+        for i in 0..100u64 {
+            store.delete(&Key::from(i)).unwrap();
+        }
+        let expected = contents(&store);
+        assert!(store.stale_bytes() > 0);
+
+        store.merge().unwrap();
+        assert_eq!(store.stale_bytes(), 0);
+        assert_eq!(contents(&store), expected);
+
+        // Deleted keys stay deleted after the old segments are gone
+        drop(store);
+        let store = BitcaskStore::open(&path, BitcaskOptions::default()).unwrap();
+        assert_eq!(contents(&store), expected);
+        assert_eq!(store.stale_bytes(), 0);
+    }
+
+    #[test]
+    fn test_sealing_merges_once_mostly_stale() {
+        let path = temp_dir("test_bitcask_auto_merge");
+        let mut store = BitcaskStore::open(&path, small_options()).unwrap();
+
+        // The same few keys over and over leave almost everything stale
+        for round in 0..200 {
+            for i in 0..5u64 {
+                store.insert(Key::from(i), Value::new(value_for(i, round))).unwrap();
+            }
+        }
+
+        assert!(store.segment_count() <= 3);
+        assert!(store.stale_bytes() * 2 <= store.total_bytes + store.options.max_segment_size);
+        assert_eq!(store.get(&Key::from(3u64)).unwrap().unwrap().get(), &value_for(3, 199));
+    }
+}
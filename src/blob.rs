@@ -0,0 +1,408 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::keys::Key;
+use crate::page::PageFormat;
+use crate::storage_manager::{PageId, Store};
+
+// Full pages written before the writer commits them, so a large blob
+// never has to sit in memory waiting for commit. The store keeps the
+// chain's head until finish, so one cut short by a crash is freed on open.
+const BLOB_COMMIT_PAGES: usize = 64;
+
+// Streams a value into a chain of overflow pages. The key only sees the
+// new value once finish is called, dropping the writer instead frees the
+// pages written so far.
+pub struct BlobWriter<'a> {
+    store: &'a mut Store,
+    key: Option<Key>,
+    head: Option<PageId>,
+    // Last page of the chain, written once its successor is known
+    tail: Option<(PageId, Vec<u8>)>,
+    len: u64,
+    uncommitted: usize,
+}
+
+impl<'a> BlobWriter<'a> {
+    pub(crate) fn new(store: &'a mut Store, key: Key) -> Self {
+        BlobWriter {
+            store,
+            key: Some(key),
+            head: None,
+            tail: None,
+            len: 0,
+            uncommitted: 0,
+        }
+    }
+
+    // Bytes written so far
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn next_page(&mut self) -> Result<(), io::Error> {
+        let id = self.store.allocate_page()?;
+
+        match self.tail.take() {
+            Some((tail_id, chunk)) => {
+                self.store.put_overflow_page(&tail_id, &chunk, Some(&id))?;
+
+                self.uncommitted += 1;
+                if self.uncommitted >= BLOB_COMMIT_PAGES {
+                    // The committed chain has to end in an overflow page
+                    // for recovery to walk it
+                    self.store.put_overflow_page(&id, &[], None)?;
+                    self.store.commit()?;
+                    self.uncommitted = 0;
+                }
+            }
+            None => {
+                self.store.set_blob_head(Some(id.clone()));
+                self.head = Some(id.clone());
+            }
+        }
+
+        self.tail = Some((id, Vec::with_capacity(PageFormat::max_record_size())));
+        Ok(())
+    }
+
+    fn write_tail(&mut self) -> Result<(), io::Error> {
+        match self.tail.take() {
+            Some((id, chunk)) => self.store.put_overflow_page(&id, &chunk, None),
+            None => Ok(()),
+        }
+    }
+
+    // Points the key at the written bytes and commits
+    pub fn finish(mut self) -> Result<(), io::Error> {
+        self.write_tail()?;
+
+        let key = self.key.take().unwrap();
+        self.store.finish_blob(key, self.len, self.head.take())
+    }
+}
+
+impl Write for BlobWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+
+        while written < buf.len() {
+            let full = match &self.tail {
+                Some((_, chunk)) => chunk.len() == PageFormat::max_record_size(),
+                None => true,
+            };
+            if full {
+                self.next_page()?;
+            }
+
+            let (_, chunk) = self.tail.as_mut().unwrap();
+            let n = (PageFormat::max_record_size() - chunk.len()).min(buf.len() - written);
+            chunk.extend_from_slice(&buf[written..written + n]);
+            written += n;
+        }
+
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    // Nothing is visible under the key until finish
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BlobWriter<'_> {
+    fn drop(&mut self) {
+        if self.key.is_none() {
+            return;
+        }
+
+        // Abandoned, give the partial chain back
+        if self.write_tail().is_ok() {
+            if let Some(head) = self.head.take() {
+                if self.store.release_overflow(head).is_ok() {
+                    self.store.set_blob_head(None);
+                }
+            }
+        }
+        let _ = self.store.commit();
+    }
+}
+
+pub(crate) enum BlobSource {
+    Inline(Vec<u8>),
+    Chain { len: u64, head: PageId },
+}
+
+// Reads a value a page at a time. Every overflow page holds the same
+// number of bytes, so seeking only has to find the right page in the chain.
+pub struct BlobReader<'a> {
+    store: &'a mut Store,
+    source: BlobSource,
+    pos: u64,
+    // Chain page ids discovered so far
+    pages: Vec<PageId>,
+    // Index, bytes and successor of the last page read
+    current: Option<(usize, Vec<u8>, Option<PageId>)>,
+}
+
+impl<'a> BlobReader<'a> {
+    pub(crate) fn new(store: &'a mut Store, source: BlobSource) -> Self {
+        let pages = match &source {
+            BlobSource::Chain { head, .. } => vec![head.clone()],
+            BlobSource::Inline(_) => vec![],
+        };
+
+        BlobReader {
+            store,
+            source,
+            pos: 0,
+            pages,
+            current: None,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        match &self.source {
+            BlobSource::Inline(value) => value.len() as u64,
+            BlobSource::Chain { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Loads the chunk of the index-th page, walking the chain to reach it
+    fn load_page(&mut self, index: usize) -> Result<(), io::Error> {
+        if matches!(&self.current, Some((current, _, _)) if *current == index) {
+            return Ok(());
+        }
+
+        while self.pages.len() <= index {
+            let last = self.pages.len() - 1;
+            let next = match &self.current {
+                Some((current, _, next)) if *current == last => next.clone(),
+                _ => self.store.read_overflow_chunk(&self.pages[last])?.1,
+            };
+
+            match next {
+                Some(next) => self.pages.push(next),
+                None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Overflow chain ended early")),
+            }
+        }
+
+        let (chunk, next) = self.store.read_overflow_chunk(&self.pages[index])?;
+        self.current = Some((index, chunk, next));
+
+        Ok(())
+    }
+}
+
+impl Read for BlobReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len() || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let chunk = match &self.source {
+            BlobSource::Inline(value) => &value[self.pos as usize..],
+            BlobSource::Chain { .. } => {
+                let chunk_size = PageFormat::max_record_size() as u64;
+                let index = (self.pos / chunk_size) as usize;
+                self.load_page(index)?;
+
+                let (_, chunk, _) = self.current.as_ref().unwrap();
+                chunk.get((self.pos % chunk_size) as usize..)
+                    .ok_or(io::Error::new(io::ErrorKind::UnexpectedEof, "Overflow page shorter than expected"))?
+            }
+        };
+
+        let n = chunk.len().min(buf.len());
+        buf[..n].copy_from_slice(&chunk[..n]);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl Seek for BlobReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        match target {
+            Some(target) => {
+                self.pos = target;
+                Ok(target)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of blob")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::values::Value;
+    use crate::wal::wal_path;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn temp_store(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(wal_path(&path));
+        path
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 253) as u8).collect()
+    }
+
+    #[test]
+    fn test_stream_in_and_out() {
+        let path = temp_store("test_blob_stream");
+        let mut store = Store::new(&path).unwrap();
+
+        let key = Key::new();
+        let data = pattern(1_000_000);
+
+        let mut writer = store.blob_writer(key.clone()).unwrap();
+        for piece in data.chunks(7000) {
+            writer.write_all(piece).unwrap();
+        }
+        assert_eq!(writer.len(), data.len() as u64);
+        writer.finish().unwrap();
+
+        let mut reader = store.blob_reader(&key).unwrap().unwrap();
+        assert_eq!(reader.len(), data.len() as u64);
+
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+
+        // Blobs are ordinary values too
+        assert_eq!(store.get(&key).unwrap().unwrap().get(), &data);
+    }
+
+    #[test]
+    fn test_seek() {
+        let path = temp_store("test_blob_seek");
+        let mut store = Store::new(&path).unwrap();
+
+        let key = Key::new();
+        let data = pattern(100_000);
+        store.insert(key.clone(), Value::new(data.clone())).unwrap();
+
+        let mut reader = store.blob_reader(&key).unwrap().unwrap();
+        let mut buf = [0u8; 100];
+
+        reader.seek(SeekFrom::Start(50_000)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[50_000..50_100]);
+
+        reader.seek(SeekFrom::Current(-20_100)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[30_000..30_100]);
+
+        reader.seek(SeekFrom::End(-100)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[data.len() - 100..]);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        assert!(reader.seek(SeekFrom::Current(-200_000)).is_err());
+    }
+
+    #[test]
+    fn test_reader_over_inline_value() {
+        let path = temp_store("test_blob_inline");
+        let mut store = Store::new(&path).unwrap();
+
+        let key = Key::new();
+        store.insert(key.clone(), Value::new("short value")).unwrap();
+
+        let mut reader = store.blob_reader(&key).unwrap().unwrap();
+        reader.seek(SeekFrom::Start(6)).unwrap();
+
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+        assert_eq!(read, "value");
+
+        assert!(store.blob_reader(&Key::new()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_dropped_writer_leaves_old_value() {
+        let path = temp_store("test_blob_dropped");
+        let mut store = Store::new(&path).unwrap();
+
+        let key = Key::new();
+        store.insert(key.clone(), Value::new("old")).unwrap();
+        let pages = store.len();
+
+        {
+            let mut writer = store.blob_writer(key.clone()).unwrap();
+            writer.write_all(&pattern(600_000)).unwrap();
+        }
+
+        assert_eq!(store.get(&key).unwrap().unwrap().get(), b"old");
+        assert_eq!(store.len(), pages);
+    }
+
+    #[test]
+    fn test_crash_mid_blob_frees_its_pages_on_open() {
+        let path = temp_store("test_blob_crash");
+        let key = Key::new();
+
+        let pages = {
+            let mut store = Store::new(&path).unwrap();
+            store.insert(key.clone(), Value::new("old")).unwrap();
+            let pages = store.len();
+
+            // Enough pages for the writer to have committed part of the chain
+            let mut writer = store.blob_writer(key.clone()).unwrap();
+            writer.write_all(&pattern(BLOB_COMMIT_PAGES * 3 * PageFormat::max_record_size())).unwrap();
+            std::mem::forget(writer);
+            std::mem::forget(store);
+
+            pages
+        };
+
+        let store = Store::new(&path).unwrap();
+        assert_eq!(store.len(), pages);
+        assert_eq!(store.get(&key).unwrap().unwrap().get(), b"old");
+    }
+
+    #[test]
+    fn test_finished_blob_survives_reopen() {
+        let path = temp_store("test_blob_reopen");
+        let key = Key::new();
+        let data = pattern(300_000);
+
+        {
+            let mut store = Store::new(&path).unwrap();
+            let mut writer = store.blob_writer(key.clone()).unwrap();
+            writer.write_all(&data).unwrap();
+            writer.finish().unwrap();
+
+            // Overwriting with a new blob frees the old chain
+            let pages = store.len();
+            let mut writer = store.blob_writer(key.clone()).unwrap();
+            writer.write_all(&data).unwrap();
+            writer.finish().unwrap();
+            assert_eq!(store.len(), pages);
+        }
+
+        let mut store = Store::new(&path).unwrap();
+        let mut read = vec![];
+        store.blob_reader(&key).unwrap().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+    }
+}
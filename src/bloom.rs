@@ -0,0 +1,160 @@
+use std::f64::consts::LN_2;
+use std::io;
+
+use crate::storage_manager::StoreError;
+
+// Hash count + bit count
+const HEADER_SIZE: usize = 4 + 8;
+
+// Most hash functions a filter will use, whatever rate it is built for
+const MAX_HASHES: u32 = 30;
+
+// How often filters spared a lookup. Hits are lookups the filter let
+// through that found the key, false positives are those that did not.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BloomStats {
+    pub hits: u64,
+    pub skips: u64,
+    pub false_positives: u64,
+}
+
+// The two base hashes of a key. The filter's hash functions are
+// combinations of these, so keys are hashed once whatever their number.
+pub(crate) fn key_hashes(key: &[u8]) -> (u32, u32) {
+    let first = crc32fast::hash(key);
+
+    let mut hasher = crc32fast::Hasher::new_with_initial(0x9e3779b9);
+    hasher.update(key);
+    (first, hasher.finalize() | 1)
+}
+
+// Bloom filter over a fixed set of keys. It answers whether a key may be
+// in the set: never wrongly no, wrongly yes at about the rate it was built
+// for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    bit_count: u64,
+    hashes: u32,
+}
+
+// Whether a filter can be built for a false positive rate. Anything
+// outside 0 to 1 would size it at infinite, negative or NaN bits per key.
+pub(crate) fn is_valid_rate(false_positive_rate: f64) -> bool {
+    false_positive_rate > 0.0 && false_positive_rate < 1.0
+}
+
+impl BloomFilter {
+    // Sized for the given number of keys and false positive rate, which
+    // must lie strictly between 0 and 1
+    pub fn new(keys: usize, false_positive_rate: f64) -> Result<Self, io::Error> {
+        if !is_valid_rate(false_positive_rate) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Bloom filter false positive rate {} is not between 0 and 1", false_positive_rate)));
+        }
+
+        let bits_per_key = -false_positive_rate.ln() / (LN_2 * LN_2);
+        let bit_count = ((keys.max(1) as f64 * bits_per_key).ceil() as u64).max(8);
+        let hashes = ((bits_per_key * LN_2).round() as u32).clamp(1, MAX_HASHES);
+
+        Ok(BloomFilter {
+            bits: vec![0; bit_count.div_ceil(8) as usize],
+            bit_count,
+            hashes,
+        })
+    }
+
+    // Bits a key maps to, by double hashing
+    fn positions(&self, (first, second): (u32, u32)) -> impl Iterator<Item = u64> {
+        let bit_count = self.bit_count;
+        (0..self.hashes as u64).map(move |i| (first as u64 + i * second as u64) % bit_count)
+    }
+
+    pub(crate) fn insert_hashes(&mut self, hashes: (u32, u32)) {
+        for bit in self.positions(hashes) {
+            self.bits[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        self.insert_hashes(key_hashes(key));
+    }
+
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.positions(key_hashes(key)).all(|bit| self.bits[(bit / 8) as usize] & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.bits.len());
+        bytes.extend_from_slice(&self.hashes.to_le_bytes());
+        bytes.extend_from_slice(&self.bit_count.to_le_bytes());
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StoreError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(StoreError::Corruption { msg: "Bloom filter too short".to_string() });
+        }
+
+        let hashes = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+        let bit_count = u64::from_le_bytes(bytes[4..12].try_into().unwrap());
+        let bits = bytes[HEADER_SIZE..].to_vec();
+        if bit_count == 0 || bits.len() as u64 != bit_count.div_ceil(8) || !(1..=MAX_HASHES).contains(&hashes) {
+            return Err(StoreError::Corruption { msg: "Bad bloom filter header".to_string() });
+        }
+
+        Ok(BloomFilter { bits, bit_count, hashes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_false_negatives() {
+        let mut filter = BloomFilter::new(1000, 0.01).unwrap();
+        for i in 0..1000u32 {
+            filter.insert(&i.to_be_bytes());
+        }
+
+        for i in 0..1000u32 {
+            assert!(filter.may_contain(&i.to_be_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_false_positive_rate_follows_config() {
+        for rate in [0.1, 0.01, 0.001] {
+            let mut filter = BloomFilter::new(5000, rate).unwrap();
+            for i in 0..5000u32 {
+                filter.insert(&i.to_be_bytes());
+            }
+
+            let false_positives = (5000..105000u32).filter(|i| filter.may_contain(&i.to_be_bytes())).count();
+            let measured = false_positives as f64 / 100000.0;
+            assert!(measured < rate * 2.0, "rate {} measured {}", rate, measured);
+        }
+    }
+
+    #[test]
+    fn test_rejects_rates_outside_zero_to_one() {
+        for rate in [0.0, -0.5, 1.0, 1.5, f64::NAN, f64::INFINITY] {
+            let err = BloomFilter::new(100, rate).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let mut filter = BloomFilter::new(10, 0.05).unwrap();
+        filter.insert(b"key");
+
+        let decoded = BloomFilter::from_bytes(&filter.to_bytes()).unwrap();
+        assert_eq!(decoded, filter);
+        assert!(decoded.may_contain(b"key"));
+
+        assert!(BloomFilter::from_bytes(&filter.to_bytes()[..HEADER_SIZE]).is_err());
+        assert!(BloomFilter::from_bytes(b"short").is_err());
+    }
+}
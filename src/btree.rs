@@ -0,0 +1,1006 @@
+use std::io;
+use std::ops::Bound;
+
+use crate::page::{PageFormat, PageType};
+use crate::storage_manager::{PageId, Pager, StoreError};
+
+// Largest key plus value a leaf entry may hold, keeps at least three
+// entries per node so splits always leave both halves non-empty
+pub const MAX_ENTRY_SIZE: usize = 1024;
+
+const KEY_LEN_SIZE: usize = 2;
+const LINK_SIZE: usize = 8;
+
+// A key and its value
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+// Nodes below a quarter full are merged with or borrow from a sibling
+fn min_fill() -> usize {
+    PageFormat::capacity() / 4
+}
+
+// Slot 0 of every node page holds its links, entries follow in key order.
+// Leaf links are the previous and next leaf, internal nodes store their
+// leftmost child there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Leaf {
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    prev: Option<PageId>,
+    next: Option<PageId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Internal {
+    keys: Vec<Vec<u8>>,
+    children: Vec<PageId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Leaf(Leaf),
+    Internal(Internal),
+}
+
+fn encode_link(link: &Option<PageId>) -> u64 {
+    link.as_ref().map(|id| id.as_u64() + 1).unwrap_or(0)
+}
+
+fn decode_link(bytes: &[u8]) -> Option<PageId> {
+    match u64::from_le_bytes(bytes.try_into().unwrap()) {
+        0 => None,
+        link => Some(PageId::from_u64(link - 1)),
+    }
+}
+
+fn encode_entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(KEY_LEN_SIZE + key.len() + value.len());
+
+    record.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    record.extend_from_slice(key);
+    record.extend_from_slice(value);
+
+    record
+}
+
+fn decode_entry(record: &[u8]) -> Result<(Vec<u8>, Vec<u8>), StoreError> {
+    if record.len() < KEY_LEN_SIZE {
+        return Err(StoreError::Corruption { msg: "B+tree entry too short".to_string() });
+    }
+
+    let key_len = u16::from_le_bytes([record[0], record[1]]) as usize;
+    if record.len() < KEY_LEN_SIZE + key_len {
+        return Err(StoreError::Corruption { msg: "B+tree entry key overruns record".to_string() });
+    }
+
+    let key = record[KEY_LEN_SIZE..KEY_LEN_SIZE + key_len].to_vec();
+    let value = record[KEY_LEN_SIZE + key_len..].to_vec();
+
+    Ok((key, value))
+}
+
+fn entry_size(key: &[u8], value_len: usize) -> usize {
+    KEY_LEN_SIZE + key.len() + value_len + PageFormat::slot_overhead()
+}
+
+// Index that splits items into two halves of roughly equal byte size
+fn split_point(sizes: &[usize]) -> usize {
+    let total: usize = sizes.iter().sum();
+
+    let mut running = 0;
+    for (i, size) in sizes.iter().enumerate() {
+        running += size;
+        if running * 2 >= total {
+            return (i + 1).clamp(1, sizes.len() - 1);
+        }
+    }
+
+    sizes.len() / 2
+}
+
+impl Leaf {
+    fn size(&self) -> usize {
+        let links = 2 * LINK_SIZE + PageFormat::slot_overhead();
+
+        links + self.entries.iter().map(|(key, value)| entry_size(key, value.len())).sum::<usize>()
+    }
+
+    fn split_point(&self) -> usize {
+        let sizes: Vec<usize> = self.entries.iter().map(|(key, value)| entry_size(key, value.len())).collect();
+        split_point(&sizes)
+    }
+}
+
+impl Internal {
+    fn size(&self) -> usize {
+        let links = LINK_SIZE + PageFormat::slot_overhead();
+
+        links + self.keys.iter().map(|key| entry_size(key, LINK_SIZE)).sum::<usize>()
+    }
+
+    fn child_index(&self, key: &[u8]) -> usize {
+        self.keys.partition_point(|separator| separator.as_slice() <= key)
+    }
+
+    // Index of the separator moved up when splitting this node's keys
+    fn split_point(&self) -> usize {
+        let sizes: Vec<usize> = self.keys.iter().map(|key| entry_size(key, LINK_SIZE)).collect();
+        split_point(&sizes).clamp(1, self.keys.len() - 2)
+    }
+}
+
+impl Node {
+    fn size(&self) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.size(),
+            Node::Internal(internal) => internal.size(),
+        }
+    }
+
+    fn fits(&self) -> bool {
+        self.size() <= PageFormat::capacity()
+    }
+
+    fn is_underfull(&self) -> bool {
+        self.size() < min_fill()
+    }
+
+    fn to_page(&self) -> PageFormat {
+        let mut page = PageFormat::new();
+
+        match self {
+            Node::Leaf(leaf) => {
+                page.set_page_type(PageType::BTreeLeaf);
+
+                let mut links = Vec::with_capacity(2 * LINK_SIZE);
+                links.extend_from_slice(&encode_link(&leaf.prev).to_le_bytes());
+                links.extend_from_slice(&encode_link(&leaf.next).to_le_bytes());
+                page.insert_record(&links);
+
+                for (key, value) in &leaf.entries {
+                    page.insert_record(&encode_entry(key, value));
+                }
+            }
+            Node::Internal(internal) => {
+                page.set_page_type(PageType::BTreeInternal);
+                page.insert_record(&internal.children[0].as_u64().to_le_bytes());
+
+                for (key, child) in internal.keys.iter().zip(&internal.children[1..]) {
+                    page.insert_record(&encode_entry(key, &child.as_u64().to_le_bytes()));
+                }
+            }
+        }
+
+        page
+    }
+
+    fn from_page(page: &PageFormat) -> Result<Self, StoreError> {
+        let records = page.records();
+        let (links, entries) = match records.split_first() {
+            Some((links, entries)) => (links.1, entries),
+            None => return Err(StoreError::Corruption { msg: "B+tree node without links".to_string() }),
+        };
+
+        match page.get_page_type() {
+            PageType::BTreeLeaf if links.len() == 2 * LINK_SIZE => {
+                let entries = entries
+                    .iter()
+                    .map(|(_, record)| decode_entry(record))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(Node::Leaf(Leaf {
+                    entries,
+                    prev: decode_link(&links[..LINK_SIZE]),
+                    next: decode_link(&links[LINK_SIZE..]),
+                }))
+            }
+            PageType::BTreeInternal if links.len() == LINK_SIZE => {
+                let mut keys = Vec::with_capacity(entries.len());
+                let mut children = Vec::with_capacity(entries.len() + 1);
+                children.push(PageId::from_u64(u64::from_le_bytes(links.try_into().unwrap())));
+
+                for (_, record) in entries {
+                    let (key, child) = decode_entry(record)?;
+                    if child.len() != LINK_SIZE {
+                        return Err(StoreError::Corruption { msg: "Bad B+tree child pointer".to_string() });
+                    }
+
+                    keys.push(key);
+                    children.push(PageId::from_u64(u64::from_le_bytes(child.try_into().unwrap())));
+                }
+
+                Ok(Node::Internal(Internal { keys, children }))
+            }
+            _ => Err(StoreError::Corruption { msg: "Page is not a B+tree node".to_string() }),
+        }
+    }
+}
+
+fn read_node<P: Pager>(pager: &P, id: &PageId) -> Result<Node, io::Error> {
+    let page = pager.read(id)?;
+    Ok(Node::from_page(&page)?)
+}
+
+fn write_node<P: Pager>(pager: &mut P, id: &PageId, node: &Node) -> Result<(), io::Error> {
+    pager.write(id, &node.to_page())
+}
+
+fn set_prev_link<P: Pager>(pager: &mut P, id: &PageId, prev: Option<PageId>) -> Result<(), io::Error> {
+    match read_node(pager, id)? {
+        Node::Leaf(mut leaf) => {
+            leaf.prev = prev;
+            write_node(pager, id, &Node::Leaf(leaf))
+        }
+        Node::Internal(_) => Err(StoreError::Corruption { msg: "Leaf link points at internal node".to_string() }.into()),
+    }
+}
+
+// Disk-resident B+tree mapping byte string keys to byte string values.
+// Only the root page id is held in memory, every node is a PageFormat
+// page read and written through a Pager.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BTree {
+    root: Option<PageId>,
+}
+
+impl BTree {
+    pub fn new() -> Self {
+        BTree { root: None }
+    }
+
+    pub fn open(root: Option<PageId>) -> Self {
+        BTree { root }
+    }
+
+    pub fn root(&self) -> Option<&PageId> {
+        self.root.as_ref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn get<P: Pager>(&self, pager: &P, key: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
+        let leaf = match self.find_leaf(pager, key)? {
+            Some((_, leaf)) => leaf,
+            None => return Ok(None),
+        };
+
+        Ok(leaf.entries
+            .binary_search_by(|(entry_key, _)| entry_key.as_slice().cmp(key))
+            .ok()
+            .map(|i| leaf.entries[i].1.clone()))
+    }
+
+    // Walks from the root to the leaf that would hold key
+    fn find_leaf<P: Pager>(&self, pager: &P, key: &[u8]) -> Result<Option<(PageId, Leaf)>, io::Error> {
+        let mut id = match &self.root {
+            Some(root) => root.clone(),
+            None => return Ok(None),
+        };
+
+        loop {
+            match read_node(pager, &id)? {
+                Node::Leaf(leaf) => return Ok(Some((id, leaf))),
+                Node::Internal(internal) => id = internal.children[internal.child_index(key)].clone(),
+            }
+        }
+    }
+
+    // Inserts or replaces a value, returning the previous one
+    pub fn insert<P: Pager>(&mut self, pager: &mut P, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
+        if KEY_LEN_SIZE + key.len() + value.len() > MAX_ENTRY_SIZE {
+            return Err(StoreError::Serialization("B+tree entry too large".to_string()).into());
+        }
+
+        let root = match &self.root {
+            Some(root) => root.clone(),
+            None => {
+                let id = pager.allocate()?;
+                let leaf = Leaf {
+                    entries: vec![(key.to_vec(), value.to_vec())],
+                    prev: None,
+                    next: None,
+                };
+                write_node(pager, &id, &Node::Leaf(leaf))?;
+
+                self.root = Some(id);
+                return Ok(None);
+            }
+        };
+
+        let (old, split) = self.insert_into(pager, &root, key, value)?;
+
+        // A split root grows the tree by one level
+        if let Some((separator, right)) = split {
+            let id = pager.allocate()?;
+            let internal = Internal {
+                keys: vec![separator],
+                children: vec![root, right],
+            };
+            write_node(pager, &id, &Node::Internal(internal))?;
+
+            self.root = Some(id);
+        }
+
+        Ok(old)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn insert_into<P: Pager>(&self, pager: &mut P, id: &PageId, key: &[u8], value: &[u8]) -> Result<(Option<Vec<u8>>, Option<(Vec<u8>, PageId)>), io::Error> {
+        match read_node(pager, id)? {
+            Node::Leaf(mut leaf) => {
+                let old = match leaf.entries.binary_search_by(|(entry_key, _)| entry_key.as_slice().cmp(key)) {
+                    Ok(i) => Some(std::mem::replace(&mut leaf.entries[i].1, value.to_vec())),
+                    Err(i) => {
+                        leaf.entries.insert(i, (key.to_vec(), value.to_vec()));
+                        None
+                    }
+                };
+
+                let node = Node::Leaf(leaf);
+                if node.fits() {
+                    write_node(pager, id, &node)?;
+                    return Ok((old, None));
+                }
+
+                let mut leaf = match node {
+                    Node::Leaf(leaf) => leaf,
+                    Node::Internal(_) => unreachable!(),
+                };
+
+                // Move the upper half into a new right sibling
+                let right_id = pager.allocate()?;
+                let right = Leaf {
+                    entries: leaf.entries.split_off(leaf.split_point()),
+                    prev: Some(id.clone()),
+                    next: leaf.next.take(),
+                };
+
+                if let Some(next) = &right.next {
+                    set_prev_link(pager, next, Some(right_id.clone()))?;
+                }
+                leaf.next = Some(right_id.clone());
+
+                let separator = right.entries[0].0.clone();
+                write_node(pager, id, &Node::Leaf(leaf))?;
+                write_node(pager, &right_id, &Node::Leaf(right))?;
+
+                Ok((old, Some((separator, right_id))))
+            }
+            Node::Internal(mut internal) => {
+                let index = internal.child_index(key);
+                let child = internal.children[index].clone();
+
+                let (old, split) = self.insert_into(pager, &child, key, value)?;
+                let (separator, right) = match split {
+                    Some(split) => split,
+                    None => return Ok((old, None)),
+                };
+
+                internal.keys.insert(index, separator);
+                internal.children.insert(index + 1, right);
+
+                if Node::Internal(internal.clone()).fits() {
+                    write_node(pager, id, &Node::Internal(internal))?;
+                    return Ok((old, None));
+                }
+
+                // The middle key moves up, keys either side of it stay put
+                let mid = internal.split_point();
+                let right_keys = internal.keys.split_off(mid + 1);
+                let separator = internal.keys.pop().unwrap();
+                let right_children = internal.children.split_off(mid + 1);
+
+                let right_id = pager.allocate()?;
+                let right = Internal {
+                    keys: right_keys,
+                    children: right_children,
+                };
+
+                write_node(pager, id, &Node::Internal(internal))?;
+                write_node(pager, &right_id, &Node::Internal(right))?;
+
+                Ok((old, Some((separator, right_id))))
+            }
+        }
+    }
+
+    // Removes a key, returning its value
+    pub fn remove<P: Pager>(&mut self, pager: &mut P, key: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
+        let root = match &self.root {
+            Some(root) => root.clone(),
+            None => return Ok(None),
+        };
+
+        let (old, _) = self.remove_from(pager, &root, key)?;
+        if old.is_none() {
+            return Ok(None);
+        }
+
+        // Shrink the tree when the root is left with a single child
+        match read_node(pager, &root)? {
+            Node::Internal(internal) if internal.keys.is_empty() => {
+                self.root = Some(internal.children[0].clone());
+                pager.free(root)?;
+            }
+            Node::Leaf(leaf) if leaf.entries.is_empty() => {
+                self.root = None;
+                pager.free(root)?;
+            }
+            _ => {}
+        }
+
+        Ok(old)
+    }
+
+    // Returns the removed value and whether the node is now underfull
+    fn remove_from<P: Pager>(&self, pager: &mut P, id: &PageId, key: &[u8]) -> Result<(Option<Vec<u8>>, bool), io::Error> {
+        match read_node(pager, id)? {
+            Node::Leaf(mut leaf) => {
+                let i = match leaf.entries.binary_search_by(|(entry_key, _)| entry_key.as_slice().cmp(key)) {
+                    Ok(i) => i,
+                    Err(_) => return Ok((None, false)),
+                };
+
+                let (_, old) = leaf.entries.remove(i);
+                let node = Node::Leaf(leaf);
+                write_node(pager, id, &node)?;
+
+                Ok((Some(old), node.is_underfull()))
+            }
+            Node::Internal(mut internal) => {
+                let index = internal.child_index(key);
+                let child = internal.children[index].clone();
+
+                let (old, underfull) = self.remove_from(pager, &child, key)?;
+                if !underfull {
+                    return Ok((old, false));
+                }
+
+                self.rebalance(pager, &mut internal, index)?;
+
+                let node = Node::Internal(internal);
+                write_node(pager, id, &node)?;
+
+                Ok((old, node.is_underfull()))
+            }
+        }
+    }
+
+    // Fixes an underfull child by merging it with a sibling, or by
+    // redistributing entries when the two don't fit in one page
+    fn rebalance<P: Pager>(&self, pager: &mut P, parent: &mut Internal, index: usize) -> Result<(), io::Error> {
+        if parent.children.len() < 2 {
+            return Ok(());
+        }
+
+        let (left_index, right_index) = if index > 0 { (index - 1, index) } else { (index, index + 1) };
+        let left_id = parent.children[left_index].clone();
+        let right_id = parent.children[right_index].clone();
+
+        match (read_node(pager, &left_id)?, read_node(pager, &right_id)?) {
+            (Node::Leaf(left), Node::Leaf(right)) => {
+                let mut entries = left.entries;
+                entries.extend(right.entries);
+
+                let mut merged = Leaf {
+                    entries,
+                    prev: left.prev,
+                    next: right.next,
+                };
+
+                if Node::Leaf(merged.clone()).fits() {
+                    if let Some(next) = &merged.next {
+                        set_prev_link(pager, next, Some(left_id.clone()))?;
+                    }
+
+                    write_node(pager, &left_id, &Node::Leaf(merged))?;
+                    pager.free(right_id)?;
+
+                    parent.keys.remove(left_index);
+                    parent.children.remove(right_index);
+                    return Ok(());
+                }
+
+                // Too big for one page, so split the entries evenly instead
+                let right = Leaf {
+                    entries: merged.entries.split_off(merged.split_point()),
+                    prev: Some(left_id.clone()),
+                    next: merged.next.replace(right_id.clone()),
+                };
+
+                parent.keys[left_index] = right.entries[0].0.clone();
+
+                write_node(pager, &left_id, &Node::Leaf(merged))?;
+                write_node(pager, &right_id, &Node::Leaf(right))
+            }
+            (Node::Internal(left), Node::Internal(right)) => {
+                let mut keys = left.keys;
+                keys.push(parent.keys[left_index].clone());
+                keys.extend(right.keys);
+
+                let mut children = left.children;
+                children.extend(right.children);
+
+                let mut merged = Internal { keys, children };
+
+                if Node::Internal(merged.clone()).fits() {
+                    write_node(pager, &left_id, &Node::Internal(merged))?;
+                    pager.free(right_id)?;
+
+                    parent.keys.remove(left_index);
+                    parent.children.remove(right_index);
+                    return Ok(());
+                }
+
+                let mid = merged.split_point();
+                let right_keys = merged.keys.split_off(mid + 1);
+                let separator = merged.keys.pop().unwrap();
+                let right_children = merged.children.split_off(mid + 1);
+
+                parent.keys[left_index] = separator;
+
+                let right = Internal {
+                    keys: right_keys,
+                    children: right_children,
+                };
+
+                write_node(pager, &left_id, &Node::Internal(merged))?;
+                write_node(pager, &right_id, &Node::Internal(right))
+            }
+            _ => Err(StoreError::Corruption { msg: "B+tree siblings at different levels".to_string() }.into()),
+        }
+    }
+
+    // Number of levels from the root down to the leaves
+    pub fn height<P: Pager>(&self, pager: &P) -> Result<usize, io::Error> {
+        let mut id = match &self.root {
+            Some(root) => root.clone(),
+            None => return Ok(0),
+        };
+
+        let mut height = 1;
+        while let Node::Internal(internal) = read_node(pager, &id)? {
+            id = internal.children[0].clone();
+            height += 1;
+        }
+
+        Ok(height)
+    }
+
+    // Cursor over the entries between two bounds, read a leaf at a time
+    pub fn range(&self, lower: Bound<Vec<u8>>, upper: Bound<Vec<u8>>) -> Cursor {
+        Cursor {
+            root: self.root.clone(),
+            lower,
+            upper,
+            front: None,
+            back: None,
+            front_key: None,
+            back_key: None,
+            done: self.root.is_none(),
+        }
+    }
+
+    // Descends to the leaf holding key, or to the first or last leaf
+    fn edge_leaf<P: Pager>(&self, pager: &P, key: Option<&[u8]>, last: bool) -> Result<Option<(PageId, Leaf)>, io::Error> {
+        if let Some(key) = key {
+            return self.find_leaf(pager, key);
+        }
+
+        let mut id = match &self.root {
+            Some(root) => root.clone(),
+            None => return Ok(None),
+        };
+
+        loop {
+            match read_node(pager, &id)? {
+                Node::Leaf(leaf) => return Ok(Some((id, leaf))),
+                Node::Internal(internal) if last => id = internal.children[internal.children.len() - 1].clone(),
+                Node::Internal(internal) => id = internal.children[0].clone(),
+            }
+        }
+    }
+}
+
+// Bounds covering every key that starts with prefix
+pub fn prefix_bounds(prefix: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let mut upper = prefix.to_vec();
+
+    // The first key past the prefix is the prefix with its last non-0xff
+    // byte bumped, an all 0xff prefix runs to the end of the keyspace
+    while let Some(byte) = upper.pop() {
+        if byte < u8::MAX {
+            upper.push(byte + 1);
+            return (Bound::Included(prefix.to_vec()), Bound::Excluded(upper));
+        }
+    }
+
+    (Bound::Included(prefix.to_vec()), Bound::Unbounded)
+}
+
+fn bound_key(bound: &Bound<Vec<u8>>) -> Option<&[u8]> {
+    match bound {
+        Bound::Included(key) | Bound::Excluded(key) => Some(key),
+        Bound::Unbounded => None,
+    }
+}
+
+fn above_lower(lower: &Bound<Vec<u8>>, key: &[u8]) -> bool {
+    match lower {
+        Bound::Included(bound) => key >= bound.as_slice(),
+        Bound::Excluded(bound) => key > bound.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+fn below_upper(upper: &Bound<Vec<u8>>, key: &[u8]) -> bool {
+    match upper {
+        Bound::Included(bound) => key <= bound.as_slice(),
+        Bound::Excluded(bound) => key < bound.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+// The leaf a cursor end is positioned in. Front ends yield entries from
+// pos upwards, back ends yield the entries below pos.
+struct Position {
+    leaf: Leaf,
+    pos: usize,
+}
+
+// Walks a key range from both ends along the leaf links. It only holds
+// the two leaves it is positioned in, and stops once the ends meet.
+pub struct Cursor {
+    root: Option<PageId>,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    front: Option<Position>,
+    back: Option<Position>,
+    front_key: Option<Vec<u8>>,
+    back_key: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl Cursor {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next<P: Pager>(&mut self, pager: &P) -> Result<Option<Entry>, io::Error> {
+        if self.done {
+            return Ok(None);
+        }
+
+        if self.front.is_none() {
+            let tree = BTree::open(self.root.clone());
+            let leaf = match tree.edge_leaf(pager, bound_key(&self.lower), false)? {
+                Some((_, leaf)) => leaf,
+                None => return self.finish(),
+            };
+
+            let pos = leaf.entries.partition_point(|(key, _)| !above_lower(&self.lower, key));
+            self.front = Some(Position { leaf, pos });
+        }
+
+        let front = self.front.as_mut().unwrap();
+        while front.pos == front.leaf.entries.len() {
+            let next = match &front.leaf.next {
+                Some(next) => next.clone(),
+                None => return self.finish(),
+            };
+
+            front.leaf = match read_node(pager, &next)? {
+                Node::Leaf(leaf) => leaf,
+                Node::Internal(_) => return Err(StoreError::Corruption { msg: "Leaf link points at internal node".to_string() }.into()),
+            };
+            front.pos = 0;
+        }
+
+        let (key, value) = front.leaf.entries[front.pos].clone();
+        front.pos += 1;
+
+        let met_back = self.back_key.as_ref().map(|back| &key >= back).unwrap_or(false);
+        if met_back || !below_upper(&self.upper, &key) {
+            return self.finish();
+        }
+
+        self.front_key = Some(key.clone());
+        Ok(Some((key, value)))
+    }
+
+    pub fn next_back<P: Pager>(&mut self, pager: &P) -> Result<Option<Entry>, io::Error> {
+        if self.done {
+            return Ok(None);
+        }
+
+        if self.back.is_none() {
+            let tree = BTree::open(self.root.clone());
+            let leaf = match tree.edge_leaf(pager, bound_key(&self.upper), true)? {
+                Some((_, leaf)) => leaf,
+                None => return self.finish(),
+            };
+
+            let pos = leaf.entries.partition_point(|(key, _)| below_upper(&self.upper, key));
+            self.back = Some(Position { leaf, pos });
+        }
+
+        let back = self.back.as_mut().unwrap();
+        while back.pos == 0 {
+            let prev = match &back.leaf.prev {
+                Some(prev) => prev.clone(),
+                None => return self.finish(),
+            };
+
+            back.leaf = match read_node(pager, &prev)? {
+                Node::Leaf(leaf) => leaf,
+                Node::Internal(_) => return Err(StoreError::Corruption { msg: "Leaf link points at internal node".to_string() }.into()),
+            };
+            back.pos = back.leaf.entries.len();
+        }
+
+        back.pos -= 1;
+        let (key, value) = back.leaf.entries[back.pos].clone();
+
+        let met_front = self.front_key.as_ref().map(|front| &key <= front).unwrap_or(false);
+        if met_front || !above_lower(&self.lower, &key) {
+            return self.finish();
+        }
+
+        self.back_key = Some(key.clone());
+        Ok(Some((key, value)))
+    }
+
+    fn finish(&mut self) -> Result<Option<Entry>, io::Error> {
+        self.done = true;
+        self.front = None;
+        self.back = None;
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::collections::BTreeMap;
+    use uuid::Uuid;
+
+    // In-memory pager that counts reads
+    #[derive(Default)]
+    pub(crate) struct MemPager {
+        pages: BTreeMap<PageId, PageFormat>,
+        next_id: u64,
+        pub reads: Cell<usize>,
+    }
+
+    impl MemPager {
+        pub(crate) fn len(&self) -> usize {
+            self.pages.len()
+        }
+    }
+
+    impl Pager for MemPager {
+        fn allocate(&mut self) -> Result<PageId, io::Error> {
+            let id = PageId::from_u64(self.next_id);
+            self.next_id += 1;
+            self.pages.insert(id.clone(), PageFormat::new());
+            Ok(id)
+        }
+
+        fn read(&self, id: &PageId) -> Result<PageFormat, io::Error> {
+            self.reads.set(self.reads.get() + 1);
+            self.pages.get(id).cloned().ok_or(io::Error::other("Page not allocated"))
+        }
+
+        fn write(&mut self, id: &PageId, page: &PageFormat) -> Result<(), io::Error> {
+            self.pages.insert(id.clone(), page.clone());
+            Ok(())
+        }
+
+        fn free(&mut self, id: PageId) -> Result<(), io::Error> {
+            self.pages.remove(&id).map(|_| ()).ok_or(io::Error::other("Page not allocated"))
+        }
+    }
+
+    fn random_key(len: usize) -> Vec<u8> {
+        let mut key = vec![];
+        while key.len() < len {
+            key.extend_from_slice(Uuid::new_v4().as_bytes());
+        }
+        key.truncate(len);
+        key
+    }
+
+    // Walks the leaf chain forwards, checking the back links on the way
+    fn leaf_entries(tree: &BTree, pager: &mut MemPager) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut id = match &tree.root {
+            Some(root) => root.clone(),
+            None => return vec![],
+        };
+        while let Node::Internal(internal) = read_node(pager, &id).unwrap() {
+            id = internal.children[0].clone();
+        }
+
+        let mut entries = vec![];
+        let mut prev = None;
+        let mut current = Some(id);
+        while let Some(id) = current {
+            let leaf = match read_node(pager, &id).unwrap() {
+                Node::Leaf(leaf) => leaf,
+                Node::Internal(_) => panic!("leaf chain reached an internal node"),
+            };
+
+            assert_eq!(leaf.prev, prev);
+            entries.extend(leaf.entries);
+            prev = Some(id);
+            current = leaf.next;
+        }
+
+        entries
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let mut pager = MemPager::default();
+        let mut tree = BTree::new();
+
+        assert_eq!(tree.get(&pager, b"a").unwrap(), None);
+
+        assert_eq!(tree.insert(&mut pager, b"a", b"1").unwrap(), None);
+        assert_eq!(tree.insert(&mut pager, b"b", b"2").unwrap(), None);
+        assert_eq!(tree.insert(&mut pager, b"a", b"3").unwrap(), Some(b"1".to_vec()));
+
+        assert_eq!(tree.get(&pager, b"a").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(tree.get(&pager, b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(tree.get(&pager, b"c").unwrap(), None);
+    }
+
+    #[test]
+    fn test_splits_keep_leaves_ordered() {
+        let mut pager = MemPager::default();
+        let mut tree = BTree::new();
+
+        let mut expected = BTreeMap::new();
+        for i in 0..2000 {
+            let key = random_key(16);
+            let value = (i as u32).to_le_bytes().to_vec();
+            tree.insert(&mut pager, &key, &value).unwrap();
+            expected.insert(key, value);
+        }
+
+        assert!(tree.height(&pager).unwrap() >= 2);
+
+        let entries = leaf_entries(&tree, &mut pager);
+        assert_eq!(entries, expected.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_lookup_reads_one_page_per_level() {
+        let mut pager = MemPager::default();
+        let mut tree = BTree::new();
+
+        let keys: Vec<Vec<u8>> = (0..3000).map(|_| random_key(100)).collect();
+        for key in &keys {
+            tree.insert(&mut pager, key, b"value").unwrap();
+        }
+
+        let height = tree.height(&pager).unwrap();
+        assert!(height >= 3);
+
+        for key in keys.iter().take(50) {
+            pager.reads.set(0);
+            assert_eq!(tree.get(&pager, key).unwrap(), Some(b"value".to_vec()));
+            assert_eq!(pager.reads.get(), height);
+        }
+    }
+
+    #[test]
+    fn test_remove_merges_and_redistributes() {
+        let mut pager = MemPager::default();
+        let mut tree = BTree::new();
+
+        let mut expected = BTreeMap::new();
+        for _ in 0..3000 {
+            let key = random_key(60);
+            tree.insert(&mut pager, &key, b"some value").unwrap();
+            expected.insert(key, b"some value".to_vec());
+        }
+        let pages = pager.len();
+
+        // Remove two thirds of the keys
+        let removed: Vec<Vec<u8>> = expected.keys().cloned().enumerate().filter(|(i, _)| i % 3 != 0).map(|(_, key)| key).collect();
+        for key in &removed {
+            assert_eq!(tree.remove(&mut pager, key).unwrap(), Some(b"some value".to_vec()));
+            expected.remove(key);
+        }
+
+        assert!(pager.len() < pages);
+        assert_eq!(tree.remove(&mut pager, &removed[0]).unwrap(), None);
+
+        let entries = leaf_entries(&tree, &mut pager);
+        assert_eq!(entries, expected.clone().into_iter().collect::<Vec<_>>());
+
+        for key in expected.keys() {
+            assert_eq!(tree.get(&pager, key).unwrap(), Some(b"some value".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_remove_everything_frees_all_pages() {
+        let mut pager = MemPager::default();
+        let mut tree = BTree::new();
+
+        let keys: Vec<Vec<u8>> = (0..1500).map(|_| random_key(40)).collect();
+        for key in &keys {
+            tree.insert(&mut pager, key, b"v").unwrap();
+        }
+
+        for key in &keys {
+            tree.remove(&mut pager, key).unwrap();
+        }
+
+        assert!(tree.is_empty());
+        assert_eq!(pager.len(), 0);
+    }
+
+    #[test]
+    fn test_cursor_walks_range_from_both_ends() {
+        let mut pager = MemPager::default();
+        let mut tree = BTree::new();
+
+        let keys: Vec<Vec<u8>> = (0u32..2000).map(|i| i.to_be_bytes().to_vec()).collect();
+        for key in &keys {
+            tree.insert(&mut pager, key, b"v").unwrap();
+        }
+
+        let mut cursor = tree.range(Bound::Excluded(keys[10].clone()), Bound::Included(keys[1500].clone()));
+        let mut scanned = vec![];
+        while let Some((key, _)) = cursor.next(&pager).unwrap() {
+            scanned.push(key);
+        }
+        assert_eq!(scanned, keys[11..=1500].to_vec());
+
+        let mut cursor = tree.range(Bound::Unbounded, Bound::Excluded(keys[700].clone()));
+        let mut scanned = vec![];
+        while let Some((key, _)) = cursor.next_back(&pager).unwrap() {
+            scanned.push(key);
+        }
+        scanned.reverse();
+        assert_eq!(scanned, keys[..700].to_vec());
+    }
+
+    #[test]
+    fn test_prefix_bounds() {
+        assert_eq!(prefix_bounds(b"ab"), (Bound::Included(b"ab".to_vec()), Bound::Excluded(b"ac".to_vec())));
+        assert_eq!(prefix_bounds(&[1, 0xff]), (Bound::Included(vec![1, 0xff]), Bound::Excluded(vec![2])));
+        assert_eq!(prefix_bounds(&[0xff]), (Bound::Included(vec![0xff]), Bound::Unbounded));
+    }
+
+    #[test]
+    fn test_entry_too_large() {
+        let mut pager = MemPager::default();
+        let mut tree = BTree::new();
+
+        let result = tree.insert(&mut pager, &vec![0u8; MAX_ENTRY_SIZE], b"");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_node_page_round_trip() {
+        let leaf = Node::Leaf(Leaf {
+            entries: vec![(b"key".to_vec(), b"value".to_vec())],
+            prev: Some(PageId::from_u64(0)),
+            next: None,
+        });
+        let internal = Node::Internal(Internal {
+            keys: vec![b"m".to_vec()],
+            children: vec![PageId::from_u64(3), PageId::from_u64(4)],
+        });
+
+        for node in [leaf, internal] {
+            let page = PageFormat::deserialize(node.to_page().serialize());
+            assert_eq!(Node::from_page(&page).unwrap(), node);
+        }
+
+        assert!(Node::from_page(&PageFormat::new()).is_err());
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::page::PageFormat;
+
+pub const DEFAULT_POOL_CAPACITY: usize = 256;
+
+// Frames are keyed by the page's location in the store file
+struct Frame {
+    page: PageFormat,
+    dirty: bool,
+    pin_count: u32,
+    last_used: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub write_backs: u64,
+}
+
+// Bounded cache of page frames with LRU eviction. The pool never touches
+// the file itself, evicted dirty pages are handed back to the caller to
+// be written out.
+pub struct BufferPool {
+    capacity: usize,
+    frames: HashMap<u64, Frame>,
+    lru: BTreeMap<u64, u64>,
+    tick: u64,
+    stats: PoolStats,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize) -> Self {
+        BufferPool {
+            capacity: capacity.max(1),
+            frames: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            stats: PoolStats::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.stats
+    }
+
+    fn touch(&mut self, location: u64) {
+        self.tick += 1;
+
+        if let Some(frame) = self.frames.get_mut(&location) {
+            self.lru.remove(&frame.last_used);
+            frame.last_used = self.tick;
+            self.lru.insert(self.tick, location);
+        }
+    }
+
+    // Looks up a cached page, counting a hit or a miss
+    pub fn get(&mut self, location: u64) -> Option<&PageFormat> {
+        if !self.frames.contains_key(&location) {
+            self.stats.misses += 1;
+            return None;
+        }
+
+        self.stats.hits += 1;
+        self.touch(location);
+        self.frames.get(&location).map(|frame| &frame.page)
+    }
+
+    // Looks up a cached page without counting it as a use
+    pub fn peek(&self, location: u64) -> Option<&PageFormat> {
+        self.frames.get(&location).map(|frame| &frame.page)
+    }
+
+    pub fn contains(&self, location: u64) -> bool {
+        self.frames.contains_key(&location)
+    }
+
+    pub fn is_dirty(&self, location: u64) -> bool {
+        self.frames.get(&location).map(|frame| frame.dirty).unwrap_or(false)
+    }
+
+    // Caches a page, returning any dirty pages evicted to make room
+    pub fn insert(&mut self, location: u64, page: PageFormat, dirty: bool) -> Vec<(u64, PageFormat)> {
+        if let Some(frame) = self.frames.get_mut(&location) {
+            frame.page = page;
+            frame.dirty |= dirty;
+            self.touch(location);
+            return vec![];
+        }
+
+        let evicted = self.make_room();
+
+        self.frames.insert(location, Frame {
+            page,
+            dirty,
+            pin_count: 0,
+            last_used: 0,
+        });
+        self.touch(location);
+
+        evicted
+    }
+
+    fn make_room(&mut self) -> Vec<(u64, PageFormat)> {
+        let mut evicted = vec![];
+
+        while self.frames.len() >= self.capacity {
+            // Least recently used frame that isn't pinned
+            let victim = self.lru
+                .values()
+                .copied()
+                .find(|location| self.frames[location].pin_count == 0);
+
+            let location = match victim {
+                Some(location) => location,
+                None => break,
+            };
+
+            let frame = self.frames.remove(&location).unwrap();
+            self.lru.remove(&frame.last_used);
+            self.stats.evictions += 1;
+
+            if frame.dirty {
+                self.stats.write_backs += 1;
+                evicted.push((location, frame.page));
+            }
+        }
+
+        evicted
+    }
+
+    // Pinned frames are never evicted
+    pub fn pin(&mut self, location: u64) -> bool {
+        match self.frames.get_mut(&location) {
+            Some(frame) => {
+                frame.pin_count += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn unpin(&mut self, location: u64, dirty: bool) -> bool {
+        match self.frames.get_mut(&location) {
+            Some(frame) if frame.pin_count > 0 => {
+                frame.pin_count -= 1;
+                frame.dirty |= dirty;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn pin_count(&self, location: u64) -> u32 {
+        self.frames.get(&location).map(|frame| frame.pin_count).unwrap_or(0)
+    }
+
+    // Clones out every dirty page and marks the frames clean
+    pub fn take_dirty(&mut self) -> Vec<(u64, PageFormat)> {
+        let mut dirty: Vec<(u64, PageFormat)> = self.frames
+            .iter_mut()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(location, frame)| {
+                frame.dirty = false;
+                (*location, frame.page.clone())
+            })
+            .collect();
+
+        dirty.sort_by_key(|(location, _)| *location);
+        self.stats.write_backs += dirty.len() as u64;
+
+        dirty
+    }
+
+    pub fn remove(&mut self, location: u64) -> Option<PageFormat> {
+        let frame = self.frames.remove(&location)?;
+        self.lru.remove(&frame.last_used);
+
+        Some(frame.page)
+    }
+
+    // Throws away every dirty frame, losing its changes
+    pub fn discard_dirty(&mut self) {
+        let dirty: Vec<u64> = self.frames
+            .iter()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(location, _)| *location)
+            .collect();
+
+        for location in dirty {
+            self.remove(location);
+        }
+    }
+
+    // Drops every clean, unpinned frame
+    pub fn clear(&mut self) {
+        let clean: Vec<u64> = self.frames
+            .iter()
+            .filter(|(_, frame)| !frame.dirty && frame.pin_count == 0)
+            .map(|(location, _)| *location)
+            .collect();
+
+        for location in clean {
+            self.remove(location);
+        }
+    }
+}
+
+// Latches for pages being read in from the file, one per location. A
+// reader missing a page that another reader is already loading waits for
+// that read instead of issuing its own.
+#[derive(Default)]
+pub struct PageLatches {
+    latches: Mutex<HashMap<u64, Arc<Mutex<()>>>>,
+}
+
+impl PageLatches {
+    // Runs load holding the latch for location
+    pub fn with_latch<T>(&self, location: u64, load: impl FnOnce() -> T) -> T {
+        let latch = self.latches.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(location)
+            .or_default()
+            .clone();
+
+        let result = {
+            let _held = latch.lock().unwrap_or_else(PoisonError::into_inner);
+            load()
+        };
+
+        // The last one out removes the latch. Both checking the count and
+        // dropping this handle happen under the table lock so no other
+        // thread can pick the latch up in between.
+        let mut latches = self.latches.lock().unwrap_or_else(PoisonError::into_inner);
+        if Arc::strong_count(&latch) == 2 {
+            latches.remove(&location);
+        }
+        drop(latch);
+
+        result
+    }
+
+    pub fn len(&self) -> usize {
+        self.latches.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    fn page_with(record: &[u8]) -> PageFormat {
+        let mut page = PageFormat::new();
+        page.insert_record(record).unwrap();
+        page
+    }
+
+    #[test]
+    fn test_hit_and_miss_counters() {
+        let mut pool = BufferPool::new(4);
+
+        assert!(pool.get(0).is_none());
+        pool.insert(0, page_with(b"a"), false);
+
+        assert_eq!(pool.get(0).unwrap().read_record(0), Some(&b"a"[..]));
+        assert_eq!(pool.stats().hits, 1);
+        assert_eq!(pool.stats().misses, 1);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut pool = BufferPool::new(2);
+
+        pool.insert(0, page_with(b"a"), false);
+        pool.insert(4096, page_with(b"b"), false);
+
+        // Touch the first page so the second one is the LRU
+        pool.get(0);
+        pool.insert(8192, page_with(b"c"), false);
+
+        assert!(pool.contains(0));
+        assert!(!pool.contains(4096));
+        assert!(pool.contains(8192));
+        assert_eq!(pool.stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_evicting_dirty_page_returns_it() {
+        let mut pool = BufferPool::new(1);
+
+        pool.insert(0, page_with(b"dirty"), true);
+        let evicted = pool.insert(4096, page_with(b"clean"), false);
+
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].0, 0);
+        assert_eq!(evicted[0].1.read_record(0), Some(&b"dirty"[..]));
+        assert_eq!(pool.stats().write_backs, 1);
+
+        // Clean pages are dropped silently
+        let evicted = pool.insert(8192, page_with(b"other"), false);
+        assert!(evicted.is_empty());
+    }
+
+    #[test]
+    fn test_pinned_pages_are_not_evicted() {
+        let mut pool = BufferPool::new(1);
+
+        pool.insert(0, page_with(b"pinned"), false);
+        assert!(pool.pin(0));
+
+        pool.insert(4096, page_with(b"b"), false);
+
+        // Pool grows past capacity rather than evict a pinned page
+        assert!(pool.contains(0));
+        assert_eq!(pool.len(), 2);
+
+        assert!(pool.unpin(0, true));
+        assert!(!pool.unpin(0, false));
+        assert!(pool.is_dirty(0));
+
+        pool.insert(8192, page_with(b"c"), false);
+        assert!(!pool.contains(0));
+    }
+
+    #[test]
+    fn test_take_dirty_marks_frames_clean() {
+        let mut pool = BufferPool::new(4);
+
+        pool.insert(4096, page_with(b"b"), true);
+        pool.insert(0, page_with(b"a"), true);
+        pool.insert(8192, page_with(b"c"), false);
+
+        let dirty = pool.take_dirty();
+        let locations: Vec<u64> = dirty.iter().map(|(location, _)| *location).collect();
+
+        assert_eq!(locations, vec![0, 4096]);
+        assert!(!pool.is_dirty(0));
+        assert!(pool.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn test_discard_dirty() {
+        let mut pool = BufferPool::new(4);
+
+        pool.insert(0, page_with(b"a"), true);
+        pool.insert(4096, page_with(b"b"), false);
+
+        pool.discard_dirty();
+
+        assert!(!pool.contains(0));
+        assert!(pool.contains(4096));
+    }
+
+    #[test]
+    fn test_clear_keeps_dirty_frames() {
+        let mut pool = BufferPool::new(4);
+
+        pool.insert(0, page_with(b"a"), true);
+        pool.insert(4096, page_with(b"b"), false);
+
+        pool.clear();
+
+        assert!(pool.contains(0));
+        assert!(!pool.contains(4096));
+    }
+
+    #[test]
+    fn test_page_latch_admits_one_loader_at_a_time() {
+        let latches = Arc::new(PageLatches::default());
+        let loading = Arc::new(AtomicUsize::new(0));
+        let most_loading = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..8u64)
+            .map(|i| {
+                let (latches, loading, most_loading) = (latches.clone(), loading.clone(), most_loading.clone());
+
+                thread::spawn(move || {
+                    // Half the threads load page 0, the others a page each
+                    if i % 2 == 1 {
+                        return latches.with_latch(i, || thread::sleep(Duration::from_millis(5)));
+                    }
+
+                    latches.with_latch(0, || {
+                        let now = loading.fetch_add(1, Ordering::SeqCst) + 1;
+                        most_loading.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(5));
+                        loading.fetch_sub(1, Ordering::SeqCst);
+                    });
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(most_loading.load(Ordering::SeqCst), 1);
+        assert!(latches.is_empty());
+    }
+}
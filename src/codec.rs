@@ -0,0 +1,471 @@
+use crate::storage_manager::StoreError;
+
+// Length prefix written before variable sized values nested inside others
+const LEN_SIZE: usize = 4;
+
+// Most elements a sequence may decode to when they take no bytes, such as
+// units, which leave nothing in the input to check the count against
+const MAX_EMPTY_ITEMS: usize = 1 << 16;
+
+// Turns a value into bytes for a page. encode writes a self-delimiting
+// form that can be nested inside other values, encode_value is the form
+// stored when the value is the whole record and may drop length prefixes,
+// so strings and byte vectors are stored as their raw bytes.
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+
+    fn encode_value(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode(&mut out);
+        out
+    }
+}
+
+// Reads back what the matching Encode impl wrote. decode consumes its
+// bytes from the front of input, decode_value expects the whole input.
+pub trait Decode: Sized {
+    fn decode(input: &mut &[u8]) -> Result<Self, StoreError>;
+
+    fn decode_value(mut input: &[u8]) -> Result<Self, StoreError> {
+        let value = Self::decode(&mut input)?;
+        if !input.is_empty() {
+            return Err(StoreError::Serialization(format!("{} trailing bytes after value", input.len())));
+        }
+
+        Ok(value)
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], StoreError> {
+    if input.len() < len {
+        return Err(StoreError::Serialization(format!("Expected {} bytes, found {}", len, input.len())));
+    }
+
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+
+    Ok(bytes)
+}
+
+fn encode_len(len: usize, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+fn decode_len(input: &mut &[u8]) -> Result<usize, StoreError> {
+    let bytes = take(input, LEN_SIZE)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+macro_rules! number_codec {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+                    let bytes = take(input, std::mem::size_of::<$ty>())?;
+                    Ok(<$ty>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+number_codec!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+// Pointer sized integers are always stored as 64 bits
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out);
+    }
+}
+
+impl Decode for usize {
+    fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+        usize::try_from(u64::decode(input)?).map_err(|e| StoreError::Serialization(e.to_string()))
+    }
+}
+
+impl Encode for isize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as i64).encode(out);
+    }
+}
+
+impl Decode for isize {
+    fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+        isize::try_from(i64::decode(input)?).map_err(|e| StoreError::Serialization(e.to_string()))
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(StoreError::Serialization(format!("Invalid bool byte {}", byte))),
+        }
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn encode_value(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+
+    fn encode_value(&self) -> Vec<u8> {
+        self.as_str().encode_value()
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+        let len = decode_len(input)?;
+        String::decode_value(take(input, len)?)
+    }
+
+    fn decode_value(input: &[u8]) -> Result<Self, StoreError> {
+        String::from_utf8(input.to_vec()).map_err(|e| StoreError::Serialization(e.to_string()))
+    }
+}
+
+// Sequences are a count followed by their elements, as a whole value the
+// count is left out and elements run to the end of the record. Elements
+// that take no bytes, such as units, keep their count even then, as
+// nothing else would tell how many there were.
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+
+    fn encode_value(&self) -> Vec<u8> {
+        let mut out = vec![];
+        for item in self {
+            item.encode(&mut out);
+        }
+
+        if out.is_empty() && !self.is_empty() {
+            self.encode(&mut out);
+        }
+        out
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+
+    fn encode_value(&self) -> Vec<u8> {
+        self.as_slice().encode_value()
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+        let len = decode_len(input)?;
+
+        // Elements that take bytes can't outnumber them, so a bad count
+        // can't reserve more than the input could hold
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            let before = input.len();
+            items.push(T::decode(input)?);
+
+            if input.len() == before && len > MAX_EMPTY_ITEMS {
+                return Err(StoreError::Serialization(format!("{} empty elements is more than {}", len, MAX_EMPTY_ITEMS)));
+            }
+        }
+
+        Ok(items)
+    }
+
+    fn decode_value(mut input: &[u8]) -> Result<Self, StoreError> {
+        let whole = input;
+        let mut items = vec![];
+        while !input.is_empty() {
+            let before = input.len();
+            items.push(T::decode(&mut input)?);
+
+            // Elements that take no bytes were written with their count
+            if input.len() == before {
+                let mut input = whole;
+                let items = Self::decode(&mut input)?;
+                if !input.is_empty() {
+                    return Err(StoreError::Serialization(format!("{} trailing bytes after value", input.len())));
+                }
+                return Ok(items);
+            }
+        }
+
+        Ok(items)
+    }
+}
+
+// Fixed size arrays need no count
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::decode(input)?);
+        }
+
+        Ok(items.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+            None => out.push(0),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(StoreError::Serialization(format!("Invalid option tag {}", tag))),
+        }
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (**self).encode(out);
+    }
+
+    fn encode_value(&self) -> Vec<u8> {
+        (**self).encode_value()
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (**self).encode(out);
+    }
+
+    fn encode_value(&self) -> Vec<u8> {
+        (**self).encode_value()
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+        Ok(Box::new(T::decode(input)?))
+    }
+
+    fn decode_value(input: &[u8]) -> Result<Self, StoreError> {
+        Ok(Box::new(T::decode_value(input)?))
+    }
+}
+
+impl Encode for () {
+    fn encode(&self, _out: &mut Vec<u8>) {}
+}
+
+impl Decode for () {
+    fn decode(_input: &mut &[u8]) -> Result<Self, StoreError> {
+        Ok(())
+    }
+}
+
+// Reads back a serde type stored with bincode. Serializing can fail and
+// Encode can't, so values only go in through Store::insert_serde, which
+// returns the error.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Bincode<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> Decode for Bincode<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+        let len = decode_len(input)?;
+        Self::decode_value(take(input, len)?)
+    }
+
+    fn decode_value(input: &[u8]) -> Result<Self, StoreError> {
+        bincode::deserialize(input)
+            .map(Bincode)
+            .map_err(|e| StoreError::Serialization(e.to_string()))
+    }
+}
+
+macro_rules! tuple_codec {
+    ($($name:ident),+) => {
+        impl<$($name: Encode),+> Encode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode(out);)+
+            }
+        }
+
+        impl<$($name: Decode),+> Decode for ($($name,)+) {
+            fn decode(input: &mut &[u8]) -> Result<Self, StoreError> {
+                Ok(($($name::decode(input)?,)+))
+            }
+        }
+    };
+}
+
+tuple_codec!(A);
+tuple_codec!(A, B);
+tuple_codec!(A, B, C);
+tuple_codec!(A, B, C, D);
+tuple_codec!(A, B, C, D, E);
+tuple_codec!(A, B, C, D, E, F);
+tuple_codec!(A, B, C, D, E, F, G);
+tuple_codec!(A, B, C, D, E, F, G, H);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Debug;
+
+    fn round_trip<T: Encode + Decode + PartialEq + Debug>(value: T) {
+        assert_eq!(T::decode_value(&value.encode_value()).unwrap(), value);
+
+        // The nested form has to work when followed by other data
+        let mut out = vec![];
+        value.encode(&mut out);
+        out.push(0xaa);
+
+        let mut input = out.as_slice();
+        assert_eq!(T::decode(&mut input).unwrap(), value);
+        assert_eq!(input, &[0xaa]);
+    }
+
+    #[test]
+    fn test_numbers_round_trip() {
+        round_trip(0u8);
+        round_trip(u16::MAX);
+        round_trip(123_456u32);
+        round_trip(u64::MAX - 1);
+        round_trip(i8::MIN);
+        round_trip(-42i32);
+        round_trip(i128::MIN);
+        round_trip(1.5f64);
+        round_trip(usize::MAX);
+        round_trip(-7isize);
+        round_trip(true);
+        round_trip(false);
+    }
+
+    #[test]
+    fn test_containers_round_trip() {
+        round_trip(String::from("hello"));
+        round_trip(String::new());
+        round_trip(vec![1u8, 2, 3]);
+        round_trip(vec![String::from("a"), String::from("bc")]);
+        round_trip(vec![vec![1u32], vec![], vec![2, 3]]);
+        round_trip(Some(5u16));
+        round_trip(None::<String>);
+        round_trip([7u8; 16]);
+        round_trip(Box::new(9i64));
+        round_trip(());
+        round_trip(vec![vec![(); 3]]);
+        round_trip(vec![(); 3]);
+        round_trip(vec![((), [0u8; 0]); 2]);
+        round_trip(Vec::<()>::new());
+    }
+
+    #[test]
+    fn test_tuples_round_trip() {
+        round_trip((1u8,));
+        round_trip((String::from("name"), 42u32, Some(true)));
+        round_trip((1u8, 2u16, 3u32, 4u64, 5i8, 6i16, 7i32, vec![8i64]));
+    }
+
+    #[test]
+    fn test_strings_and_bytes_store_raw() {
+        assert_eq!("abc".encode_value(), b"abc");
+        assert_eq!(String::from("abc").encode_value(), b"abc");
+        assert_eq!(vec![1u8, 2, 3].encode_value(), vec![1, 2, 3]);
+        assert_eq!(b"raw"[..].encode_value(), b"raw");
+        assert_eq!(b"raw".encode_value(), b"raw");
+
+        let mut out = vec![];
+        "abc".encode(&mut out);
+        assert_eq!(out, vec![3, 0, 0, 0, b'a', b'b', b'c']);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_bincode_round_trip() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Account {
+            name: String,
+            balance: i64,
+            tags: Vec<String>,
+        }
+
+        let account = Account {
+            name: String::from("ada"),
+            balance: -5,
+            tags: vec![String::from("admin")],
+        };
+        let bytes = bincode::serialize(&account).unwrap();
+        assert_eq!(Bincode::<Account>::decode_value(&bytes).unwrap(), Bincode(account));
+
+        // Nested, the bytes carry a length prefix
+        let mut out = vec![];
+        encode_len(bytes.len(), &mut out);
+        out.extend_from_slice(&bytes);
+        out.push(0xaa);
+
+        let mut input = out.as_slice();
+        assert!(Bincode::<Account>::decode(&mut input).is_ok());
+        assert_eq!(input, &[0xaa]);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(u32::decode_value(&[1, 2]).is_err());
+        assert!(u8::decode_value(&[1, 2]).is_err());
+        assert!(bool::decode_value(&[2]).is_err());
+        assert!(Option::<u8>::decode_value(&[3, 0]).is_err());
+        assert!(String::decode_value(&[0xff, 0xfe]).is_err());
+        assert!(Vec::<u32>::decode(&mut &[5, 0, 0, 0, 1][..]).is_err());
+        assert!(Vec::<()>::decode_value(&[1]).is_err());
+        assert!(Vec::<()>::decode(&mut &[0xff, 0xff, 0xff, 0xff][..]).is_err());
+    }
+}
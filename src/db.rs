@@ -0,0 +1,297 @@
+use std::io;
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, SystemTime};
+
+use crate::btree::Cursor;
+use crate::codec::{Decode, Encode};
+use crate::iter::{step, Item};
+use crate::keys::Key;
+use crate::snapshot::Snapshot;
+use crate::storage_manager::{Store, StoreOptions};
+use crate::transaction::Transaction;
+use crate::values::Value;
+
+// Store handle that can be shared between threads, usually as Arc<Db>.
+// Any number of readers run at once, reading pages with positional I/O
+// through the shared buffer pool. A writer has the store to itself until
+// its write is committed, log fsync included, so readers and other
+// writers wait for it and only ever see committed data. Long scans are
+// best made through a DbSnapshot, which only holds the store for a step
+// at a time.
+pub struct Db {
+    store: RwLock<Store>,
+}
+
+impl Db {
+    pub fn new(path: &Path) -> Result<Db, io::Error> {
+        Self::open(path, StoreOptions::default())
+    }
+
+    pub fn open(path: &Path, options: StoreOptions) -> Result<Db, io::Error> {
+        Ok(Db { store: RwLock::new(Store::open(path, options)?) })
+    }
+
+    // A writer that panicked may have left changes half made, so the
+    // store is not handed out again
+    fn read_store(&self) -> Result<RwLockReadGuard<'_, Store>, io::Error> {
+        self.store.read().map_err(|_| io::Error::other("Store lock poisoned by a panicked writer"))
+    }
+
+    fn write_store(&self) -> Result<RwLockWriteGuard<'_, Store>, io::Error> {
+        self.store.write().map_err(|_| io::Error::other("Store lock poisoned by a panicked writer"))
+    }
+
+    pub fn get(&self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        self.read_store()?.get(key)
+    }
+
+    pub fn get_as<T: Decode>(&self, key: &Key) -> Result<Option<Value<T>>, io::Error> {
+        self.read_store()?.get_as(key)
+    }
+
+    pub fn contains(&self, key: &Key) -> Result<bool, io::Error> {
+        self.read_store()?.contains(key)
+    }
+
+    pub fn insert<T: Encode>(&self, key: Key, value: Value<T>) -> Result<(), io::Error> {
+        self.write_store()?.insert(key, value)
+    }
+
+    pub fn put_with_ttl<T: Encode>(&self, key: Key, value: Value<T>, ttl: Duration) -> Result<(), io::Error> {
+        self.write_store()?.put_with_ttl(key, value, ttl)
+    }
+
+    pub fn expire(&self, key: &Key, ttl: Duration) -> Result<bool, io::Error> {
+        self.write_store()?.expire(key, ttl)
+    }
+
+    pub fn delete(&self, key: &Key) -> Result<bool, io::Error> {
+        self.write_store()?.delete(key)
+    }
+
+    pub fn sweep_expired(&self) -> Result<usize, io::Error> {
+        self.write_store()?.sweep_expired()
+    }
+
+    // Point-in-time view that stays the same while writers carry on
+    pub fn snapshot(self: &Arc<Self>) -> Result<DbSnapshot, io::Error> {
+        let snapshot = self.write_store()?.snapshot()?;
+
+        Ok(DbSnapshot { db: self.clone(), snapshot })
+    }
+
+    // Runs f with shared access to the store, for reads that need to see
+    // the same data across several calls. Writers wait until f returns.
+    pub fn read<R>(&self, f: impl FnOnce(&Store) -> R) -> Result<R, io::Error> {
+        let store = self.read_store()?;
+        Ok(f(&store))
+    }
+
+    // Runs f in a transaction, committing it if f returns Ok and rolling
+    // it back otherwise. Other writers wait until it is done.
+    pub fn transaction<R>(&self, f: impl FnOnce(&mut Transaction<'_>) -> Result<R, io::Error>) -> Result<R, io::Error> {
+        let mut store = self.write_store()?;
+        let mut tx = store.begin()?;
+
+        let result = f(&mut tx)?;
+        tx.commit()?;
+
+        Ok(result)
+    }
+}
+
+// Snapshot of a Db, see Snapshot. Every lookup, and every step of a scan,
+// takes the store's read lock on its own, so writers get in between.
+pub struct DbSnapshot {
+    db: Arc<Db>,
+    snapshot: Snapshot,
+}
+
+impl DbSnapshot {
+    pub fn taken_at(&self) -> SystemTime {
+        self.snapshot.taken_at()
+    }
+
+    pub fn get(&self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        self.snapshot.get(&*self.db.read_store()?, key)
+    }
+
+    pub fn get_as<T: Decode>(&self, key: &Key) -> Result<Option<Value<T>>, io::Error> {
+        self.snapshot.get_as(&*self.db.read_store()?, key)
+    }
+
+    pub fn contains(&self, key: &Key) -> Result<bool, io::Error> {
+        self.snapshot.contains(&*self.db.read_store()?, key)
+    }
+
+    pub fn iter(&self) -> Result<SnapshotIter<'_>, io::Error> {
+        self.range(..)
+    }
+
+    pub fn range<R: RangeBounds<Key>>(&self, range: R) -> Result<SnapshotIter<'_>, io::Error> {
+        let cursor = self.snapshot.view(&*self.db.read_store()?)?.range_cursor(range);
+        Ok(SnapshotIter { snapshot: self, cursor })
+    }
+
+    pub fn prefix(&self, prefix: &[u8]) -> Result<SnapshotIter<'_>, io::Error> {
+        let cursor = self.snapshot.view(&*self.db.read_store()?)?.prefix_cursor(prefix);
+        Ok(SnapshotIter { snapshot: self, cursor })
+    }
+}
+
+// Key/value pairs of a DbSnapshot in key order, locking the store for
+// each one
+pub struct SnapshotIter<'a> {
+    snapshot: &'a DbSnapshot,
+    cursor: Cursor,
+}
+
+impl SnapshotIter<'_> {
+    fn step(&mut self, back: bool) -> Option<Item> {
+        let store = match self.snapshot.db.read_store() {
+            Ok(store) => store,
+            Err(err) => return Some(Err(err)),
+        };
+
+        match self.snapshot.snapshot.view(&store) {
+            Ok(view) => step(&view, &mut self.cursor, back),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+impl Iterator for SnapshotIter<'_> {
+    type Item = Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step(false)
+    }
+}
+
+impl DoubleEndedIterator for SnapshotIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.step(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::wal_path;
+    use std::env;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(wal_path(&path));
+        path
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_db_is_send_and_sync() {
+        assert_send_sync::<Db>();
+        assert_send_sync::<Store>();
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_on_error() {
+        let path = temp_db("test_db_transaction");
+        let db = Db::new(&path).unwrap();
+
+        let kept = db.transaction(|tx| {
+            tx.put(Key::from("a"), Value::new("a"))?;
+            Ok(Key::from("a"))
+        }).unwrap();
+
+        let failed: Result<(), io::Error> = db.transaction(|tx| {
+            tx.put(Key::from("b"), Value::new("b"))?;
+            Err(io::Error::other("changed my mind"))
+        });
+
+        assert!(failed.is_err());
+        assert_eq!(db.get(&kept).unwrap().unwrap().get(), b"a");
+        assert!(!db.contains(&Key::from("b")).unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_readers_and_writers() {
+        const WRITERS: u32 = 4;
+        const READERS: u32 = 4;
+        const BATCHES: u32 = 10;
+        const BATCH_SIZE: u32 = 20;
+
+        let path = temp_db("test_db_stress");
+        let db = Arc::new(Db::open(&path, StoreOptions { pool_capacity: 16, ..Default::default() }).unwrap());
+        let writing = Arc::new(AtomicBool::new(true));
+
+        let value_for = |writer: u32, i: u32| vec![(writer * 31 + i) as u8; 64 + i as usize];
+
+        let readers: Vec<_> = (0..READERS)
+            .map(|reader| {
+                let (db, writing) = (db.clone(), writing.clone());
+
+                thread::spawn(move || {
+                    let mut seen = 0;
+                    let mut i = reader;
+
+                    while writing.load(Ordering::SeqCst) {
+                        // Whatever a reader finds must be a whole committed value
+                        let (writer, n) = (i % WRITERS, i % (BATCHES * BATCH_SIZE));
+                        if let Some(value) = db.get(&Key::from_parts(&(writer, n))).unwrap() {
+                            assert_eq!(value.get(), &value_for(writer, n));
+                        }
+
+                        // Committed batches never go away again
+                        let count = db.read(|store| store.iter().count()).unwrap();
+                        assert!(count >= seen);
+                        assert_eq!(count % BATCH_SIZE as usize, 0);
+                        seen = count;
+
+                        i = i.wrapping_mul(7).wrapping_add(13);
+                    }
+                })
+            })
+            .collect();
+
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|writer| {
+                let db = db.clone();
+
+                thread::spawn(move || {
+                    for batch in 0..BATCHES {
+                        db.transaction(|tx| {
+                            for i in batch * BATCH_SIZE..(batch + 1) * BATCH_SIZE {
+                                tx.put(Key::from_parts(&(writer, i)), Value::new(value_for(writer, i)))?;
+                            }
+                            Ok(())
+                        }).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        writing.store(false, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        let total = (WRITERS * BATCHES * BATCH_SIZE) as usize;
+        assert_eq!(db.read(|store| store.iter().count()).unwrap(), total);
+        for writer in 0..WRITERS {
+            for i in 0..BATCHES * BATCH_SIZE {
+                assert_eq!(db.get(&Key::from_parts(&(writer, i))).unwrap().unwrap().get(), &value_for(writer, i));
+            }
+        }
+    }
+}
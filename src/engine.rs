@@ -0,0 +1,237 @@
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+
+use crate::bitcask::{BitcaskOptions, BitcaskStore};
+use crate::bloom::BloomStats;
+use crate::codec::{Decode, Encode};
+use crate::keys::Key;
+use crate::lsm::{LsmOptions, LsmStore};
+use crate::storage_manager::{Store, StoreOptions};
+use crate::values::Value;
+
+type KeyValue = (Key, Value<Vec<u8>>);
+
+pub type EntryIter<'a> = Box<dyn Iterator<Item = Result<KeyValue, io::Error>> + 'a>;
+
+// Key/value operations every storage engine supports, so code can be
+// written once and run on whichever engine a store was opened with
+pub trait Engine: Send {
+    fn get(&self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error>;
+
+    fn put(&mut self, key: Key, value: Value<Vec<u8>>) -> Result<(), io::Error>;
+
+    // Returns false if there was no such key
+    fn delete(&mut self, key: &Key) -> Result<bool, io::Error>;
+
+    // Key/value pairs between two bounds in key order
+    fn scan(&self, lower: Bound<Key>, upper: Bound<Key>) -> EntryIter<'_>;
+
+    // Makes everything written so far durable and compact on disk
+    fn flush(&mut self) -> Result<(), io::Error>;
+
+    fn contains(&self, key: &Key) -> Result<bool, io::Error> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    // How often bloom filters spared lookups a read, all zero for engines
+    // that have none
+    fn bloom_stats(&self) -> BloomStats {
+        BloomStats::default()
+    }
+}
+
+impl dyn Engine + '_ {
+    // Decodes the stored bytes as T
+    pub fn get_as<T: Decode>(&self, key: &Key) -> Result<Option<Value<T>>, io::Error> {
+        match self.get(key)? {
+            Some(value) => Ok(Some(Value::new(T::decode_value(value.get())?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert<T: Encode>(&mut self, key: Key, value: Value<T>) -> Result<(), io::Error> {
+        self.put(key, Value::new(value.get().encode_value()))
+    }
+}
+
+// Which engine to open a store with, and its options
+pub enum EngineKind {
+    // B+tree indexed pages updated in place, see Store. The default.
+    // StoreOptions::hash_index adds a hash index for exact-key lookups.
+    Paged(StoreOptions),
+    // Log-structured merge tree, see LsmStore. Its path is a directory.
+    Lsm(LsmOptions),
+    // Append-only log with an in-memory key directory, see BitcaskStore.
+    // Its path is a directory.
+    Bitcask(BitcaskOptions),
+}
+
+impl Default for EngineKind {
+    fn default() -> Self {
+        EngineKind::Paged(StoreOptions::default())
+    }
+}
+
+pub fn open(path: &Path, kind: EngineKind) -> Result<Box<dyn Engine>, io::Error> {
+    Ok(match kind {
+        EngineKind::Paged(options) => Box::new(Store::open(path, options)?),
+        EngineKind::Lsm(options) => Box::new(LsmStore::open(path, options)?),
+        EngineKind::Bitcask(options) => Box::new(BitcaskStore::open(path, options)?),
+    })
+}
+
+// Whether no key can lie between lower and upper. BTreeMap::range panics
+// on such bounds instead of returning nothing.
+pub(crate) fn is_empty_range(lower: &Bound<Key>, upper: &Bound<Key>) -> bool {
+    match (lower, upper) {
+        (Bound::Included(lower), Bound::Included(upper)) => lower > upper,
+        (Bound::Included(lower) | Bound::Excluded(lower), Bound::Excluded(upper) | Bound::Included(upper)) => lower >= upper,
+        _ => false,
+    }
+}
+
+impl Engine for Store {
+    fn get(&self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        Store::get(self, key)
+    }
+
+    fn put(&mut self, key: Key, value: Value<Vec<u8>>) -> Result<(), io::Error> {
+        self.insert(key, value)
+    }
+
+    fn delete(&mut self, key: &Key) -> Result<bool, io::Error> {
+        Store::delete(self, key)
+    }
+
+    fn scan(&self, lower: Bound<Key>, upper: Bound<Key>) -> EntryIter<'_> {
+        Box::new(self.range((lower, upper)))
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Store::flush(self)
+    }
+}
+
+impl Engine for LsmStore {
+    fn get(&self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        LsmStore::get(self, key)
+    }
+
+    fn put(&mut self, key: Key, value: Value<Vec<u8>>) -> Result<(), io::Error> {
+        self.insert(key, value)
+    }
+
+    fn delete(&mut self, key: &Key) -> Result<bool, io::Error> {
+        LsmStore::delete(self, key)
+    }
+
+    fn scan(&self, lower: Bound<Key>, upper: Bound<Key>) -> EntryIter<'_> {
+        Box::new(self.range(lower, upper))
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        LsmStore::flush(self)
+    }
+
+    fn bloom_stats(&self) -> BloomStats {
+        LsmStore::bloom_stats(self)
+    }
+}
+
+impl Engine for BitcaskStore {
+    fn get(&self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        BitcaskStore::get(self, key)
+    }
+
+    fn put(&mut self, key: Key, value: Value<Vec<u8>>) -> Result<(), io::Error> {
+        self.insert(key, value)
+    }
+
+    fn delete(&mut self, key: &Key) -> Result<bool, io::Error> {
+        BitcaskStore::delete(self, key)
+    }
+
+    fn scan(&self, lower: Bound<Key>, upper: Bound<Key>) -> EntryIter<'_> {
+        Box::new(self.range(lower, upper))
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.sync()
+    }
+
+    fn contains(&self, key: &Key) -> Result<bool, io::Error> {
+        BitcaskStore::contains(self, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::wal_path;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(wal_path(&path));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    fn exercise(engine: &mut dyn Engine) {
+        for i in 0..300u64 {
+            engine.insert(Key::from(i), Value::new(i * 3)).unwrap();
+        }
+        for i in (0..300u64).step_by(3) {
+            assert!(engine.delete(&Key::from(i)).unwrap());
+        }
+        assert!(!engine.delete(&Key::from(0u64)).unwrap());
+        engine.flush().unwrap();
+
+        assert_eq!(*engine.get_as::<u64>(&Key::from(7u64)).unwrap().unwrap().get(), 21);
+        assert!(!engine.contains(&Key::from(9u64)).unwrap());
+
+        let scanned: Vec<u64> = engine.scan(Bound::Included(Key::from(10u64)), Bound::Excluded(Key::from(20u64)))
+            .map(|entry| entry.unwrap().0.to_u64().unwrap())
+            .collect();
+        assert_eq!(scanned, vec![10, 11, 13, 14, 16, 17, 19]);
+        assert_eq!(engine.scan(Bound::Unbounded, Bound::Unbounded).count(), 200);
+    }
+
+    #[test]
+    fn test_engines_behave_alike() {
+        let kinds = [
+            (temp_path("test_engine_paged"), EngineKind::default()),
+            (temp_path("test_engine_hashed"), EngineKind::Paged(StoreOptions { hash_index: true, ..Default::default() })),
+            (temp_path("test_engine_lsm"), EngineKind::Lsm(LsmOptions { memtable_size: 4 << 10, ..Default::default() })),
+            (temp_path("test_engine_bitcask"), EngineKind::Bitcask(BitcaskOptions { max_segment_size: 4 << 10, ..Default::default() })),
+        ];
+
+        for (path, kind) in kinds {
+            let mut engine = open(&path, kind).unwrap();
+            exercise(engine.as_mut());
+        }
+    }
+
+    #[test]
+    fn test_bloom_stats_through_engine() {
+        let path = temp_path("test_engine_bloom_stats");
+        let mut engine = open(&path, EngineKind::Lsm(LsmOptions::default())).unwrap();
+        engine.insert(Key::from(1u64), Value::new(1u64)).unwrap();
+        engine.flush().unwrap();
+
+        assert!(engine.get(&Key::from(1u64)).unwrap().is_some());
+        for i in 2..100u64 {
+            assert!(engine.get(&Key::from(i)).unwrap().is_none());
+        }
+        let stats = engine.bloom_stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.skips + stats.false_positives, 98);
+
+        let path = temp_path("test_engine_bloom_stats_paged");
+        let engine = open(&path, EngineKind::default()).unwrap();
+        assert_eq!(engine.bloom_stats(), BloomStats::default());
+    }
+}
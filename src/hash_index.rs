@@ -0,0 +1,583 @@
+use std::collections::BTreeSet;
+use std::io;
+
+use crate::page::{PageFormat, PageType};
+use crate::storage_manager::{PageId, Pager, StoreError};
+
+// Largest key plus value a bucket entry may hold, so a split bucket always
+// has room for the entry that overflowed it
+pub const MAX_ENTRY_SIZE: usize = 1024;
+
+const KEY_LEN_SIZE: usize = 2;
+const LINK_SIZE: usize = 8;
+
+// Bucket ids held by each directory page. A power of two, so the slots of
+// a directory that has grown past one page split evenly across pages.
+const SLOTS_PER_PAGE: usize = 256;
+
+// Keys sharing this many hash bits cannot be told apart by splitting
+const MAX_DEPTH: u32 = 32;
+
+// FNV-1a with a final mix so the low bits, which pick the slot, depend on
+// every byte of the key. Stable across runs since buckets are on disk.
+fn hash_key(key: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in key {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash
+}
+
+fn low_bits(hash: u64, depth: u32) -> usize {
+    (hash & ((1u64 << depth) - 1)) as usize
+}
+
+fn encode_link(link: &Option<PageId>) -> u64 {
+    link.as_ref().map(|id| id.as_u64() + 1).unwrap_or(0)
+}
+
+fn decode_link(link: u64) -> Option<PageId> {
+    match link {
+        0 => None,
+        link => Some(PageId::from_u64(link - 1)),
+    }
+}
+
+fn entry_size(key: &[u8], value: &[u8]) -> usize {
+    KEY_LEN_SIZE + key.len() + value.len() + PageFormat::slot_overhead()
+}
+
+// Slot 0 of a bucket page holds its local depth, the number of hash bits
+// all of its keys share. Entries follow in no particular order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Bucket {
+    depth: u32,
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Bucket {
+    fn size(&self) -> usize {
+        let header = 1 + PageFormat::slot_overhead();
+
+        header + self.entries.iter().map(|(key, value)| entry_size(key, value)).sum::<usize>()
+    }
+
+    fn fits(&self) -> bool {
+        self.size() <= PageFormat::capacity()
+    }
+
+    fn position(&self, key: &[u8]) -> Option<usize> {
+        self.entries.iter().position(|(entry_key, _)| entry_key == key)
+    }
+
+    fn to_page(&self) -> PageFormat {
+        let mut page = PageFormat::new();
+        page.set_page_type(PageType::HashBucket);
+        page.insert_record(&[self.depth as u8]);
+
+        for (key, value) in &self.entries {
+            let mut record = Vec::with_capacity(KEY_LEN_SIZE + key.len() + value.len());
+            record.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            record.extend_from_slice(key);
+            record.extend_from_slice(value);
+            page.insert_record(&record);
+        }
+
+        page
+    }
+
+    fn from_page(page: &PageFormat) -> Result<Self, StoreError> {
+        let records = page.records();
+        let depth = match records.split_first() {
+            Some(((_, [depth]), _)) if page.get_page_type() == PageType::HashBucket => *depth as u32,
+            _ => return Err(StoreError::Corruption { msg: "Page is not a hash bucket".to_string() }),
+        };
+
+        let mut entries = Vec::with_capacity(records.len() - 1);
+        for (_, record) in &records[1..] {
+            let key_len = match record.get(..KEY_LEN_SIZE) {
+                Some(len) => u16::from_le_bytes([len[0], len[1]]) as usize,
+                None => return Err(StoreError::Corruption { msg: "Hash bucket entry too short".to_string() }),
+            };
+            if record.len() < KEY_LEN_SIZE + key_len {
+                return Err(StoreError::Corruption { msg: "Hash bucket entry key overruns record".to_string() });
+            }
+
+            let key = record[KEY_LEN_SIZE..KEY_LEN_SIZE + key_len].to_vec();
+            let value = record[KEY_LEN_SIZE + key_len..].to_vec();
+            entries.push((key, value));
+        }
+
+        Ok(Bucket { depth, entries })
+    }
+}
+
+// A directory page is a single record of bucket ids, its slots, with the
+// next directory page linked through the page header
+fn directory_page(slots: &[PageId], next: &Option<PageId>) -> PageFormat {
+    let mut page = PageFormat::new();
+    page.set_page_type(PageType::HashDirectory);
+    page.set_next_page(encode_link(next));
+
+    let record: Vec<u8> = slots.iter().flat_map(|id| id.as_u64().to_le_bytes()).collect();
+    page.insert_record(&record);
+
+    page
+}
+
+fn read_slots<P: Pager>(pager: &P, id: &PageId) -> Result<(Vec<PageId>, Option<PageId>), io::Error> {
+    let page = pager.read(id)?;
+    let record = match page.read_record(0) {
+        Some(record) if page.get_page_type() == PageType::HashDirectory && record.len() % LINK_SIZE == 0 => record,
+        _ => return Err(StoreError::Corruption { msg: "Page is not a hash directory".to_string() }.into()),
+    };
+
+    let slots = record
+        .chunks_exact(LINK_SIZE)
+        .map(|id| PageId::from_u64(u64::from_le_bytes(id.try_into().unwrap())))
+        .collect();
+
+    Ok((slots, decode_link(page.get_next_page())))
+}
+
+fn read_bucket<P: Pager>(pager: &P, id: &PageId) -> Result<Bucket, io::Error> {
+    let page = pager.read(id)?;
+    Ok(Bucket::from_page(&page)?)
+}
+
+fn write_bucket<P: Pager>(pager: &mut P, id: &PageId, bucket: &Bucket) -> Result<(), io::Error> {
+    pager.write(id, &bucket.to_page())
+}
+
+// Disk-resident extendible hash index for exact-key lookups. The low
+// bits of a key's hash pick a directory slot, which names the bucket page
+// holding the key, so a lookup reads one directory page and one bucket
+// whatever the number of keys. A bucket that fills up splits in two on
+// its next hash bit, doubling the directory first if it has no spare bit.
+//
+// Only the directory's page ids are held in memory, the directory pages
+// themselves are chained from the first one, which is the index root. A
+// store opened with StoreOptions::hash_index keeps one next to its B+tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashIndex {
+    directory: Vec<PageId>,
+    depth: u32,
+}
+
+impl HashIndex {
+    pub fn new() -> Self {
+        HashIndex { directory: vec![], depth: 0 }
+    }
+
+    // Walks the directory chain starting at root
+    pub fn open<P: Pager>(pager: &P, root: Option<PageId>) -> Result<Self, io::Error> {
+        let mut directory = vec![];
+        let mut slots = 0;
+
+        let mut current = root;
+        while let Some(id) = current {
+            let (page_slots, next) = read_slots(pager, &id)?;
+            slots += page_slots.len();
+            directory.push(id);
+            current = next;
+        }
+
+        if !directory.is_empty() && (!slots.is_power_of_two() || slots / directory.len() != slots.min(SLOTS_PER_PAGE)) {
+            return Err(StoreError::Corruption { msg: "Bad hash directory size".to_string() }.into());
+        }
+
+        Ok(HashIndex { directory, depth: slots.max(1).trailing_zeros() })
+    }
+
+    pub fn root(&self) -> Option<&PageId> {
+        self.directory.first()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directory.is_empty()
+    }
+
+    // Number of hash bits the directory is indexed by
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    fn slot_count(&self) -> usize {
+        1 << self.depth
+    }
+
+    // Bucket the given directory slot points at
+    fn bucket_at<P: Pager>(&self, pager: &P, slot: usize) -> Result<PageId, io::Error> {
+        let (slots, _) = read_slots(pager, &self.directory[slot / SLOTS_PER_PAGE])?;
+        slots.get(slot % SLOTS_PER_PAGE).cloned()
+            .ok_or(StoreError::Corruption { msg: "Hash directory slot out of range".to_string() }.into())
+    }
+
+    // Points every slot whose low bits are those of slot, looking at the
+    // given number of bits, at bucket
+    fn set_slots<P: Pager>(&self, pager: &mut P, slot: usize, depth: u32, bucket: &PageId) -> Result<(), io::Error> {
+        let step = 1usize << depth;
+        let first = slot & (step - 1);
+
+        let per_page = self.slot_count().min(SLOTS_PER_PAGE);
+
+        for (i, id) in self.directory.iter().enumerate() {
+            let start = first.wrapping_sub(i * per_page) & (step - 1);
+            if start >= per_page {
+                continue;
+            }
+
+            let (mut slots, next) = read_slots(pager, id)?;
+            for slot in (start..slots.len()).step_by(step) {
+                slots[slot] = bucket.clone();
+            }
+            pager.write(id, &directory_page(&slots, &next))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get<P: Pager>(&self, pager: &P, key: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let id = self.bucket_at(pager, low_bits(hash_key(key), self.depth))?;
+        let mut bucket = read_bucket(pager, &id)?;
+
+        Ok(bucket.position(key).map(|i| bucket.entries.swap_remove(i).1))
+    }
+
+    // Inserts or replaces a value, returning the previous one
+    pub fn insert<P: Pager>(&mut self, pager: &mut P, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
+        if KEY_LEN_SIZE + key.len() + value.len() > MAX_ENTRY_SIZE {
+            return Err(StoreError::Serialization("Hash index entry too large".to_string()).into());
+        }
+
+        if self.is_empty() {
+            let root = pager.allocate()?;
+            let bucket = pager.allocate()?;
+            write_bucket(pager, &bucket, &Bucket { depth: 0, entries: vec![(key.to_vec(), value.to_vec())] })?;
+            pager.write(&root, &directory_page(&[bucket], &None))?;
+
+            self.directory = vec![root];
+            self.depth = 0;
+            return Ok(None);
+        }
+
+        let hash = hash_key(key);
+        loop {
+            let slot = low_bits(hash, self.depth);
+            let id = self.bucket_at(pager, slot)?;
+            let mut bucket = read_bucket(pager, &id)?;
+
+            let position = bucket.position(key);
+            let old = match position {
+                Some(i) => Some(std::mem::replace(&mut bucket.entries[i].1, value.to_vec())),
+                None => {
+                    bucket.entries.push((key.to_vec(), value.to_vec()));
+                    None
+                }
+            };
+
+            if bucket.fits() {
+                write_bucket(pager, &id, &bucket)?;
+                return Ok(old);
+            }
+
+            // Split the bucket as it was and try again, the entry may
+            // still land in a full half
+            match (position, old) {
+                (Some(i), Some(old)) => bucket.entries[i].1 = old,
+                _ => {
+                    bucket.entries.pop();
+                }
+            }
+            if bucket.depth == self.depth {
+                if self.depth == MAX_DEPTH {
+                    return Err(StoreError::Serialization("Too many keys share a hash".to_string()).into());
+                }
+                self.grow(pager)?;
+            }
+            self.split(pager, slot, id, bucket)?;
+        }
+    }
+
+    // Doubles the directory, the new upper half pointing at the same
+    // buckets as the lower half
+    fn grow<P: Pager>(&mut self, pager: &mut P) -> Result<(), io::Error> {
+        if self.slot_count() < SLOTS_PER_PAGE {
+            let (mut slots, _) = read_slots(pager, &self.directory[0])?;
+            slots.extend_from_within(..);
+            pager.write(&self.directory[0], &directory_page(&slots, &None))?;
+        } else {
+            let copies = (0..self.directory.len()).map(|_| pager.allocate()).collect::<Result<Vec<_>, _>>()?;
+            let old = self.directory.len();
+            self.directory.extend(copies);
+
+            for i in 0..self.directory.len() {
+                let (slots, _) = read_slots(pager, &self.directory[i % old])?;
+                pager.write(&self.directory[i], &directory_page(&slots, &self.directory.get(i + 1).cloned()))?;
+            }
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Moves the entries of a full bucket whose next hash bit is set into a
+    // new bucket
+    fn split<P: Pager>(&mut self, pager: &mut P, slot: usize, id: PageId, bucket: Bucket) -> Result<(), io::Error> {
+        let bit = bucket.depth;
+        let (high, low): (Vec<_>, Vec<_>) = bucket.entries
+            .into_iter()
+            .partition(|(key, _)| hash_key(key) & (1 << bit) != 0);
+
+        let sibling = pager.allocate()?;
+        write_bucket(pager, &id, &Bucket { depth: bit + 1, entries: low })?;
+        write_bucket(pager, &sibling, &Bucket { depth: bit + 1, entries: high })?;
+
+        self.set_slots(pager, slot | (1 << bit), bit + 1, &sibling)
+    }
+
+    // Removes a key, returning its value
+    pub fn remove<P: Pager>(&mut self, pager: &mut P, key: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let mut slot = low_bits(hash_key(key), self.depth);
+        let mut id = self.bucket_at(pager, slot)?;
+        let mut bucket = read_bucket(pager, &id)?;
+
+        let old = match bucket.position(key) {
+            Some(i) => bucket.entries.swap_remove(i).1,
+            None => return Ok(None),
+        };
+
+        // Fold the bucket into its buddy, the bucket differing only in the
+        // last bit they were split on, for as long as both fit in one page
+        let mut merged = false;
+        while bucket.depth > 0 {
+            let buddy_slot = slot ^ (1 << (bucket.depth - 1));
+            let buddy_id = self.bucket_at(pager, buddy_slot)?;
+            let buddy = read_bucket(pager, &buddy_id)?;
+
+            let mut combined = Bucket { depth: bucket.depth - 1, entries: bucket.entries.clone() };
+            combined.entries.extend(buddy.entries);
+            if buddy.depth != bucket.depth || !combined.fits() {
+                break;
+            }
+
+            // The bucket with the bit clear stays
+            let (keep, gone) = match slot & (1 << combined.depth) {
+                0 => (id, buddy_id),
+                _ => (buddy_id, id),
+            };
+            pager.free(gone)?;
+            self.set_slots(pager, slot, combined.depth, &keep)?;
+
+            slot = low_bits(slot as u64, combined.depth);
+            id = keep;
+            bucket = combined;
+            merged = true;
+        }
+
+        write_bucket(pager, &id, &bucket)?;
+        if merged {
+            self.shrink(pager)?;
+        }
+
+        Ok(Some(old))
+    }
+
+    // Halves the directory for as long as no bucket needs its top bit
+    fn shrink<P: Pager>(&mut self, pager: &mut P) -> Result<(), io::Error> {
+        while self.depth > 0 {
+            let mut slots = vec![];
+            for id in &self.directory {
+                slots.extend(read_slots(pager, id)?.0);
+            }
+
+            let half = slots.len() / 2;
+            if slots[..half] != slots[half..] {
+                return Ok(());
+            }
+
+            if self.directory.len() > 1 {
+                for id in self.directory.split_off(self.directory.len() / 2) {
+                    pager.free(id)?;
+                }
+                let last = self.directory.len() - 1;
+                let start = last * SLOTS_PER_PAGE;
+                pager.write(&self.directory[last], &directory_page(&slots[start..start + SLOTS_PER_PAGE], &None))?;
+            } else {
+                pager.write(&self.directory[0], &directory_page(&slots[..half], &None))?;
+            }
+
+            self.depth -= 1;
+        }
+
+        Ok(())
+    }
+
+    // Frees every page of the index
+    pub fn clear<P: Pager>(&mut self, pager: &mut P) -> Result<(), io::Error> {
+        let mut buckets = BTreeSet::new();
+        for id in &self.directory {
+            buckets.extend(read_slots(pager, id)?.0);
+        }
+
+        for id in buckets.into_iter().chain(self.directory.drain(..)) {
+            pager.free(id)?;
+        }
+        self.depth = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree::tests::MemPager;
+    use crate::keys::Key;
+    use std::collections::BTreeMap;
+
+    fn value_for(i: u64) -> Vec<u8> {
+        vec![i as u8; 150 + i as usize % 100]
+    }
+
+    #[test]
+    fn test_insert_get_and_remove() {
+        let mut pager = MemPager::default();
+        let mut index = HashIndex::new();
+
+        assert_eq!(index.get(&pager, b"a").unwrap(), None);
+        assert_eq!(index.remove(&mut pager, b"a").unwrap(), None);
+
+        assert_eq!(index.insert(&mut pager, b"a", b"1").unwrap(), None);
+        assert_eq!(index.insert(&mut pager, b"b", b"2").unwrap(), None);
+        assert_eq!(index.insert(&mut pager, b"a", b"3").unwrap(), Some(b"1".to_vec()));
+
+        assert_eq!(index.get(&pager, b"a").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(index.get(&pager, b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(index.get(&pager, b"c").unwrap(), None);
+
+        assert_eq!(index.remove(&mut pager, b"a").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(index.get(&pager, b"a").unwrap(), None);
+        assert_eq!(pager.len(), 2);
+    }
+
+    #[test]
+    fn test_lookup_reads_two_pages_at_any_size() {
+        let mut pager = MemPager::default();
+        let mut index = HashIndex::new();
+
+        let mut expected = BTreeMap::new();
+        for i in 0..8000u64 {
+            let key = Key::from(i);
+            index.insert(&mut pager, key.as_bytes(), &value_for(i)).unwrap();
+            expected.insert(key, value_for(i));
+        }
+
+        // The directory has outgrown its first page
+        assert!(index.directory.len() > 1);
+
+        for (key, value) in expected.iter().step_by(97) {
+            pager.reads.set(0);
+            assert_eq!(index.get(&pager, key.as_bytes()).unwrap().as_ref(), Some(value));
+            assert_eq!(pager.reads.get(), 2);
+        }
+        for i in 8000..8100u64 {
+            pager.reads.set(0);
+            assert_eq!(index.get(&pager, Key::from(i).as_bytes()).unwrap(), None);
+            assert_eq!(pager.reads.get(), 2);
+        }
+
+        let reopened = HashIndex::open(&pager, index.root().cloned()).unwrap();
+        assert_eq!(reopened, index);
+        for (key, value) in &expected {
+            assert_eq!(reopened.get(&pager, key.as_bytes()).unwrap().as_ref(), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_remove_merges_buckets_and_shrinks_directory() {
+        let mut pager = MemPager::default();
+        let mut index = HashIndex::new();
+
+        for i in 0..4000u64 {
+            index.insert(&mut pager, Key::from(i).as_bytes(), &value_for(i)).unwrap();
+        }
+        let grown = pager.len();
+        let depth = index.depth();
+
+        for i in (0..4000u64).filter(|i| i % 4 != 0) {
+            assert_eq!(index.remove(&mut pager, Key::from(i).as_bytes()).unwrap(), Some(value_for(i)));
+        }
+        assert!(pager.len() < grown);
+        assert!(index.depth() < depth);
+
+        for i in 0..4000u64 {
+            let expected = (i % 4 == 0).then(|| value_for(i));
+            assert_eq!(index.get(&pager, Key::from(i).as_bytes()).unwrap(), expected);
+        }
+
+        for i in (0..4000u64).step_by(4) {
+            index.remove(&mut pager, Key::from(i).as_bytes()).unwrap();
+        }
+        assert_eq!(index.depth(), 0);
+        assert_eq!(pager.len(), 2);
+
+        index.clear(&mut pager).unwrap();
+        assert!(index.is_empty());
+        assert_eq!(pager.len(), 0);
+    }
+
+    #[test]
+    fn test_update_that_overflows_its_bucket() {
+        let mut pager = MemPager::default();
+        let mut index = HashIndex::new();
+
+        for i in 0..30u64 {
+            index.insert(&mut pager, Key::from(i).as_bytes(), &[i as u8; 100]).unwrap();
+        }
+        let depth = index.depth();
+
+        let old = index.insert(&mut pager, Key::from(0u64).as_bytes(), &[0xff; 1000]).unwrap();
+        assert_eq!(old, Some(vec![0; 100]));
+        assert!(index.depth() > depth);
+
+        assert_eq!(index.get(&pager, Key::from(0u64).as_bytes()).unwrap(), Some(vec![0xff; 1000]));
+        for i in 1..30u64 {
+            assert_eq!(index.get(&pager, Key::from(i).as_bytes()).unwrap(), Some(vec![i as u8; 100]));
+        }
+    }
+
+    #[test]
+    fn test_entry_too_large() {
+        let mut pager = MemPager::default();
+        let mut index = HashIndex::new();
+
+        assert!(index.insert(&mut pager, &vec![0u8; MAX_ENTRY_SIZE], b"").is_err());
+    }
+
+    #[test]
+    fn test_bucket_page_round_trip() {
+        let bucket = Bucket {
+            depth: 3,
+            entries: vec![(b"key".to_vec(), b"value".to_vec()), (b"other".to_vec(), vec![])],
+        };
+
+        let page = PageFormat::deserialize(bucket.to_page().serialize());
+        assert_eq!(Bucket::from_page(&page).unwrap(), bucket);
+
+        assert!(Bucket::from_page(&PageFormat::new()).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use std::io;
+
+use crate::btree::{Cursor, Entry};
+use crate::keys::Key;
+use crate::storage_manager::PageView;
+use crate::values::Value;
+
+pub(crate) type Item = Result<(Key, Value<Vec<u8>>), io::Error>;
+
+// Key/value pairs of a store in key order, as returned by Store::iter,
+// Store::range and Store::prefix or the same on a Snapshot. Pages are read
+// as the iterator advances so the whole keyspace never has to be in memory
+// at once.
+pub struct Iter<'a> {
+    view: PageView<'a>,
+    cursor: Cursor,
+}
+
+impl<'a> Iter<'a> {
+    pub(crate) fn new(view: PageView<'a>, cursor: Cursor) -> Self {
+        Iter { view, cursor }
+    }
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        step(&self.view, &mut self.cursor, false)
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        step(&self.view, &mut self.cursor, true)
+    }
+}
+
+// Moves the cursor on to the next record that hasn't expired, from the
+// back end if back is set, and loads it
+pub(crate) fn step(view: &PageView<'_>, cursor: &mut Cursor, back: bool) -> Option<Item> {
+    loop {
+        let entry = match back {
+            false => cursor.next(view),
+            true => cursor.next_back(view),
+        };
+
+        let (_, record_id): Entry = match entry {
+            Ok(entry) => entry?,
+            Err(err) => return Some(Err(err)),
+        };
+
+        if let Some(item) = view.load_indexed(&record_id).transpose() {
+            return Some(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage_manager::Store;
+    use crate::values::Value;
+    use crate::wal::wal_path;
+    use crate::Key;
+    use std::env;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    fn temp_store(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(wal_path(&path));
+        path
+    }
+
+    fn sorted_keys(store: &mut Store, count: usize) -> Vec<Key> {
+        let mut keys: Vec<Key> = (0..count).map(|_| Key::new()).collect();
+        keys.sort();
+
+        let mut tx = store.begin().unwrap();
+        for key in &keys {
+            tx.put(key.clone(), Value::new(key.as_bytes().to_vec())).unwrap();
+        }
+        tx.commit().unwrap();
+
+        keys
+    }
+
+    #[test]
+    fn test_iter_in_key_order() {
+        let path = temp_store("test_iter_order");
+        let mut store = Store::new(&path).unwrap();
+        let keys = sorted_keys(&mut store, 800);
+
+        let scanned: Vec<Key> = store.iter().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(scanned, keys);
+
+        let reversed: Vec<Key> = store.iter().rev().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(reversed, keys.iter().rev().cloned().collect::<Vec<_>>());
+
+        for entry in store.iter() {
+            let (key, value) = entry.unwrap();
+            assert_eq!(value.get(), key.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let path = temp_store("test_iter_range");
+        let mut store = Store::new(&path).unwrap();
+        let keys = sorted_keys(&mut store, 500);
+
+        let scanned: Vec<Key> = store.range(keys[100].clone()..keys[300].clone()).map(|entry| entry.unwrap().0).collect();
+        assert_eq!(scanned, keys[100..300].to_vec());
+
+        let scanned: Vec<Key> = store.range(keys[450].clone()..).map(|entry| entry.unwrap().0).collect();
+        assert_eq!(scanned, keys[450..].to_vec());
+
+        let scanned: Vec<Key> = store.range(..=keys[10].clone()).rev().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(scanned, keys[..=10].iter().rev().cloned().collect::<Vec<_>>());
+
+        assert_eq!(store.range(keys[5].clone()..keys[5].clone()).count(), 0);
+    }
+
+    #[test]
+    fn test_both_ends_meet_in_the_middle() {
+        let path = temp_store("test_iter_both_ends");
+        let mut store = Store::new(&path).unwrap();
+        let keys = sorted_keys(&mut store, 300);
+
+        let mut iter = store.iter();
+        let mut front = vec![];
+        let mut back = vec![];
+        while let Some(entry) = iter.next() {
+            front.push(entry.unwrap().0);
+
+            match iter.next_back() {
+                Some(entry) => back.push(entry.unwrap().0),
+                None => break,
+            }
+        }
+
+        back.reverse();
+        front.extend(back);
+        assert_eq!(front, keys);
+    }
+
+    #[test]
+    fn test_prefix_scan() {
+        let path = temp_store("test_iter_prefix");
+        let mut store = Store::new(&path).unwrap();
+        let keys = sorted_keys(&mut store, 1000);
+
+        let prefix = [keys[500].as_bytes()[0]];
+        let expected: Vec<Key> = keys.iter().filter(|key| key.as_bytes()[0] == prefix[0]).cloned().collect();
+
+        let scanned: Vec<Key> = store.prefix(&prefix).map(|entry| entry.unwrap().0).collect();
+        assert_eq!(scanned, expected);
+
+        assert_eq!(store.prefix(&[]).count(), keys.len());
+    }
+
+    #[test]
+    fn test_composite_key_scans() {
+        let path = temp_store("test_iter_composite");
+        let mut store = Store::new(&path).unwrap();
+
+        let mut tx = store.begin().unwrap();
+        for user in ["al", "alice", "bob"] {
+            for day in -3i32..3 {
+                tx.put(Key::from_parts(&(user, day)), Value::new(day)).unwrap();
+            }
+        }
+        tx.commit().unwrap();
+
+        // Everything for alice, and nothing for al even though it is a
+        // byte prefix of alice
+        let scanned: Vec<(String, i32)> = store.prefix(Key::from_parts(&("alice",)).as_bytes())
+            .map(|entry| entry.unwrap().0.parts().unwrap())
+            .collect();
+        assert_eq!(scanned, (-3..3).map(|day| (String::from("alice"), day)).collect::<Vec<_>>());
+
+        let scanned: Vec<i32> = store.range(Key::from_parts(&("bob", -1i32))..Key::from_parts(&("bob", 2i32)))
+            .map(|entry| entry.unwrap().0.parts::<(String, i32)>().unwrap().1)
+            .collect();
+        assert_eq!(scanned, vec![-1, 0, 1]);
+    }
+
+    #[test]
+    fn test_created_between() {
+        let path = temp_store("test_iter_created_between");
+        let mut store = Store::new(&path).unwrap();
+        let pause = || thread::sleep(Duration::from_millis(3));
+
+        let batch = |store: &mut Store| {
+            let keys: Vec<Key> = (0..50).map(|_| Key::new_time_ordered()).collect();
+            let mut tx = store.begin().unwrap();
+            for key in &keys {
+                tx.put(key.clone(), Value::new("event")).unwrap();
+            }
+            tx.commit().unwrap();
+            keys
+        };
+
+        batch(&mut store);
+        pause();
+        let start = SystemTime::now();
+        pause();
+        let middle = batch(&mut store);
+        pause();
+        let end = SystemTime::now();
+        pause();
+        batch(&mut store);
+
+        // Not time ordered, but sorts inside the window's byte range
+        let mut stray = Key::time_ordered_bound(start).into_bytes();
+        stray[15] = 1;
+        store.insert(Key::from(stray), Value::new("stray")).unwrap();
+
+        let scanned: Vec<Key> = store.created_between(start, end).map(|entry| entry.unwrap().0).collect();
+        assert_eq!(scanned, middle);
+
+        assert_eq!(store.created_between(end, start).count(), 0);
+    }
+
+    #[test]
+    fn test_iter_empty_store() {
+        let path = temp_store("test_iter_empty");
+        let store = Store::new(&path).unwrap();
+
+        assert_eq!(store.iter().count(), 0);
+        assert!(store.iter().next_back().is_none());
+    }
+}
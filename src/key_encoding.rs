@@ -0,0 +1,319 @@
+use uuid::Uuid;
+
+use crate::storage_manager::StoreError;
+
+// Byte strings end with 0x00 0x01, a 0x00 inside them is written as
+// 0x00 0xff. The terminator sorts below any escaped or ordinary byte, so a
+// string sorts before every longer string it is a prefix of.
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xff;
+const TERMINATOR: u8 = 0x01;
+
+// Writes a value in a form whose byte order matches the value's order, so
+// encoded keys can be compared with memcmp. Parts carry no type tags, the
+// reader has to know the types it is decoding, the same as with Decode.
+pub trait EncodeKey {
+    fn encode_key(&self, out: &mut Vec<u8>);
+}
+
+pub trait DecodeKey: Sized {
+    fn decode_key(input: &mut &[u8]) -> Result<Self, StoreError>;
+}
+
+pub fn encode_key<T: EncodeKey + ?Sized>(value: &T) -> Vec<u8> {
+    let mut out = vec![];
+    value.encode_key(&mut out);
+    out
+}
+
+// Decodes a whole key, failing if any bytes are left over
+pub fn decode_key<T: DecodeKey>(mut input: &[u8]) -> Result<T, StoreError> {
+    let value = T::decode_key(&mut input)?;
+    if !input.is_empty() {
+        return Err(StoreError::Serialization(format!("{} trailing bytes after key", input.len())));
+    }
+
+    Ok(value)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], StoreError> {
+    if input.len() < len {
+        return Err(StoreError::Serialization(format!("Expected {} key bytes, found {}", len, input.len())));
+    }
+
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+
+    Ok(bytes)
+}
+
+// Unsigned integers are big endian
+macro_rules! unsigned_key {
+    ($($ty:ty),*) => {
+        $(
+            impl EncodeKey for $ty {
+                fn encode_key(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+            }
+
+            impl DecodeKey for $ty {
+                fn decode_key(input: &mut &[u8]) -> Result<Self, StoreError> {
+                    let bytes = take(input, std::mem::size_of::<$ty>())?;
+                    Ok(<$ty>::from_be_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+// Signed integers flip the sign bit so negatives sort below positives
+macro_rules! signed_key {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl EncodeKey for $ty {
+                fn encode_key(&self, out: &mut Vec<u8>) {
+                    let flipped = (*self as $unsigned) ^ (1 << (<$unsigned>::BITS - 1));
+                    flipped.encode_key(out);
+                }
+            }
+
+            impl DecodeKey for $ty {
+                fn decode_key(input: &mut &[u8]) -> Result<Self, StoreError> {
+                    let flipped = <$unsigned>::decode_key(input)?;
+                    Ok((flipped ^ (1 << (<$unsigned>::BITS - 1))) as $ty)
+                }
+            }
+        )*
+    };
+}
+
+// Floats flip the sign bit of positives and every bit of negatives, which
+// orders them numerically with -0.0 just below 0.0 and NaNs at either end
+macro_rules! float_key {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl EncodeKey for $ty {
+                fn encode_key(&self, out: &mut Vec<u8>) {
+                    let bits = self.to_bits();
+                    let sign = 1 << (<$unsigned>::BITS - 1);
+                    let flipped = if bits & sign == 0 { bits ^ sign } else { !bits };
+                    flipped.encode_key(out);
+                }
+            }
+
+            impl DecodeKey for $ty {
+                fn decode_key(input: &mut &[u8]) -> Result<Self, StoreError> {
+                    let flipped = <$unsigned>::decode_key(input)?;
+                    let sign = 1 << (<$unsigned>::BITS - 1);
+                    let bits = if flipped & sign != 0 { flipped ^ sign } else { !flipped };
+                    Ok(<$ty>::from_bits(bits))
+                }
+            }
+        )*
+    };
+}
+
+unsigned_key!(u8, u16, u32, u64, u128);
+signed_key!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+float_key!(f32 => u32, f64 => u64);
+
+impl EncodeKey for bool {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl DecodeKey for bool {
+    fn decode_key(input: &mut &[u8]) -> Result<Self, StoreError> {
+        match u8::decode_key(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(StoreError::Serialization(format!("Invalid bool key byte {}", byte))),
+        }
+    }
+}
+
+impl EncodeKey for [u8] {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        for byte in self {
+            out.push(*byte);
+            if *byte == ESCAPE {
+                out.push(ESCAPED_ZERO);
+            }
+        }
+
+        out.push(ESCAPE);
+        out.push(TERMINATOR);
+    }
+}
+
+impl EncodeKey for Vec<u8> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode_key(out);
+    }
+}
+
+impl DecodeKey for Vec<u8> {
+    fn decode_key(input: &mut &[u8]) -> Result<Self, StoreError> {
+        let mut bytes = vec![];
+
+        loop {
+            let byte = take(input, 1)?[0];
+            if byte != ESCAPE {
+                bytes.push(byte);
+                continue;
+            }
+
+            match take(input, 1)?[0] {
+                ESCAPED_ZERO => bytes.push(ESCAPE),
+                TERMINATOR => return Ok(bytes),
+                other => return Err(StoreError::Serialization(format!("Invalid escape byte {} in key", other))),
+            }
+        }
+    }
+}
+
+impl EncodeKey for str {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_key(out);
+    }
+}
+
+impl EncodeKey for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_str().encode_key(out);
+    }
+}
+
+impl DecodeKey for String {
+    fn decode_key(input: &mut &[u8]) -> Result<Self, StoreError> {
+        String::from_utf8(Vec::<u8>::decode_key(input)?).map_err(|e| StoreError::Serialization(e.to_string()))
+    }
+}
+
+// Uuids are fixed size and compare as their bytes already
+impl EncodeKey for Uuid {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl DecodeKey for Uuid {
+    fn decode_key(input: &mut &[u8]) -> Result<Self, StoreError> {
+        Ok(Uuid::from_slice(take(input, 16)?).unwrap())
+    }
+}
+
+impl<T: EncodeKey + ?Sized> EncodeKey for &T {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        (**self).encode_key(out);
+    }
+}
+
+// Tuples are their parts one after another, so they sort by the first
+// part, then the second, and so on. An encoded leading subset of a tuple
+// is a byte prefix of the full encoding, usable with Store::prefix.
+macro_rules! tuple_key {
+    ($($name:ident),+) => {
+        impl<$($name: EncodeKey),+> EncodeKey for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_key(out);)+
+            }
+        }
+
+        impl<$($name: DecodeKey),+> DecodeKey for ($($name,)+) {
+            fn decode_key(input: &mut &[u8]) -> Result<Self, StoreError> {
+                Ok(($($name::decode_key(input)?,)+))
+            }
+        }
+    };
+}
+
+tuple_key!(A);
+tuple_key!(A, B);
+tuple_key!(A, B, C);
+tuple_key!(A, B, C, D);
+tuple_key!(A, B, C, D, E);
+tuple_key!(A, B, C, D, E, F);
+tuple_key!(A, B, C, D, E, F, G);
+tuple_key!(A, B, C, D, E, F, G, H);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Debug;
+
+    // Checks encoding keeps the order of values already sorted ascending
+    fn assert_order_preserved<T: EncodeKey + DecodeKey + Debug + PartialEq>(sorted: &[T]) {
+        let encoded: Vec<Vec<u8>> = sorted.iter().map(|value| encode_key(value)).collect();
+
+        for pair in encoded.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} !< {:?}", pair[0], pair[1]);
+        }
+        for (value, bytes) in sorted.iter().zip(&encoded) {
+            assert_eq!(&decode_key::<T>(bytes).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_integers_sort_numerically() {
+        assert_order_preserved(&[0u8, 1, 127, 128, 255]);
+        assert_order_preserved(&[0u64, 1, 255, 256, 65_536, u64::MAX]);
+        assert_order_preserved(&[i8::MIN, -1, 0, 1, i8::MAX]);
+        assert_order_preserved(&[i64::MIN, -65_536, -256, -1, 0, 1, 256, i64::MAX]);
+        assert_order_preserved(&[i128::MIN, -1, 0, i128::MAX]);
+    }
+
+    #[test]
+    fn test_floats_sort_numerically() {
+        assert_order_preserved(&[f64::NEG_INFINITY, -1e300, -2.5, -1.0, -1e-300, -0.0, 0.0, 1e-300, 1.0, 2.5, 1e300, f64::INFINITY]);
+        assert_order_preserved(&[-3.5f32, -0.5, 0.0, 0.25, 7.0]);
+    }
+
+    #[test]
+    fn test_strings_sort_lexicographically() {
+        let strings: Vec<String> = ["", "a", "a\0", "a\0b", "aa", "ab", "b", "\u{e9}"].iter().map(|s| s.to_string()).collect();
+        assert_order_preserved(&strings);
+
+        assert_order_preserved(&[vec![], vec![0u8], vec![0, 0], vec![0, 1], vec![1], vec![0xff]]);
+    }
+
+    #[test]
+    fn test_tuples_sort_by_parts() {
+        let tenant = Uuid::from_u128(5);
+        let mut tuples = vec![
+            (tenant, -10i64, String::from("b")),
+            (tenant, -10, String::from("a")),
+            (tenant, 3, String::from("")),
+            (Uuid::from_u128(4), 100, String::from("z")),
+            (tenant, -10, String::from("a\0")),
+            (Uuid::from_u128(6), i64::MIN, String::from("")),
+        ];
+        tuples.sort();
+        assert_order_preserved(&tuples);
+
+        // Strings inside tuples still sort shorter first
+        assert_order_preserved(&[(String::from("a"), 9u8), (String::from("ab"), 0u8)]);
+    }
+
+    #[test]
+    fn test_leading_parts_are_a_prefix() {
+        let full = encode_key(&(7u32, String::from("user"), 3i16));
+        let partial = encode_key(&(7u32, String::from("user")));
+
+        assert!(full.starts_with(&partial));
+        assert!(!encode_key(&(7u32, String::from("username"), 3i16)).starts_with(&partial));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(decode_key::<u32>(&[1, 2]).is_err());
+        assert!(decode_key::<String>(b"abc").is_err());
+        assert!(decode_key::<Vec<u8>>(&[0, 7]).is_err());
+        assert!(decode_key::<u8>(&[1, 2]).is_err());
+        assert!(decode_key::<bool>(&[3]).is_err());
+    }
+}
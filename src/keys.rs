@@ -1,39 +1,209 @@
 use std::hash::Hash;
-use std::fmt::{Debug, Formatter};
+use std::fmt::{Debug, Display, Formatter};
 use std::fmt::Result;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+use crate::key_encoding::{decode_key, encode_key, DecodeKey, EncodeKey};
+use crate::storage_manager::StoreError;
+
+// Longest key the store accepts, so index entries always fit a page
+pub const MAX_KEY_SIZE: usize = 512;
+
+// Keys are byte strings ordered byte by byte, so they sort the same way
+// the index does. Uuid keys are their 16 bytes, integers are big endian
+// so numeric order and byte order agree.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Key {
-    id: Uuid
-} 
+    bytes: Vec<u8>
+}
 
 impl Key {
     pub fn new() -> Self {
-        Self {
-            id: Uuid::new_v4()
-        } 
+        Self::from_uuid(Uuid::new_v4())
+    }
+
+    // UUIDv7 keys start with their creation time in milliseconds, so keys
+    // made one after another sort one after another and inserts land at
+    // the right edge of the index instead of all over it
+    pub fn new_time_ordered() -> Self {
+        Self::from_uuid(Uuid::now_v7())
+    }
+
+    // Lowest time ordered key that could have been made at time, for
+    // scanning a window of them with Store::range
+    pub fn time_ordered_bound(time: SystemTime) -> Self {
+        let millis = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+
+        let mut bytes = millis.to_be_bytes()[2..].to_vec();
+        bytes.resize(16, 0);
+        Self { bytes }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self { bytes: bytes.to_vec() }
+    }
+
+    pub fn from_uuid(id: Uuid) -> Self {
+        Self { bytes: id.as_bytes().to_vec() }
+    }
+
+    pub fn from_u64(n: u64) -> Self {
+        Self { bytes: n.to_be_bytes().to_vec() }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
     }
 
+    // Any 16 byte key reads back as a Uuid
+    pub fn uuid(&self) -> Option<Uuid> {
+        Uuid::from_slice(&self.bytes).ok()
+    }
+
+    // From when every key was a Uuid. Panics on keys of any other length.
+    #[deprecated(note = "keys need not be uuids, use Key::uuid")]
     pub fn get_id(&self) -> &Uuid {
-        return &self.id
+        let bytes = self.bytes.as_slice().try_into().expect("Key is not a uuid");
+        Uuid::from_bytes_ref(bytes)
+    }
+
+    // Creation time of a time ordered key, to the millisecond
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        let id = self.uuid()?;
+        if id.get_version_num() != 7 {
+            return None;
+        }
+
+        let mut millis = [0u8; 8];
+        millis[2..].copy_from_slice(&id.as_bytes()[..6]);
+        Some(UNIX_EPOCH + Duration::from_millis(u64::from_be_bytes(millis)))
     }
 
     pub fn set_id(&mut self, id: Uuid) {
-        self.id = id;
-    } 
+        self.bytes = id.as_bytes().to_vec();
+    }
+
+    // Any 8 byte key reads back as the u64 it was made from
+    pub fn to_u64(&self) -> Option<u64> {
+        let bytes: [u8; 8] = self.bytes.as_slice().try_into().ok()?;
+        Some(u64::from_be_bytes(bytes))
+    }
+
+    // Composite keys, see key_encoding. Scanning with the prefix of some
+    // leading parts returns every key that starts with those parts.
+    pub fn from_parts<T: EncodeKey + ?Sized>(parts: &T) -> Self {
+        Self { bytes: encode_key(parts) }
+    }
+
+    pub fn parts<T: DecodeKey>(&self) -> std::result::Result<T, StoreError> {
+        decode_key(&self.bytes)
+    }
 }
 
-impl PartialEq for Key {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
-    } 
-} 
+impl Default for Key {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<&str> for Key {
+    fn from(key: &str) -> Self {
+        Self::from_bytes(key.as_bytes())
+    }
+}
+
+impl From<String> for Key {
+    fn from(key: String) -> Self {
+        Self { bytes: key.into_bytes() }
+    }
+}
+
+impl From<&[u8]> for Key {
+    fn from(key: &[u8]) -> Self {
+        Self::from_bytes(key)
+    }
+}
+
+impl From<Vec<u8>> for Key {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+}
+
+impl From<u64> for Key {
+    fn from(n: u64) -> Self {
+        Self::from_u64(n)
+    }
+}
+
+impl From<Uuid> for Key {
+    fn from(id: Uuid) -> Self {
+        Self::from_uuid(id)
+    }
+}
+
+// Printable text shows as itself, anything else as 0x followed by hex.
+// Text that itself starts with 0x is shown as hex too, so FromStr can
+// always read back what Display wrote.
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match std::str::from_utf8(&self.bytes) {
+            Ok(text) if !text.is_empty() && !text.starts_with("0x") && !text.chars().any(char::is_control) => write!(f, "{}", text),
+            _ => {
+                write!(f, "0x")?;
+                for byte in &self.bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "Key({})", self)
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
 
-impl Eq for Key {}
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Reverses Display: 0x followed by hex digits is raw bytes, anything else
+// is taken as text
+impl FromStr for Key {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let bytes = s.strip_prefix("0x").and_then(parse_hex);
 
-impl Hash for Key {
-    fn hash<H:  std::hash::Hasher>(&self, state: &mut H) {
-        self.id.hash(state);
+        Ok(match bytes {
+            Some(bytes) => Self { bytes },
+            None => Self::from(s),
+        })
     }
 }
 
@@ -66,3 +236,86 @@ impl<T: Debug> Debug for Value<T> {
         write!(f, "{:?}", self.data)
     } 
 } 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_constructors() {
+        assert_eq!(Key::from("user:42").as_bytes(), b"user:42");
+        assert_eq!(Key::from(String::from("abc")), Key::from("abc"));
+        assert_eq!(Key::from(&b"\x00\x01"[..]).as_bytes(), &[0, 1]);
+        assert_eq!(Key::from(vec![9u8]).len(), 1);
+
+        let id = Uuid::new_v4();
+        assert_eq!(Key::from(id).uuid(), Some(id));
+        assert_eq!(Key::from("text").uuid(), None);
+        #[allow(deprecated)]
+        let old = *Key::from(id).get_id();
+        assert_eq!(old, id);
+
+        assert_eq!(Key::from(42u64).to_u64(), Some(42));
+        assert_eq!(Key::new().len(), 16);
+        assert_ne!(Key::new(), Key::new());
+    }
+
+    #[test]
+    fn test_key_ordering() {
+        assert!(Key::from("a") < Key::from("b"));
+        assert!(Key::from("a") < Key::from("ab"));
+        assert!(Key::from("") < Key::from("a"));
+        assert!(Key::from(2u64) < Key::from(10u64));
+        assert!(Key::from(255u64) < Key::from(256u64));
+    }
+
+    #[test]
+    fn test_time_ordered_keys() {
+        let before = SystemTime::now() - Duration::from_millis(1);
+        let keys: Vec<Key> = (0..1000).map(|_| Key::new_time_ordered()).collect();
+        let after = SystemTime::now();
+
+        for pair in keys.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+
+        let created = keys[0].timestamp().unwrap();
+        assert!(before <= created && created <= after);
+        assert!(Key::time_ordered_bound(before) <= keys[0]);
+        assert!(Key::time_ordered_bound(after + Duration::from_millis(1)) > keys[999]);
+
+        assert_eq!(Key::new().timestamp(), None);
+        assert_eq!(Key::from("text").timestamp(), None);
+    }
+
+    #[test]
+    fn test_composite_keys() {
+        let key = Key::from_parts(&("orders", -3i32, 1.5f64));
+        assert_eq!(key.parts::<(String, i32, f64)>().unwrap(), (String::from("orders"), -3, 1.5));
+        assert!(key.parts::<(String, i32)>().is_err());
+
+        assert!(Key::from_parts(&("orders", -3i32)) < Key::from_parts(&("orders", 2i32)));
+        assert!(Key::from_parts(&("order", 9i32)) < Key::from_parts(&("orders", -9i32)));
+    }
+
+    #[test]
+    fn test_display_and_parse_round_trip() {
+        let keys = vec![
+            Key::from("user:42"),
+            Key::from(""),
+            Key::from("0x12"),
+            Key::from("tab\there"),
+            Key::from(&[0xff, 0x00, 0x10][..]),
+            Key::from(7u64),
+            Key::new(),
+        ];
+
+        for key in keys {
+            assert_eq!(key.to_string().parse::<Key>().unwrap(), key);
+        }
+
+        assert_eq!(Key::from("user:42").to_string(), "user:42");
+        assert_eq!(Key::from(&[0xab, 0x01][..]).to_string(), "0xab01");
+        assert_eq!("0xzz".parse::<Key>().unwrap(), Key::from("0xzz"));
+    }
+}
@@ -1,8 +1,29 @@
 pub mod keys;
+pub mod key_encoding;
 pub mod values;
 pub mod storage_manager;
 pub mod mocks_structs;
 pub mod page;
+pub mod superblock;
+pub mod buffer_pool;
+pub mod wal;
+pub mod file_io;
+pub mod transaction;
+pub mod btree;
+pub mod hash_index;
+pub mod iter;
+pub mod blob;
+pub mod codec;
+pub mod sweeper;
+pub mod snapshot;
+pub mod bloom;
+pub mod sorted_run;
+pub mod lsm;
+pub mod bitcask;
+pub mod engine;
+pub mod db;
 
+pub use db::Db;
+pub use engine::{Engine, EngineKind};
 pub use keys::Key;
 
@@ -0,0 +1,718 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::bloom::{is_valid_rate, BloomStats};
+use crate::codec::{Decode, Encode};
+use crate::engine::is_empty_range;
+use crate::keys::{Key, MAX_KEY_SIZE};
+use crate::sorted_run::{decode_entry, encode_entry, Run, RunEntry, RunWriter};
+use crate::storage_manager::StoreError;
+use crate::values::Value;
+use crate::wal::Wal;
+
+type KeyValue = (Key, Value<Vec<u8>>);
+
+type EntrySource<'a> = Box<dyn Iterator<Item = Result<RunEntry, io::Error>> + 'a>;
+
+// Bytes each memtable entry costs on top of its key and value
+const ENTRY_OVERHEAD: usize = 32;
+
+pub struct LsmOptions {
+    // Memtable bytes that trigger writing it out as a new run
+    pub memtable_size: usize,
+    // Level 0 runs that trigger merging them into level 1
+    pub level0_runs: usize,
+    // Size of level 1 before it is merged into level 2, each level below
+    // holds level_fanout times as much as the one above
+    pub level_size: u64,
+    pub level_fanout: u64,
+    // False positive rate of the bloom filter written with each run,
+    // which lets lookups skip runs that do not hold the key. 0 writes
+    // runs without filters, other rates have to lie between 0 and 1.
+    pub bloom_false_positive_rate: f64,
+}
+
+impl Default for LsmOptions {
+    fn default() -> Self {
+        LsmOptions {
+            memtable_size: 4 << 20,
+            level0_runs: 4,
+            level_size: 40 << 20,
+            level_fanout: 10,
+            bloom_false_positive_rate: 0.01,
+        }
+    }
+}
+
+// Log-structured merge engine, for write heavy workloads. Writes go to a
+// write-ahead log and an in-memory memtable. A full memtable is written
+// out as an immutable sorted run in level 0, and runs are merged down
+// into larger levels as those fill up, leveled style: level 0 holds runs
+// whose keys overlap, every level below it is a single run. Reads check
+// the memtable, then the runs from newest to oldest.
+//
+// The store is a directory holding the log and one file per run, named
+// after its level and a sequence number.
+pub struct LsmStore {
+    dir: PathBuf,
+    options: LsmOptions,
+    wal: Wal,
+    memtable: BTreeMap<Key, Option<Vec<u8>>>,
+    memtable_size: usize,
+    // Level 0 oldest run first, then one run or none for each other level
+    levels: Vec<Vec<Run>>,
+    next_run: u64,
+    bloom_stats: Mutex<BloomStats>,
+}
+
+// Splits a run file name like "1-00000042.run" into level and sequence
+fn parse_run_name(name: &str) -> Option<(usize, u64)> {
+    let (level, sequence) = name.strip_suffix(".run")?.split_once('-')?;
+    Some((level.parse().ok()?, sequence.parse().ok()?))
+}
+
+fn run_sequence(run: &Run) -> u64 {
+    run.path().file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_run_name)
+        .map_or(0, |(_, sequence)| sequence)
+}
+
+impl LsmStore {
+    pub fn new(path: &Path) -> Result<LsmStore, io::Error> {
+        Self::open(path, LsmOptions::default())
+    }
+
+    pub fn open(path: &Path, options: LsmOptions) -> Result<LsmStore, io::Error> {
+        let rate = options.bloom_false_positive_rate;
+        if rate != 0.0 && !is_valid_rate(rate) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Bloom filter false positive rate {} is not at least 0 and below 1", rate)));
+        }
+
+        fs::create_dir_all(path)?;
+
+        let mut runs = vec![];
+        for dir_entry in fs::read_dir(path)? {
+            let file_path = dir_entry?.path();
+            let name = file_path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+
+            // Runs that were still being written when the process died
+            if name.ends_with(".run.tmp") {
+                fs::remove_file(&file_path)?;
+            } else if let Some((level, sequence)) = parse_run_name(name) {
+                runs.push((level, sequence, file_path));
+            }
+        }
+        runs.sort();
+
+        let wal = Wal::open(&path.join("wal"))?;
+        let mut store = LsmStore {
+            dir: path.to_path_buf(),
+            options,
+            wal,
+            memtable: BTreeMap::new(),
+            memtable_size: 0,
+            levels: vec![vec![]],
+            next_run: runs.iter().map(|(_, sequence, _)| sequence + 1).max().unwrap_or(0),
+            bloom_stats: Mutex::new(BloomStats::default()),
+        };
+
+        for (level, _, file_path) in runs {
+            let run = Run::open(&file_path)?;
+            store.level_mut(level).push(run);
+        }
+        store.remove_merged_runs()?;
+
+        // Writes that hadn't made it into a run yet. They are written out
+        // as a run of their own and the log starts over, as new records
+        // appended after a torn or uncommitted tail would be lost with it
+        // or committed along with it.
+        for entry in store.wal.recover_entries()? {
+            let (key, value) = decode_entry(&entry)?;
+            store.memtable_insert(key, value);
+        }
+        if store.memtable.is_empty() {
+            store.wal.truncate()?;
+        } else {
+            store.flush()?;
+        }
+
+        Ok(store)
+    }
+
+    // A merge cut short leaves its result next to the runs it replaced.
+    // Runs only ever move down, so each level's runs are newer than those
+    // below it, and any run older than the newest run of the level below
+    // went into that run.
+    fn remove_merged_runs(&mut self) -> Result<(), io::Error> {
+        let mut replaced = vec![];
+        for level in 1..self.levels.len() {
+            let newest = match self.levels[level].last() {
+                Some(run) => run_sequence(run),
+                None => continue,
+            };
+
+            let kept = self.levels[level].len() - 1;
+            replaced.extend(self.levels[level].drain(..kept));
+
+            let (merged, above): (Vec<Run>, Vec<Run>) = std::mem::take(&mut self.levels[level - 1]).into_iter()
+                .partition(|run| run_sequence(run) < newest);
+            self.levels[level - 1] = above;
+            replaced.extend(merged);
+        }
+
+        for run in replaced {
+            fs::remove_file(run.path())?;
+        }
+
+        Ok(())
+    }
+
+    fn level_mut(&mut self, level: usize) -> &mut Vec<Run> {
+        if self.levels.len() <= level {
+            self.levels.resize_with(level + 1, Vec::new);
+        }
+
+        &mut self.levels[level]
+    }
+
+    pub fn get(&self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        if let Some(value) = self.memtable.get(key) {
+            return Ok(value.clone().map(Value::new));
+        }
+
+        // Newest runs first, the first one that knows the key wins. Runs
+        // whose filter rules the key out are not read at all.
+        for run in self.levels.iter().flat_map(|runs| runs.iter().rev()) {
+            if !run.may_contain(key) {
+                self.stats().skips += 1;
+                continue;
+            }
+
+            // Only a filter that let the lookup through can be wrong
+            let found = run.get(key)?;
+            match found {
+                Some(_) if run.has_filter() => self.stats().hits += 1,
+                None if run.has_filter() => self.stats().false_positives += 1,
+                _ => {}
+            }
+            if let Some(value) = found {
+                return Ok(value.map(Value::new));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn contains(&self, key: &Key) -> Result<bool, io::Error> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    // Decodes the stored bytes as T
+    pub fn get_as<T: Decode>(&self, key: &Key) -> Result<Option<Value<T>>, io::Error> {
+        match self.get(key)? {
+            Some(value) => Ok(Some(Value::new(T::decode_value(value.get())?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert<T: Encode>(&mut self, key: Key, value: Value<T>) -> Result<(), io::Error> {
+        self.write(key, Some(value.get().encode_value()))
+    }
+
+    pub fn delete(&mut self, key: &Key) -> Result<bool, io::Error> {
+        if !self.contains(key)? {
+            return Ok(false);
+        }
+
+        self.write(key.clone(), None)?;
+        Ok(true)
+    }
+
+    // Every live key/value pair from lower to upper in key order
+    pub fn range(&self, lower: Bound<Key>, upper: Bound<Key>) -> impl Iterator<Item = Result<KeyValue, io::Error>> + '_ {
+        self.merged(lower, upper).filter_map(|entry| match entry {
+            Ok((key, Some(value))) => Some(Ok((key, Value::new(value)))),
+            Ok((_, None)) => None,
+            Err(err) => Some(Err(err)),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Result<KeyValue, io::Error>> + '_ {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    // Writes the memtable out as a run, so the log can start over
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+
+        let mut writer = RunWriter::create(&self.run_path(0), self.options.bloom_false_positive_rate)?;
+        for (key, value) in &self.memtable {
+            writer.add(key, value.as_deref())?;
+        }
+        let run = writer.finish()?;
+
+        self.levels[0].push(run);
+        self.memtable.clear();
+        self.memtable_size = 0;
+        self.wal.truncate()?;
+
+        self.compact()
+    }
+
+    fn stats(&self) -> MutexGuard<'_, BloomStats> {
+        self.bloom_stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // How often bloom filters let lookups skip a run
+    pub fn bloom_stats(&self) -> BloomStats {
+        *self.stats()
+    }
+
+    // Runs in each level, level 0 first
+    pub fn level_runs(&self) -> Vec<usize> {
+        self.levels.iter().map(Vec::len).collect()
+    }
+
+    // Entries held in runs, deletions included, counting every version
+    pub fn run_entries(&self) -> u64 {
+        self.levels.iter().flatten().map(Run::entries).sum()
+    }
+
+    fn write(&mut self, key: Key, value: Option<Vec<u8>>) -> Result<(), io::Error> {
+        if key.len() > MAX_KEY_SIZE {
+            return Err(StoreError::Serialization(format!("Key longer than {} bytes", MAX_KEY_SIZE)).into());
+        }
+
+        self.wal.append_entry(&encode_entry(&key, value.as_deref()))?;
+        self.wal.append_commit()?;
+        self.wal.sync()?;
+
+        self.memtable_insert(key, value);
+        if self.memtable_size >= self.options.memtable_size {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn memtable_insert(&mut self, key: Key, value: Option<Vec<u8>>) {
+        self.memtable_size += key.len() + value.as_ref().map_or(0, Vec::len) + ENTRY_OVERHEAD;
+        self.memtable.insert(key, value);
+    }
+
+    fn run_path(&mut self, level: usize) -> PathBuf {
+        let sequence = self.next_run;
+        self.next_run += 1;
+
+        self.dir.join(format!("{}-{:08}.run", level, sequence))
+    }
+
+    // Entries of the memtable and every run, newest first, merged into key
+    // order with only the newest entry for each key kept
+    fn merged(&self, lower: Bound<Key>, upper: Bound<Key>) -> MergeIter<'_> {
+        if is_empty_range(&lower, &upper) {
+            return MergeIter::new(vec![]);
+        }
+
+        let mut sources: Vec<EntrySource<'_>> = vec![];
+        sources.push(Box::new(
+            self.memtable.range((lower.clone(), upper.clone())).map(|(key, value)| Ok((key.clone(), value.clone())))
+        ));
+        for run in self.levels.iter().flat_map(|runs| runs.iter().rev()) {
+            sources.push(Box::new(run.range(lower.clone(), upper.clone())));
+        }
+
+        MergeIter::new(sources)
+    }
+
+    fn max_level_size(&self, level: usize) -> u64 {
+        self.options.level_size.saturating_mul(self.options.level_fanout.saturating_pow(level as u32 - 1))
+    }
+
+    fn compact(&mut self) -> Result<(), io::Error> {
+        if self.levels[0].len() >= self.options.level0_runs {
+            self.merge_down(0)?;
+        }
+
+        let mut level = 1;
+        while level < self.levels.len() {
+            let size: u64 = self.levels[level].iter().map(Run::size).sum();
+            if size > self.max_level_size(level) {
+                self.merge_down(level)?;
+            }
+            level += 1;
+        }
+
+        Ok(())
+    }
+
+    // Merges every run of level into the run of the level below it
+    fn merge_down(&mut self, level: usize) -> Result<(), io::Error> {
+        let target = level + 1;
+        let merged = self.write_merged(level)?;
+
+        // The merged run is durable, the runs it replaces can go
+        let replaced: Vec<Run> = std::mem::take(&mut self.levels[level]).into_iter()
+            .chain(std::mem::replace(&mut self.levels[target], vec![merged]))
+            .collect();
+        for run in replaced {
+            fs::remove_file(run.path())?;
+        }
+
+        Ok(())
+    }
+
+    // Writes the run merge_down replaces level and the level below with
+    fn write_merged(&mut self, level: usize) -> Result<Run, io::Error> {
+        let target = level + 1;
+        self.level_mut(target);
+
+        // Nothing is older than the bottom level, so deletions can go
+        let bottom = self.levels[target + 1..].iter().all(Vec::is_empty);
+        let path = self.run_path(target);
+
+        let sources: Vec<EntrySource<'_>> = self.levels[level].iter().rev()
+            .chain(self.levels[target].iter().rev())
+            .map(|run| Box::new(run.range(Bound::Unbounded, Bound::Unbounded)) as EntrySource<'_>)
+            .collect();
+
+        let mut writer = RunWriter::create(&path, self.options.bloom_false_positive_rate)?;
+        for entry in MergeIter::new(sources) {
+            let (key, value) = entry?;
+            if value.is_some() || !bottom {
+                writer.add(&key, value.as_deref())?;
+            }
+        }
+
+        writer.finish()
+    }
+}
+
+// Merges sources that are each in key order, which come newest first.
+// For a key found in several sources only the newest entry is returned.
+struct MergeIter<'a> {
+    sources: Vec<EntrySource<'a>>,
+    heads: Vec<Option<RunEntry>>,
+    started: bool,
+}
+
+impl<'a> MergeIter<'a> {
+    fn new(sources: Vec<EntrySource<'a>>) -> Self {
+        let heads = sources.iter().map(|_| None).collect();
+        MergeIter { sources, heads, started: false }
+    }
+
+    fn advance(&mut self, source: usize) -> Result<(), io::Error> {
+        self.heads[source] = self.sources[source].next().transpose()?;
+        Ok(())
+    }
+}
+
+impl Iterator for MergeIter<'_> {
+    type Item = Result<RunEntry, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            for source in 0..self.sources.len() {
+                if let Err(err) = self.advance(source) {
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        // min_by keeps the first of equal keys, which is the newest
+        let newest = (0..self.heads.len())
+            .filter(|&source| self.heads[source].is_some())
+            .min_by(|&a, &b| self.heads[a].as_ref().unwrap().0.cmp(&self.heads[b].as_ref().unwrap().0))?;
+        let entry = self.heads[newest].take()?;
+
+        // Older entries for the same key are shadowed
+        for source in 0..self.heads.len() {
+            let shadowed = source == newest || self.heads[source].as_ref().is_some_and(|(key, _)| key == &entry.0);
+            if shadowed {
+                if let Err(err) = self.advance(source) {
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        Some(Ok(entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    // Small enough that a few hundred writes go through every level
+    fn small_options() -> LsmOptions {
+        LsmOptions {
+            memtable_size: 16 << 10,
+            level0_runs: 3,
+            level_size: 64 << 10,
+            level_fanout: 2,
+            ..Default::default()
+        }
+    }
+
+    fn value_for(i: u64, round: u64) -> Vec<u8> {
+        let len = if i.is_multiple_of(97) { 6000 } else { 10 + (i * 7 + round) as usize % 90 };
+        vec![(i + round) as u8; len]
+    }
+
+    // Applies the same writes to the store and to a map, pseudo randomly
+    fn churn(store: &mut LsmStore, model: &mut BTreeMap<Key, Vec<u8>>, rounds: u64) {
+        let mut seed = 17u64;
+        for round in 0..rounds {
+            for _ in 0..400 {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let i = (seed >> 33) % 500;
+                let key = Key::from(i);
+
+                if (seed >> 20).is_multiple_of(5) {
+                    assert_eq!(store.delete(&key).unwrap(), model.remove(&key).is_some());
+                } else {
+                    store.insert(key.clone(), Value::new(value_for(i, round))).unwrap();
+                    model.insert(key, value_for(i, round));
+                }
+            }
+        }
+    }
+
+    fn assert_matches(store: &LsmStore, model: &BTreeMap<Key, Vec<u8>>) {
+        for i in 0..500u64 {
+            let key = Key::from(i);
+            assert_eq!(store.get(&key).unwrap().map(|value| value.get().clone()).as_ref(), model.get(&key));
+        }
+
+        let scanned: Vec<(Key, Vec<u8>)> = store.iter().map(|entry| {
+            let (key, value) = entry.unwrap();
+            (key, value.get().clone())
+        }).collect();
+        assert_eq!(scanned, model.iter().map(|(key, value)| (key.clone(), value.clone())).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_reads_merge_memtable_and_runs() {
+        let path = temp_dir("test_lsm_reads");
+        let mut store = LsmStore::open(&path, small_options()).unwrap();
+        let mut model = BTreeMap::new();
+
+        churn(&mut store, &mut model, 6);
+
+        // Writes went through flushes and compactions into deeper levels
+        assert!(store.level_runs().len() > 2);
+        assert!(store.level_runs()[0] < 3);
+        assert_matches(&store, &model);
+
+        let lower = Key::from(100u64);
+        let upper = Key::from(200u64);
+        let scanned: Vec<Key> = store.range(Bound::Included(lower.clone()), Bound::Excluded(upper.clone()))
+            .map(|entry| entry.unwrap().0)
+            .collect();
+        let expected: Vec<Key> = model.range(lower.clone()..upper.clone()).map(|(key, _)| key.clone()).collect();
+        assert_eq!(scanned, expected);
+
+        assert_eq!(store.range(Bound::Excluded(lower.clone()), Bound::Excluded(lower)).count(), 0);
+    }
+
+    #[test]
+    fn test_reopen_recovers_runs_and_log() {
+        let path = temp_dir("test_lsm_reopen");
+        let mut model = BTreeMap::new();
+
+        {
+            let mut store = LsmStore::open(&path, small_options()).unwrap();
+            churn(&mut store, &mut model, 3);
+
+            // Left in the log only
+            store.insert(Key::from("unflushed"), Value::new(b"still here".to_vec())).unwrap();
+            model.insert(Key::from("unflushed"), b"still here".to_vec());
+            assert!(!store.memtable.is_empty());
+        }
+
+        // A run that never finished writing is thrown away
+        fs::write(path.join("0-99999999.run.tmp"), b"partial").unwrap();
+
+        let mut store = LsmStore::open(&path, small_options()).unwrap();
+        assert!(!path.join("0-99999999.run.tmp").exists());
+        assert_eq!(store.get(&Key::from("unflushed")).unwrap().unwrap().get(), b"still here");
+
+        model.remove(&Key::from("unflushed"));
+        store.delete(&Key::from("unflushed")).unwrap();
+        assert_matches(&store, &model);
+    }
+
+    #[test]
+    fn test_writes_after_recovering_a_damaged_log_survive() {
+        let path = temp_dir("test_lsm_damaged_log");
+
+        {
+            let mut store = LsmStore::open(&path, small_options()).unwrap();
+            store.insert(Key::from("kept"), Value::new("a")).unwrap();
+        }
+
+        // A write that crashed before its commit record, then a torn record
+        {
+            let mut wal = Wal::open(&path.join("wal")).unwrap();
+            wal.append_entry(&encode_entry(&Key::from("ghost"), Some(b"b"))).unwrap();
+            wal.sync().unwrap();
+        }
+        let mut log = OpenOptions::new().append(true).open(path.join("wal")).unwrap();
+        log.write_all(&[7; 10]).unwrap();
+        drop(log);
+
+        {
+            let mut store = LsmStore::open(&path, small_options()).unwrap();
+            assert_eq!(store.get(&Key::from("kept")).unwrap().unwrap().get(), b"a");
+            assert!(store.get(&Key::from("ghost")).unwrap().is_none());
+            store.insert(Key::from("after"), Value::new("c")).unwrap();
+        }
+
+        let store = LsmStore::open(&path, small_options()).unwrap();
+        assert_eq!(store.get(&Key::from("kept")).unwrap().unwrap().get(), b"a");
+        assert_eq!(store.get(&Key::from("after")).unwrap().unwrap().get(), b"c");
+        assert!(store.get(&Key::from("ghost")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_compaction_drops_shadowed_entries() {
+        let path = temp_dir("test_lsm_compaction");
+        let mut store = LsmStore::open(&path, small_options()).unwrap();
+
+        for round in 0..4 {
+            for i in 0..200u64 {
+                store.insert(Key::from(i), Value::new(value_for(i, round))).unwrap();
+            }
+            store.flush().unwrap();
+        }
+        for i in 0..200u64 {
+            store.delete(&Key::from(i)).unwrap();
+        }
+        store.flush().unwrap();
+        for _ in 0..3 {
+            store.insert(Key::from("last"), Value::new(vec![1])).unwrap();
+            store.flush().unwrap();
+        }
+
+        // Once merged to the bottom, overwritten values and deletions are
+        // gone and only the one live key is left
+        assert_eq!(store.iter().count(), 1);
+        assert!(store.run_entries() < 10);
+
+        let files = fs::read_dir(&path).unwrap().count();
+        assert_eq!(files, store.level_runs().iter().sum::<usize>() + 1);
+    }
+
+    #[test]
+    fn test_reopen_removes_runs_of_an_interrupted_merge() {
+        let path = temp_dir("test_lsm_interrupted_merge");
+        let options = || LsmOptions { memtable_size: 1 << 20, level0_runs: 100, ..small_options() };
+        let mut model = BTreeMap::new();
+
+        {
+            let mut store = LsmStore::open(&path, options()).unwrap();
+            for round in 0..3u64 {
+                for i in 0..100u64 {
+                    store.insert(Key::from(i), Value::new(value_for(i, round))).unwrap();
+                    model.insert(Key::from(i), value_for(i, round));
+                }
+                store.flush().unwrap();
+                if round == 0 {
+                    store.merge_down(0).unwrap();
+                }
+            }
+
+            // Crash once the merged run is written, before the runs it
+            // replaces are removed
+            store.write_merged(0).unwrap();
+            assert_eq!(store.level_runs(), vec![2, 1]);
+        }
+
+        let store = LsmStore::open(&path, options()).unwrap();
+        assert_eq!(store.level_runs(), vec![0, 1]);
+        assert_eq!(fs::read_dir(&path).unwrap().count(), 2);
+        assert_matches(&store, &model);
+    }
+
+    #[test]
+    fn test_bloom_filters_skip_runs_without_the_key() {
+        let path = temp_dir("test_lsm_bloom");
+        let options = || LsmOptions { memtable_size: 1 << 20, level0_runs: 100, ..small_options() };
+
+        {
+            let mut store = LsmStore::open(&path, options()).unwrap();
+            for run in 0..5u64 {
+                for i in 0..100u64 {
+                    store.insert(Key::from(run * 1000 + i), Value::new(value_for(i, run))).unwrap();
+                }
+                store.flush().unwrap();
+            }
+            assert_eq!(store.level_runs(), vec![5]);
+        }
+
+        // Filters are read back with the runs
+        let store = LsmStore::open(&path, options()).unwrap();
+        for i in 0..100u64 {
+            assert!(store.get(&Key::from(i)).unwrap().is_some());
+        }
+        let stats = store.bloom_stats();
+        assert_eq!(stats.hits, 100);
+        // Every lookup passed over the four newer runs
+        assert!(stats.skips + stats.false_positives == 400 && stats.false_positives < 20);
+
+        for i in 0..200u64 {
+            assert!(store.get(&Key::from(i + 500)).unwrap().is_none());
+        }
+        let stats = store.bloom_stats();
+        assert!(stats.skips + stats.false_positives == 400 + 5 * 200 && stats.false_positives < 40);
+        drop(store);
+
+        // Without filters every run is read
+        let path = temp_dir("test_lsm_no_bloom");
+        let mut store = LsmStore::open(&path, LsmOptions { bloom_false_positive_rate: 0.0, ..small_options() }).unwrap();
+        store.insert(Key::from(1u64), Value::new("one")).unwrap();
+        store.flush().unwrap();
+
+        assert!(store.get(&Key::from(2u64)).unwrap().is_none());
+        assert!(store.get(&Key::from(1u64)).unwrap().is_some());
+        assert_eq!(store.bloom_stats(), BloomStats::default());
+    }
+
+    #[test]
+    fn test_rejects_bad_false_positive_rates() {
+        let path = temp_dir("test_lsm_bad_rate");
+
+        for rate in [-0.1, 1.0, 2.0, f64::NAN] {
+            let err = LsmStore::open(&path, LsmOptions { bloom_false_positive_rate: rate, ..small_options() }).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_rejects_oversized_keys() {
+        let path = temp_dir("test_lsm_key_size");
+        let mut store = LsmStore::open(&path, small_options()).unwrap();
+
+        let key = Key::from(vec![1u8; MAX_KEY_SIZE + 1]);
+        assert!(store.insert(key, Value::new("value")).is_err());
+    }
+}
@@ -9,6 +9,12 @@ pub struct MockFile {
     pub file: Cursor<Vec<u8>>
 }
 
+impl Default for MockFile {
+    fn default() -> Self {
+        Self::new()
+    }
+} 
+
 impl MockFile {
 
     pub fn new() -> MockFile {
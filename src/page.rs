@@ -4,11 +4,80 @@ const NUM_SLOTS_SIZE: usize = 4;
 const SLOT_CAP_SIZE: usize = 4;
 const FOOTER_HEADER_SIZE: usize = 16;
 
-const METADATA_SIZE: usize = FOOTER_HEADER_SIZE * 2 + NUM_SLOTS_SIZE + SLOT_CAP_SIZE;
+const CHECKSUM_SIZE: usize = 4;
+
+const SLOT_ENTRY_SIZE: usize = 4;
+
+const METADATA_SIZE: usize = FOOTER_HEADER_SIZE * 2 + NUM_SLOTS_SIZE + SLOT_CAP_SIZE + CHECKSUM_SIZE;
 const SLOTS_SIZE: usize = TOTAL_PAGE_SIZE - METADATA_SIZE;
 
+// The checksum sits in the trailer, just before the footer
+const CHECKSUM_OFFSET: usize = TOTAL_PAGE_SIZE - FOOTER_HEADER_SIZE - CHECKSUM_SIZE;
+
 use std::{fmt::Debug, io::{Seek, self, Write}, fs::File};
 
+// First header byte records what a page is used for
+const PAGE_TYPE_OFFSET: usize = 0;
+
+// Last 8 header bytes link a page to the next one in a chain
+const NEXT_PAGE_OFFSET: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageType {
+    Raw,
+    Superblock,
+    PageMap,
+    Data,
+    Free,
+    BTreeLeaf,
+    BTreeInternal,
+    Overflow,
+    RunIndex,
+    RunFooter,
+    HashDirectory,
+    HashBucket,
+    Bloom,
+}
+
+impl PageType {
+    fn to_byte(self) -> u8 {
+        match self {
+            PageType::Raw => 0,
+            PageType::Superblock => 1,
+            PageType::PageMap => 2,
+            PageType::Data => 3,
+            PageType::Free => 4,
+            PageType::BTreeLeaf => 5,
+            PageType::BTreeInternal => 6,
+            PageType::Overflow => 7,
+            PageType::RunIndex => 8,
+            PageType::RunFooter => 9,
+            PageType::HashDirectory => 10,
+            PageType::HashBucket => 11,
+            PageType::Bloom => 12,
+        } 
+    } 
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            1 => PageType::Superblock,
+            2 => PageType::PageMap,
+            3 => PageType::Data,
+            4 => PageType::Free,
+            5 => PageType::BTreeLeaf,
+            6 => PageType::BTreeInternal,
+            7 => PageType::Overflow,
+            8 => PageType::RunIndex,
+            9 => PageType::RunFooter,
+            10 => PageType::HashDirectory,
+            11 => PageType::HashBucket,
+            12 => PageType::Bloom,
+            _ => PageType::Raw,
+        } 
+    } 
+} 
+
+#[derive(Clone)]
 pub struct PageFormat {
     header: [u8; FOOTER_HEADER_SIZE],
     slot_cap: u32,
@@ -39,6 +108,12 @@ impl Debug for PageFormat {
     }
 } 
 
+impl Default for PageFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+} 
+
 impl PageFormat {
     pub fn new() -> Self {
         let header = [0u8; FOOTER_HEADER_SIZE];
@@ -68,12 +143,29 @@ impl PageFormat {
         // Slots is 4096
         data.extend_from_slice(&self.slots);
 
+        // Checksum placeholder, filled in once the footer is written
+        data.extend_from_slice(&[0u8; CHECKSUM_SIZE]);
+
         // Footer is 16 bytes
         data.extend_from_slice(&self.footer);
 
+        let checksum = page_checksum(&data);
+        data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+
         data
     } 
 
+    // Checks the CRC32 stored in serialized page bytes against their contents
+    pub fn verify_checksum(data: &[u8]) -> bool {
+        if data.len() != TOTAL_PAGE_SIZE {
+            return false;
+        } 
+
+        let stored = array_from_vec::<CHECKSUM_SIZE>(data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_SIZE].to_vec());
+
+        u32::from_le_bytes(stored) == page_checksum(data)
+    } 
+
     pub fn deserialize(mut data: Vec<u8>) -> Self {
         // Assumption data vector is 4096 bytes
 
@@ -93,6 +185,9 @@ impl PageFormat {
         let slots = data.drain(..SLOTS_SIZE).collect();
         let slots = array_from_vec::<SLOTS_SIZE>(slots);
 
+        // Skip 4 checksum bytes
+        data.drain(..CHECKSUM_SIZE);
+
         // Reming 16 bytes for footer
         let footer = data.drain(..16).collect();
         let footer = array_from_vec::<16>(footer);
@@ -109,6 +204,22 @@ impl PageFormat {
 
 
 
+    pub fn set_page_type(&mut self, page_type: PageType) {
+        self.header[PAGE_TYPE_OFFSET] = page_type.to_byte();
+    } 
+
+    pub fn get_page_type(&self) -> PageType {
+        PageType::from_byte(self.header[PAGE_TYPE_OFFSET])
+    } 
+
+    pub fn set_next_page(&mut self, next: u64) {
+        self.header[NEXT_PAGE_OFFSET..].copy_from_slice(&next.to_le_bytes());
+    } 
+
+    pub fn get_next_page(&self) -> u64 {
+        u64::from_le_bytes(array_from_vec::<8>(self.header[NEXT_PAGE_OFFSET..].to_vec()))
+    } 
+
     pub fn set_header(&mut self, header: [u8; 16]) {
         self.header = header
     }
@@ -125,9 +236,205 @@ impl PageFormat {
         &self.header 
     } 
 
-    pub fn set_slots(&mut self, values: &[u8]) {
+    // Copies raw bytes over the slots area, slot directory included, so
+    // only tests get to use it. Records go through insert_record and
+    // update_record.
+    #[cfg(test)]
+    fn set_slots(&mut self, values: &[u8]) -> bool {
         let len = values.len().min(self.slots.len());
         self.slots[..len].copy_from_slice(&values[..len]);
+
+        len == values.len()
+    } 
+
+    pub fn get_num_slots(&self) -> u32 {
+        self.num_slots
+    }
+
+    // Slot directory entries grow from the start of the slots area and
+    // record bodies grow down from the end. A zero offset marks a free slot.
+    fn slot_entry(&self, slot: u16) -> (usize, usize) {
+        let pos = slot as usize * SLOT_ENTRY_SIZE;
+        let offset = u16::from_le_bytes([self.slots[pos], self.slots[pos + 1]]) as usize;
+        let len = u16::from_le_bytes([self.slots[pos + 2], self.slots[pos + 3]]) as usize;
+
+        (offset, len)
+    } 
+
+    fn set_slot_entry(&mut self, slot: u16, offset: usize, len: usize) {
+        let pos = slot as usize * SLOT_ENTRY_SIZE;
+        self.slots[pos..pos + 2].copy_from_slice(&(offset as u16).to_le_bytes());
+        self.slots[pos + 2..pos + 4].copy_from_slice(&(len as u16).to_le_bytes());
+    } 
+
+    fn directory_end(&self) -> usize {
+        self.num_slots as usize * SLOT_ENTRY_SIZE
+    } 
+
+    // Lowest offset used by a record body
+    fn records_start(&self) -> usize {
+        (0..self.num_slots as u16)
+            .map(|slot| self.slot_entry(slot))
+            .filter(|(offset, _)| *offset != 0)
+            .map(|(offset, _)| offset)
+            .min()
+            .unwrap_or(SLOTS_SIZE)
+    } 
+
+    fn free_slot(&self) -> Option<u16> {
+        (0..self.num_slots as u16).find(|slot| self.slot_entry(*slot).0 == 0)
+    } 
+
+    // Bytes available for record bodies and new directory entries, counting
+    // space that compaction would reclaim
+    pub fn free_space(&self) -> usize {
+        let used: usize = self.records().iter().map(|(_, record)| record.len()).sum();
+
+        SLOTS_SIZE - self.directory_end() - used
+    } 
+
+    pub fn can_fit(&self, len: usize) -> bool {
+        let needed = match self.free_slot() {
+            Some(_) => len,
+            None => len + SLOT_ENTRY_SIZE,
+        };
+
+        needed <= self.free_space()
+    } 
+
+    pub fn is_full(&self) -> bool {
+        !self.can_fit(1)
+    } 
+
+    pub fn max_record_size() -> usize {
+        SLOTS_SIZE - SLOT_ENTRY_SIZE
+    } 
+
+    // Total bytes available to records and their directory entries
+    pub fn capacity() -> usize {
+        SLOTS_SIZE
+    } 
+
+    // Directory bytes used by each record on top of its body
+    pub fn slot_overhead() -> usize {
+        SLOT_ENTRY_SIZE
+    } 
+
+    pub fn insert_record(&mut self, record: &[u8]) -> Option<u16> {
+        if !self.can_fit(record.len()) {
+            return None;
+        } 
+
+        // A new slot takes directory space, which has to be free before
+        // its entry is written
+        let free_slot = self.free_slot();
+        let directory_growth = if free_slot.is_some() { 0 } else { SLOT_ENTRY_SIZE };
+        self.make_room(record.len() + directory_growth);
+
+        let slot = match free_slot {
+            Some(slot) => slot,
+            None => {
+                self.num_slots += 1;
+                self.set_slot_entry(self.num_slots as u16 - 1, 0, 0);
+                self.num_slots as u16 - 1
+            } 
+        };
+
+        let offset = self.records_start() - record.len();
+        self.slots[offset..offset + record.len()].copy_from_slice(record);
+        self.set_slot_entry(slot, offset, record.len());
+
+        Some(slot)
+    } 
+
+    pub fn read_record(&self, slot: u16) -> Option<&[u8]> {
+        if slot as u32 >= self.num_slots {
+            return None;
+        } 
+
+        match self.slot_entry(slot) {
+            (0, _) => None,
+            (offset, len) => Some(&self.slots[offset..offset + len]),
+        } 
+    } 
+
+    pub fn update_record(&mut self, slot: u16, record: &[u8]) -> bool {
+        let (offset, len) = match self.read_record(slot) {
+            Some(_) => self.slot_entry(slot),
+            None => return false,
+        };
+
+        // Shrinking or same size records are rewritten in place
+        if record.len() <= len {
+            self.slots[offset..offset + record.len()].copy_from_slice(record);
+            self.set_slot_entry(slot, offset, record.len());
+            return true;
+        } 
+
+        if record.len() - len > self.free_space() {
+            return false;
+        } 
+
+        // Free the old body, then place the new one keeping the slot number
+        self.set_slot_entry(slot, SLOTS_SIZE, 0);
+        self.make_room(record.len());
+
+        let offset = self.records_start() - record.len();
+        self.slots[offset..offset + record.len()].copy_from_slice(record);
+        self.set_slot_entry(slot, offset, record.len());
+
+        true
+    } 
+
+    pub fn delete_record(&mut self, slot: u16) -> bool {
+        if self.read_record(slot).is_none() {
+            return false;
+        } 
+
+        self.set_slot_entry(slot, 0, 0);
+
+        // Trailing free slots can be dropped from the directory
+        while self.num_slots > 0 && self.slot_entry(self.num_slots as u16 - 1).0 == 0 {
+            self.set_slot_entry(self.num_slots as u16 - 1, 0, 0);
+            self.num_slots -= 1;
+        } 
+
+        true
+    } 
+
+    pub fn records(&self) -> Vec<(u16, &[u8])> {
+        (0..self.num_slots as u16)
+            .filter_map(|slot| self.read_record(slot).map(|record| (slot, record)))
+            .collect()
+    } 
+
+    // Compacts unless len bytes lie free between the slot directory and
+    // the lowest record body
+    fn make_room(&mut self, len: usize) {
+        if self.records_start() < self.directory_end() + len {
+            self.compact();
+        } 
+    } 
+
+    // Move all record bodies to the end of the slots area, removing the
+    // holes left by deleted or resized records. Slot numbers are unchanged.
+    pub fn compact(&mut self) {
+        let records: Vec<(u16, Vec<u8>)> = (0..self.num_slots as u16)
+            .filter_map(|slot| match self.slot_entry(slot) {
+                (0, _) => None,
+                (offset, len) => Some((slot, self.slots[offset..offset + len].to_vec())),
+            })
+            .collect();
+
+        let mut offset = SLOTS_SIZE;
+        for (slot, record) in records {
+            offset -= record.len();
+            self.slots[offset..offset + record.len()].copy_from_slice(&record);
+            self.set_slot_entry(slot, offset, record.len());
+        } 
+
+        let directory_end = self.directory_end();
+        self.slots[directory_end..offset].fill(0);
     } 
 
     pub fn write_to_disk(&self, file: &mut File) -> io::Result<()> {
@@ -143,6 +450,14 @@ impl PageFormat {
 
 }
 
+// CRC32 over the whole serialized page except the checksum itself
+fn page_checksum(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&data[..CHECKSUM_OFFSET]);
+    hasher.update(&data[CHECKSUM_OFFSET + CHECKSUM_SIZE..]);
+    hasher.finalize()
+} 
+
 pub fn array_from_vec<const N: usize>(bytes: Vec<u8>) -> [u8; N] {
     bytes[..N].try_into().unwrap() //SIZE would be 16 or 4096
 } 
@@ -233,6 +548,158 @@ fn test_deserialize_full_page() {
         assert_eq!(page.get_footer(), footer);
     }
 
+    #[test]
+    fn test_insert_and_read_records() {
+        let mut page = PageFormat::new();
+
+        let first = page.insert_record(b"first").unwrap();
+        let second = page.insert_record(b"second").unwrap();
+
+        assert_eq!(page.get_num_slots(), 2);
+        assert_eq!(page.read_record(first), Some(&b"first"[..]));
+        assert_eq!(page.read_record(second), Some(&b"second"[..]));
+        assert_eq!(page.read_record(5), None);
+    } 
+
+    #[test]
+    fn test_insert_record_when_full() {
+        let mut page = PageFormat::new();
+
+        let record = vec![7u8; PageFormat::max_record_size()];
+        assert!(page.insert_record(&record).is_some());
+
+        assert_eq!(page.free_space(), 0);
+        assert!(page.is_full());
+        assert!(page.insert_record(b"x").is_none());
+    } 
+
+    #[test]
+    fn test_delete_record_keeps_slot_numbers() {
+        let mut page = PageFormat::new();
+
+        let a = page.insert_record(b"a").unwrap();
+        let b = page.insert_record(b"b").unwrap();
+        let c = page.insert_record(b"c").unwrap();
+
+        assert!(page.delete_record(b));
+        assert!(!page.delete_record(b));
+
+        assert_eq!(page.read_record(a), Some(&b"a"[..]));
+        assert_eq!(page.read_record(b), None);
+        assert_eq!(page.read_record(c), Some(&b"c"[..]));
+
+        // Freed slot is reused
+        assert_eq!(page.insert_record(b"d"), Some(b));
+
+        let deserialized = PageFormat::deserialize(page.serialize());
+        assert_eq!(deserialized.records(), vec![(a, &b"a"[..]), (b, &b"d"[..]), (c, &b"c"[..])]);
+    } 
+
+    #[test]
+    fn test_free_space_reclaimed_after_delete() {
+        let mut page = PageFormat::new();
+        let empty = page.free_space();
+
+        let record = vec![1u8; 1000];
+        let slots: Vec<u16> = (0..3).map(|_| page.insert_record(&record).unwrap()).collect();
+        assert_eq!(page.free_space(), empty - 3 * (1000 + SLOT_ENTRY_SIZE));
+
+        page.delete_record(slots[0]);
+
+        // Needs compaction to fit in the hole left by the first record
+        let big = vec![2u8; 1500];
+        let slot = page.insert_record(&big).unwrap();
+
+        assert_eq!(page.read_record(slot), Some(&big[..]));
+        assert_eq!(page.read_record(slots[1]), Some(&record[..]));
+        assert_eq!(page.read_record(slots[2]), Some(&record[..]));
+    } 
+
+    #[test]
+    fn test_update_record() {
+        let mut page = PageFormat::new();
+
+        let a = page.insert_record(b"short").unwrap();
+        let b = page.insert_record(b"other").unwrap();
+
+        assert!(page.update_record(a, b"tiny"));
+        assert_eq!(page.read_record(a), Some(&b"tiny"[..]));
+
+        assert!(page.update_record(a, b"a much longer record"));
+        assert_eq!(page.read_record(a), Some(&b"a much longer record"[..]));
+        assert_eq!(page.read_record(b), Some(&b"other"[..]));
+
+        assert!(!page.update_record(7, b"missing"));
+    } 
+
+    #[test]
+    fn test_new_slot_after_shrink_in_full_page() {
+        let mut page = PageFormat::new();
+
+        // Leave less room than a slot entry below the record
+        let big = vec![7u8; PageFormat::max_record_size() - 2];
+        let a = page.insert_record(&big).unwrap();
+        assert!(page.update_record(a, b"now short"));
+
+        let b = page.insert_record(b"new record").unwrap();
+        assert_eq!(page.read_record(a), Some(&b"now short"[..]));
+        assert_eq!(page.read_record(b), Some(&b"new record"[..]));
+    } 
+
+    #[test]
+    fn test_delete_last_records_shrinks_directory() {
+        let mut page = PageFormat::new();
+
+        let a = page.insert_record(b"a").unwrap();
+        let b = page.insert_record(b"b").unwrap();
+
+        page.delete_record(a);
+        assert_eq!(page.get_num_slots(), 2);
+
+        page.delete_record(b);
+        assert_eq!(page.get_num_slots(), 0);
+        assert_eq!(page.free_space(), SLOTS_SIZE);
+    } 
+
+    #[test]
+    fn test_checksum_detects_corruption() {
+        let mut page = PageFormat::new();
+        page.insert_record(b"checked record").unwrap();
+
+        let mut bytes = page.serialize();
+        assert!(PageFormat::verify_checksum(&bytes));
+
+        bytes[100] ^= 0xff;
+        assert!(!PageFormat::verify_checksum(&bytes));
+    } 
+
+    #[test]
+    fn test_checksum_covers_header_and_footer() {
+        let page = PageFormat::new();
+        let bytes = page.serialize();
+
+        let mut header_damaged = bytes.clone();
+        header_damaged[0] = 1;
+        assert!(!PageFormat::verify_checksum(&header_damaged));
+
+        let mut footer_damaged = bytes.clone();
+        footer_damaged[4095] = 1;
+        assert!(!PageFormat::verify_checksum(&footer_damaged));
+    } 
+
+    #[test]
+    fn test_page_type_and_next_page_round_trip() {
+        let mut page = PageFormat::new();
+        assert_eq!(page.get_page_type(), PageType::Raw);
+
+        page.set_page_type(PageType::Data);
+        page.set_next_page(8192);
+
+        let deserialized = PageFormat::deserialize(page.serialize());
+        assert_eq!(deserialized.get_page_type(), PageType::Data);
+        assert_eq!(deserialized.get_next_page(), 8192);
+    } 
+
     #[test]
     fn test_page_size() {
         let page = PageFormat::new();
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::io;
+use std::ops::RangeBounds;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::btree::BTree;
+use crate::codec::Decode;
+use crate::iter::Iter;
+use crate::keys::Key;
+use crate::page::PageFormat;
+use crate::storage_manager::{PageId, PageView, Store};
+use crate::values::Value;
+
+// Point-in-time view of a store, taken with Store::snapshot or
+// Db::snapshot. Reads through a snapshot see every key as it was when the
+// snapshot was taken, whatever has been written since, and expiry is
+// judged at that time too. Reading one through a store other than the one
+// it was taken from is an InvalidInput error.
+pub struct Snapshot {
+    state: Arc<SnapshotState>,
+}
+
+// Pages are copied on write. The first change to a page after the
+// snapshot was taken saves the page's old image here, anything unchanged
+// is read from the store as usual. Saved images are shared by every
+// snapshot that needed them and go away with the last of those.
+pub(crate) struct SnapshotState {
+    store_id: u64,
+    index: BTree,
+    taken_at: u64,
+    file_len: u64,
+    pages: Mutex<HashMap<u64, Arc<PageFormat>>>,
+    // Where pages freed since the snapshot used to live
+    locations: Mutex<HashMap<u64, u64>>,
+}
+
+impl SnapshotState {
+    pub(crate) fn new(store_id: u64, index: BTree, taken_at: u64, file_len: u64) -> Self {
+        SnapshotState {
+            store_id,
+            index,
+            taken_at,
+            file_len,
+            pages: Mutex::new(HashMap::new()),
+            locations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn index(&self) -> &BTree {
+        &self.index
+    }
+
+    pub(crate) fn taken_at(&self) -> u64 {
+        self.taken_at
+    }
+
+    fn pages(&self) -> MutexGuard<'_, HashMap<u64, Arc<PageFormat>>> {
+        self.pages.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn locations(&self) -> MutexGuard<'_, HashMap<u64, u64>> {
+        self.locations.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Pages appended after the snapshot was taken are never read by it
+    pub(crate) fn needs_page(&self, location: u64) -> bool {
+        location < self.file_len && !self.pages().contains_key(&location)
+    }
+
+    pub(crate) fn save_page(&self, location: u64, page: Arc<PageFormat>) {
+        self.pages().insert(location, page);
+    }
+
+    pub(crate) fn page(&self, location: u64) -> Option<PageFormat> {
+        self.pages().get(&location).map(|page| PageFormat::clone(page))
+    }
+
+    pub(crate) fn save_location(&self, id: &PageId, location: u64) {
+        self.locations().entry(id.as_u64()).or_insert(location);
+    }
+
+    pub(crate) fn location(&self, id: &PageId) -> Option<u64> {
+        self.locations().get(&id.as_u64()).copied()
+    }
+
+    pub(crate) fn saved_pages(&self) -> Vec<Arc<PageFormat>> {
+        self.pages().values().cloned().collect()
+    }
+}
+
+impl Snapshot {
+    pub(crate) fn new(state: Arc<SnapshotState>) -> Self {
+        Snapshot { state }
+    }
+
+    pub(crate) fn view<'a>(&'a self, store: &'a Store) -> Result<PageView<'a>, io::Error> {
+        if store.id() != self.state.store_id {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Snapshot read through a store it was not taken from"));
+        }
+
+        Ok(PageView::new(store, Some(&self.state)))
+    }
+
+    pub fn taken_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.state.taken_at)
+    }
+
+    pub fn get(&self, store: &Store, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        self.view(store)?.get(key)
+    }
+
+    pub fn get_as<T: Decode>(&self, store: &Store, key: &Key) -> Result<Option<Value<T>>, io::Error> {
+        match self.get(store, key)? {
+            Some(value) => Ok(Some(Value::new(T::decode_value(value.get())?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn contains(&self, store: &Store, key: &Key) -> Result<bool, io::Error> {
+        Ok(self.view(store)?.find_live(key)?.is_some())
+    }
+
+    pub fn iter<'a>(&'a self, store: &'a Store) -> Result<Iter<'a>, io::Error> {
+        self.range(store, ..)
+    }
+
+    pub fn range<'a, R: RangeBounds<Key>>(&'a self, store: &'a Store, range: R) -> Result<Iter<'a>, io::Error> {
+        Ok(self.view(store)?.range(range))
+    }
+
+    pub fn prefix<'a>(&'a self, store: &'a Store, prefix: &[u8]) -> Result<Iter<'a>, io::Error> {
+        Ok(self.view(store)?.prefix(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::wal::wal_path;
+    use std::env;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    fn temp_store(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(wal_path(&path));
+        path
+    }
+
+    fn value_for(i: u64, round: u8) -> Vec<u8> {
+        // Every tenth value needs an overflow chain
+        let len = if i.is_multiple_of(10) { 9000 } else { 40 + i as usize % 50 };
+        vec![round.wrapping_add(i as u8); len]
+    }
+
+    #[test]
+    fn test_snapshot_keeps_point_in_time_view() {
+        let path = temp_store("test_snapshot_view");
+        let mut store = Store::new(&path).unwrap();
+
+        let mut tx = store.begin().unwrap();
+        for i in 0..300u64 {
+            tx.put(Key::from(i), Value::new(value_for(i, 0))).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let snapshot = store.snapshot().unwrap();
+
+        // Rewrite some keys, delete others and add new ones, sometimes
+        // freeing pages and reusing them for something else
+        let mut tx = store.begin().unwrap();
+        for i in 0..300u64 {
+            match i % 3 {
+                0 => tx.put(Key::from(i), Value::new(value_for(i, 1))).unwrap(),
+                1 => assert!(tx.delete(&Key::from(i)).unwrap()),
+                _ => {}
+            }
+        }
+        for i in 300..400u64 {
+            tx.put(Key::from(i), Value::new(value_for(i, 1))).unwrap();
+        }
+        tx.commit().unwrap();
+
+        for i in 0..300u64 {
+            assert_eq!(snapshot.get(&store, &Key::from(i)).unwrap().unwrap().get(), &value_for(i, 0));
+        }
+        assert!(!snapshot.contains(&store, &Key::from(350u64)).unwrap());
+
+        let scanned: Vec<u64> = snapshot.iter(&store).unwrap().map(|entry| entry.unwrap().0.to_u64().unwrap()).collect();
+        assert_eq!(scanned, (0..300).collect::<Vec<_>>());
+
+        let scanned: Vec<u64> = snapshot.range(&store, Key::from(100u64)..Key::from(110u64)).unwrap().rev()
+            .map(|entry| entry.unwrap().0.to_u64().unwrap())
+            .collect();
+        assert_eq!(scanned, (100..110).rev().collect::<Vec<_>>());
+
+        // The store itself moved on
+        assert_eq!(store.get(&Key::from(0u64)).unwrap().unwrap().get(), &value_for(0, 1));
+        assert!(!store.contains(&Key::from(1u64)).unwrap());
+        assert_eq!(store.iter().count(), 300);
+    }
+
+    #[test]
+    fn test_snapshots_taken_at_different_times() {
+        let path = temp_store("test_snapshot_times");
+        let mut store = Store::new(&path).unwrap();
+        let key = Key::from("counter");
+
+        let mut snapshots = vec![];
+        for n in 0..5u64 {
+            store.insert(key.clone(), Value::new(n)).unwrap();
+            snapshots.push(store.snapshot().unwrap());
+        }
+        store.delete(&key).unwrap();
+
+        for (n, snapshot) in snapshots.iter().enumerate() {
+            assert_eq!(*snapshot.get_as::<u64>(&store, &key).unwrap().unwrap().get(), n as u64);
+        }
+        assert!(store.get(&key).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_snapshot_judges_expiry_when_taken() {
+        let path = temp_store("test_snapshot_expiry");
+        let mut store = Store::new(&path).unwrap();
+
+        store.put_with_ttl(Key::from("gone"), Value::new("x"), Duration::ZERO).unwrap();
+        store.put_with_ttl(Key::from("brief"), Value::new("y"), Duration::from_millis(200)).unwrap();
+        let snapshot = store.snapshot().unwrap();
+
+        thread::sleep(Duration::from_millis(250));
+
+        assert!(!snapshot.contains(&store, &Key::from("gone")).unwrap());
+        assert_eq!(snapshot.get(&store, &Key::from("brief")).unwrap().unwrap().get(), b"y");
+        assert!(!store.contains(&Key::from("brief")).unwrap());
+    }
+
+    #[test]
+    fn test_old_versions_dropped_with_last_snapshot() {
+        let path = temp_store("test_snapshot_gc");
+        let mut store = Store::new(&path).unwrap();
+
+        for i in 0..200u64 {
+            store.insert(Key::from(i), Value::new(value_for(i, 0))).unwrap();
+        }
+        assert_eq!(store.retained_versions(), 0);
+
+        let first = store.snapshot().unwrap();
+        let second = store.snapshot().unwrap();
+        let mut tx = store.begin().unwrap();
+        for i in 0..200u64 {
+            tx.put(Key::from(i), Value::new(value_for(i, 1))).unwrap();
+        }
+        tx.commit().unwrap();
+
+        // Both snapshots share one copy of each old page
+        let retained = store.retained_versions();
+        assert!(retained > 0);
+        drop(first);
+        assert_eq!(store.retained_versions(), retained);
+
+        drop(second);
+        assert_eq!(store.retained_versions(), 0);
+
+        // Later writes save nothing for dropped snapshots
+        store.insert(Key::from(0u64), Value::new("after")).unwrap();
+        assert_eq!(store.retained_versions(), 0);
+    }
+
+    #[test]
+    fn test_snapshot_reads_while_writers_continue() {
+        const KEYS: u64 = 200;
+
+        let path = temp_store("test_snapshot_db");
+        let db = Arc::new(Db::new(&path).unwrap());
+        db.transaction(|tx| {
+            for i in 0..KEYS {
+                tx.put(Key::from(i), Value::new(value_for(i, 0)))?;
+            }
+            Ok(())
+        }).unwrap();
+
+        let snapshot = Arc::new(db.snapshot().unwrap());
+        let writing = Arc::new(AtomicBool::new(true));
+
+        let writer = {
+            let (db, writing) = (db.clone(), writing.clone());
+            thread::spawn(move || {
+                for round in 1..=10u8 {
+                    db.transaction(|tx| {
+                        for i in 0..KEYS / 2 {
+                            tx.put(Key::from(i), Value::new(value_for(i, round)))?;
+                        }
+                        tx.delete(&Key::from(KEYS - round as u64))?;
+                        Ok(())
+                    }).unwrap();
+                }
+                writing.store(false, Ordering::SeqCst);
+            })
+        };
+
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let (snapshot, writing) = (snapshot.clone(), writing.clone());
+                thread::spawn(move || {
+                    while writing.load(Ordering::SeqCst) {
+                        let values: Vec<(Key, Value<Vec<u8>>)> = snapshot.iter().unwrap().collect::<Result<_, _>>().unwrap();
+
+                        assert_eq!(values.len(), KEYS as usize);
+                        for (key, value) in values {
+                            assert_eq!(value.get(), &value_for(key.to_u64().unwrap(), 0));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(db.read(|store| store.iter().count()).unwrap(), KEYS as usize - 10);
+    }
+
+    #[test]
+    fn test_writes_get_in_during_a_snapshot_scan() {
+        let path = temp_store("test_snapshot_db_scan");
+        let db = Arc::new(Db::new(&path).unwrap());
+        for i in 0..100u64 {
+            db.insert(Key::from(i), Value::new(value_for(i, 0))).unwrap();
+        }
+
+        let snapshot = db.snapshot().unwrap();
+        let mut scan = snapshot.iter().unwrap();
+        assert_eq!(scan.next().unwrap().unwrap().0, Key::from(0u64));
+
+        // Would deadlock if the scan held the store between steps
+        for i in 0..100u64 {
+            db.insert(Key::from(i), Value::new(value_for(i, 1))).unwrap();
+        }
+        db.delete(&Key::from(50u64)).unwrap();
+
+        let rest: Vec<(Key, Value<Vec<u8>>)> = scan.collect::<Result<_, _>>().unwrap();
+        assert_eq!(rest.len(), 99);
+        for (key, value) in rest {
+            assert_eq!(value.get(), &value_for(key.to_u64().unwrap(), 0));
+        }
+
+        assert!(snapshot.contains(&Key::from(50u64)).unwrap());
+        assert_eq!(db.get(&Key::from(1u64)).unwrap().unwrap().get(), &value_for(1, 1));
+    }
+
+    #[test]
+    fn test_snapshot_read_through_another_store() {
+        let first = Store::new(&temp_store("test_snapshot_first")).unwrap();
+        let mut second = Store::new(&temp_store("test_snapshot_second")).unwrap();
+
+        let snapshot = second.snapshot().unwrap();
+        let key = Key::from("key");
+        let errors = [
+            snapshot.get(&first, &key).err(),
+            snapshot.get_as::<u64>(&first, &key).err(),
+            snapshot.contains(&first, &key).err(),
+            snapshot.iter(&first).err(),
+            snapshot.range(&first, key.clone()..).err(),
+            snapshot.prefix(&first, b"k").err(),
+        ];
+        for err in errors {
+            assert_eq!(err.unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+
+        assert!(snapshot.get(&second, &key).unwrap().is_none());
+    }
+}
@@ -0,0 +1,585 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use crate::bloom::{key_hashes, BloomFilter};
+use crate::file_io::{sync_dir, PositionalIo};
+use crate::keys::Key;
+use crate::page::{PageFormat, PageType};
+use crate::storage_manager::StoreError;
+
+const BLOCK_SIZE: u64 = 4096;
+
+const ENTRY_PUT: u8 = 0;
+const ENTRY_DELETE: u8 = 1;
+
+// Set on entries too big for one block. The entry's full value length
+// follows the kind byte and the rest of the value fills the overflow
+// blocks right after it.
+const ENTRY_SPLIT: u8 = 0x80;
+const SPLIT_LEN_SIZE: usize = 8;
+
+// Key length + kind
+const ENTRY_HEADER_SIZE: usize = 2 + 1;
+
+// Data block count + entry count + bloom block count
+const FOOTER_SIZE: usize = 8 + 8 + 8;
+
+// A key and its value, or None for a key deleted by this entry
+pub(crate) type RunEntry = (Key, Option<Vec<u8>>);
+
+// Entries are [klen u16][key][kind u8][value], the same form the LSM
+// engine writes to its log
+pub(crate) fn encode_entry(key: &Key, value: Option<&[u8]>) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(ENTRY_HEADER_SIZE + key.len() + value.map_or(0, <[u8]>::len));
+    entry.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    entry.extend_from_slice(key.as_bytes());
+
+    match value {
+        Some(value) => {
+            entry.push(ENTRY_PUT);
+            entry.extend_from_slice(value);
+        }
+        None => entry.push(ENTRY_DELETE),
+    }
+
+    entry
+}
+
+// Returns the entry along with the full value length of a split entry
+fn decode_entry_parts(entry: &[u8]) -> Result<(RunEntry, Option<u64>), StoreError> {
+    let corrupt = || StoreError::Corruption { msg: "Truncated run entry".to_string() };
+
+    let key_len = u16::from_le_bytes(entry.get(..2).ok_or_else(corrupt)?.try_into().unwrap()) as usize;
+    let key = Key::from(entry.get(2..2 + key_len).ok_or_else(corrupt)?);
+    let kind = *entry.get(2 + key_len).ok_or_else(corrupt)?;
+    let body = &entry[ENTRY_HEADER_SIZE + key_len..];
+
+    match kind {
+        ENTRY_PUT => Ok(((key, Some(body.to_vec())), None)),
+        ENTRY_DELETE => Ok(((key, None), None)),
+        kind if kind == ENTRY_PUT | ENTRY_SPLIT => {
+            let len = u64::from_le_bytes(body.get(..SPLIT_LEN_SIZE).ok_or_else(corrupt)?.try_into().unwrap());
+            Ok(((key, Some(body[SPLIT_LEN_SIZE..].to_vec())), Some(len)))
+        }
+        kind => Err(StoreError::Corruption { msg: format!("Unknown run entry kind {}", kind) }),
+    }
+}
+
+pub(crate) fn decode_entry(entry: &[u8]) -> Result<RunEntry, StoreError> {
+    match decode_entry_parts(entry)? {
+        (entry, None) => Ok(entry),
+        (_, Some(_)) => Err(StoreError::Corruption { msg: "Split entry outside a run".to_string() }),
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp: OsString = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    PathBuf::from(temp)
+}
+
+fn block_full_error() -> io::Error {
+    StoreError::Serialization("Run entry too large for a block".to_string()).into()
+}
+
+// Writes a sorted run, entries in ascending key order. The file is made
+// of PageFormat blocks: data blocks holding entries, overflow blocks for
+// the rest of entries too big for one block, then index blocks mapping
+// each data block's first key to its number, bloom blocks holding a
+// filter of the run's keys, and a footer block last. Runs are written
+// under a temporary name and only appear once complete.
+pub(crate) struct RunWriter {
+    file: File,
+    path: PathBuf,
+    block: PageFormat,
+    blocks: u64,
+    index: Vec<(Vec<u8>, u64)>,
+    entries: u64,
+    false_positive_rate: f64,
+    // Hashes of every key, if the run gets a filter
+    key_hashes: Option<Vec<(u32, u32)>>,
+}
+
+impl RunWriter {
+    // A false positive rate of 0 writes the run without a filter
+    pub(crate) fn create(path: &Path, false_positive_rate: f64) -> Result<Self, io::Error> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(temp_path(path))?;
+
+        Ok(RunWriter {
+            file,
+            path: path.to_path_buf(),
+            block: Self::new_block(PageType::Data),
+            blocks: 0,
+            index: vec![],
+            entries: 0,
+            false_positive_rate,
+            key_hashes: (false_positive_rate > 0.0).then(Vec::new),
+        })
+    }
+
+    fn new_block(page_type: PageType) -> PageFormat {
+        let mut block = PageFormat::new();
+        block.set_page_type(page_type);
+        block
+    }
+
+    fn write_block(&mut self, block: &PageFormat) -> Result<(), io::Error> {
+        self.file.write_all(&block.serialize())?;
+        self.blocks += 1;
+
+        Ok(())
+    }
+
+    fn finish_block(&mut self) -> Result<(), io::Error> {
+        if self.block.get_num_slots() == 0 {
+            return Ok(());
+        }
+
+        let block = std::mem::replace(&mut self.block, Self::new_block(PageType::Data));
+        self.write_block(&block)
+    }
+
+    pub(crate) fn add(&mut self, key: &Key, value: Option<&[u8]>) -> Result<(), io::Error> {
+        self.entries += 1;
+        if let Some(hashes) = self.key_hashes.as_mut() {
+            hashes.push(key_hashes(key.as_bytes()));
+        }
+
+        let entry = encode_entry(key, value);
+        if entry.len() > PageFormat::max_record_size() {
+            return self.add_split(key, value.unwrap_or_default());
+        }
+
+        if self.block.get_num_slots() == 0 {
+            self.index.push((key.as_bytes().to_vec(), self.blocks));
+        }
+        if self.block.insert_record(&entry).is_some() {
+            return Ok(());
+        }
+
+        self.finish_block()?;
+        self.index.push((key.as_bytes().to_vec(), self.blocks));
+        self.block.insert_record(&entry).ok_or_else(block_full_error)?;
+
+        Ok(())
+    }
+
+    // Big values get a data block of their own for the head of the entry,
+    // followed by as many overflow blocks as the rest needs
+    fn add_split(&mut self, key: &Key, value: &[u8]) -> Result<(), io::Error> {
+        self.finish_block()?;
+
+        let mut head = Vec::with_capacity(PageFormat::max_record_size());
+        head.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        head.extend_from_slice(key.as_bytes());
+        head.push(ENTRY_PUT | ENTRY_SPLIT);
+        head.extend_from_slice(&(value.len() as u64).to_le_bytes());
+
+        let (first, rest) = value.split_at(PageFormat::max_record_size() - head.len());
+        head.extend_from_slice(first);
+
+        self.index.push((key.as_bytes().to_vec(), self.blocks));
+        let mut block = Self::new_block(PageType::Data);
+        block.insert_record(&head).ok_or_else(block_full_error)?;
+        self.write_block(&block)?;
+
+        for chunk in rest.chunks(PageFormat::max_record_size()) {
+            let mut block = Self::new_block(PageType::Overflow);
+            block.insert_record(chunk).ok_or_else(block_full_error)?;
+            self.write_block(&block)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<Run, io::Error> {
+        self.finish_block()?;
+        let data_blocks = self.blocks;
+
+        let mut block = Self::new_block(PageType::RunIndex);
+        for (key, number) in std::mem::take(&mut self.index) {
+            let mut record = number.to_le_bytes().to_vec();
+            record.extend_from_slice(&key);
+
+            if block.insert_record(&record).is_none() {
+                let full = std::mem::replace(&mut block, Self::new_block(PageType::RunIndex));
+                self.write_block(&full)?;
+                block.insert_record(&record).ok_or_else(block_full_error)?;
+            }
+        }
+        if block.get_num_slots() > 0 {
+            self.write_block(&block)?;
+        }
+
+        let mut bloom_blocks = 0u64;
+        if let Some(key_hashes) = self.key_hashes.take() {
+            let mut filter = BloomFilter::new(key_hashes.len(), self.false_positive_rate)?;
+            for hashes in key_hashes {
+                filter.insert_hashes(hashes);
+            }
+
+            for chunk in filter.to_bytes().chunks(PageFormat::max_record_size()) {
+                let mut block = Self::new_block(PageType::Bloom);
+                block.insert_record(chunk).ok_or_else(block_full_error)?;
+                self.write_block(&block)?;
+                bloom_blocks += 1;
+            }
+        }
+
+        let mut footer = Self::new_block(PageType::RunFooter);
+        let mut record = data_blocks.to_le_bytes().to_vec();
+        record.extend_from_slice(&self.entries.to_le_bytes());
+        record.extend_from_slice(&bloom_blocks.to_le_bytes());
+        footer.insert_record(&record).ok_or_else(block_full_error)?;
+        self.write_block(&footer)?;
+
+        // Only a complete, durable run gets its real name, and the rename
+        // is durable before anything relies on the run
+        self.file.sync_all()?;
+        fs::rename(temp_path(&self.path), &self.path)?;
+        sync_dir(self.path.parent().unwrap_or(Path::new(".")))?;
+
+        Run::open(&self.path)
+    }
+}
+
+// A sorted run on disk, immutable once written. Only the block index and
+// the bloom filter are kept in memory, blocks are read as lookups and
+// scans need them.
+pub(crate) struct Run {
+    file: File,
+    path: PathBuf,
+    index: Vec<(Vec<u8>, u64)>,
+    bloom: Option<BloomFilter>,
+    data_blocks: u64,
+    entries: u64,
+    size: u64,
+}
+
+impl Run {
+    pub(crate) fn open(path: &Path) -> Result<Run, io::Error> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        if size < BLOCK_SIZE || !size.is_multiple_of(BLOCK_SIZE) {
+            return Err(StoreError::Corruption { msg: format!("Run {} has a partial block", path.display()) }.into());
+        }
+
+        let mut run = Run {
+            file,
+            path: path.to_path_buf(),
+            index: vec![],
+            bloom: None,
+            data_blocks: 0,
+            entries: 0,
+            size,
+        };
+
+        let footer_number = size / BLOCK_SIZE - 1;
+        let footer = run.read_block(footer_number, PageType::RunFooter)?;
+        let record = footer.read_record(0)
+            .filter(|record| record.len() == FOOTER_SIZE)
+            .ok_or(StoreError::Corruption { msg: "Run footer is missing".to_string() })?;
+        run.data_blocks = u64::from_le_bytes(record[..8].try_into().unwrap());
+        run.entries = u64::from_le_bytes(record[8..16].try_into().unwrap());
+        let bloom_blocks = u64::from_le_bytes(record[16..24].try_into().unwrap());
+
+        let bloom_start = footer_number.checked_sub(bloom_blocks)
+            .filter(|start| *start >= run.data_blocks)
+            .ok_or(StoreError::Corruption { msg: format!("Run {} has a bad footer", path.display()) })?;
+
+        for number in run.data_blocks..bloom_start {
+            let block = run.read_block(number, PageType::RunIndex)?;
+            for (_, record) in block.records() {
+                let first_block = u64::from_le_bytes(record[..8].try_into().unwrap());
+                run.index.push((record[8..].to_vec(), first_block));
+            }
+        }
+
+        if bloom_blocks > 0 {
+            let mut bytes = vec![];
+            for number in bloom_start..footer_number {
+                let block = run.read_block(number, PageType::Bloom)?;
+                bytes.extend_from_slice(block.read_record(0).unwrap_or_default());
+            }
+            run.bloom = Some(BloomFilter::from_bytes(&bytes)?);
+        }
+
+        Ok(run)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Bytes the run takes up on disk
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(crate) fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    fn read_block(&self, number: u64, expected: PageType) -> Result<PageFormat, io::Error> {
+        let mut bytes = vec![0; BLOCK_SIZE as usize];
+        self.file.read_exact_at(&mut bytes, number * BLOCK_SIZE)?;
+
+        if !PageFormat::verify_checksum(&bytes) {
+            return Err(StoreError::Corruption { msg: format!("Checksum mismatch for block {} of {}", number, self.path.display()) }.into());
+        }
+
+        let block = PageFormat::deserialize(bytes);
+        if block.get_page_type() != expected {
+            return Err(StoreError::Corruption { msg: format!("Unexpected block type at block {} of {}", number, self.path.display()) }.into());
+        }
+
+        Ok(block)
+    }
+
+    // Entries of a data block in key order, and the number of the data
+    // block after it
+    fn read_entries(&self, number: u64) -> Result<(Vec<RunEntry>, u64), io::Error> {
+        let block = self.read_block(number, PageType::Data)?;
+        let mut next = number + 1;
+        let mut entries = vec![];
+
+        for (_, record) in block.records() {
+            let (mut entry, split_len) = decode_entry_parts(record)?;
+
+            if let (Some(len), Some(value)) = (split_len, entry.1.as_mut()) {
+                while (value.len() as u64) < len {
+                    let overflow = self.read_block(next, PageType::Overflow)?;
+                    value.extend_from_slice(overflow.read_record(0).unwrap_or_default());
+                    next += 1;
+                }
+            }
+
+            entries.push(entry);
+        }
+
+        Ok((entries, next))
+    }
+
+    // False only if the run certainly has no entry for key. Runs without
+    // a filter may hold any key.
+    pub(crate) fn may_contain(&self, key: &Key) -> bool {
+        self.bloom.as_ref().is_none_or(|bloom| bloom.may_contain(key.as_bytes()))
+    }
+
+    pub(crate) fn has_filter(&self) -> bool {
+        self.bloom.is_some()
+    }
+
+    // Data block that would hold key, if any could
+    fn block_for(&self, key: &[u8]) -> Option<u64> {
+        let after = self.index.partition_point(|(first, _)| first.as_slice() <= key);
+        after.checked_sub(1).map(|i| self.index[i].1)
+    }
+
+    // The run's entry for key: Some(None) if the run deleted it, None if
+    // the run says nothing about it
+    pub(crate) fn get(&self, key: &Key) -> Result<Option<Option<Vec<u8>>>, io::Error> {
+        let number = match self.block_for(key.as_bytes()) {
+            Some(number) => number,
+            None => return Ok(None),
+        };
+
+        let (entries, _) = self.read_entries(number)?;
+        Ok(entries.into_iter().find(|(found, _)| found == key).map(|(_, value)| value))
+    }
+
+    pub(crate) fn range(&self, lower: Bound<Key>, upper: Bound<Key>) -> RunIter<'_> {
+        let next = match &lower {
+            Bound::Included(key) | Bound::Excluded(key) => self.block_for(key.as_bytes()).unwrap_or(0),
+            Bound::Unbounded => 0,
+        };
+
+        RunIter { run: self, next, entries: vec![].into_iter(), lower, upper }
+    }
+}
+
+// Entries of a run in key order between two bounds, deletions included
+pub(crate) struct RunIter<'a> {
+    run: &'a Run,
+    next: u64,
+    entries: std::vec::IntoIter<RunEntry>,
+    lower: Bound<Key>,
+    upper: Bound<Key>,
+}
+
+impl Iterator for RunIter<'_> {
+    type Item = Result<RunEntry, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                let below = match &self.lower {
+                    Bound::Included(lower) => &entry.0 < lower,
+                    Bound::Excluded(lower) => &entry.0 <= lower,
+                    Bound::Unbounded => false,
+                };
+                let above = match &self.upper {
+                    Bound::Included(upper) => &entry.0 > upper,
+                    Bound::Excluded(upper) => &entry.0 >= upper,
+                    Bound::Unbounded => false,
+                };
+
+                if above {
+                    self.next = self.run.data_blocks;
+                    self.entries = vec![].into_iter();
+                    return None;
+                }
+                if below {
+                    continue;
+                }
+                return Some(Ok(entry));
+            }
+
+            if self.next >= self.run.data_blocks {
+                return None;
+            }
+
+            match self.run.read_entries(self.next) {
+                Ok((entries, next)) => {
+                    self.entries = entries.into_iter();
+                    self.next = next;
+                }
+                Err(err) => {
+                    self.next = self.run.data_blocks;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_run(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(temp_path(&path));
+        path
+    }
+
+    fn entries() -> Vec<RunEntry> {
+        (0..2000u64)
+            .map(|i| {
+                let value = match i % 7 {
+                    0 => None,
+                    // Large enough to need several overflow blocks
+                    3 if i % 300 == 3 => Some(vec![i as u8; 10_000]),
+                    _ => Some(vec![i as u8; 20 + i as usize % 100]),
+                };
+                (Key::from(i), value)
+            })
+            .collect()
+    }
+
+    fn write_run(path: &Path, entries: &[RunEntry]) -> Run {
+        write_run_with(path, entries, 0.01)
+    }
+
+    fn write_run_with(path: &Path, entries: &[RunEntry], false_positive_rate: f64) -> Run {
+        let mut writer = RunWriter::create(path, false_positive_rate).unwrap();
+        for (key, value) in entries {
+            writer.add(key, value.as_deref()).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        let key = Key::from("key");
+        assert_eq!(decode_entry(&encode_entry(&key, Some(b"value"))).unwrap(), (key.clone(), Some(b"value".to_vec())));
+        assert_eq!(decode_entry(&encode_entry(&key, Some(b""))).unwrap(), (key.clone(), Some(vec![])));
+        assert_eq!(decode_entry(&encode_entry(&key, None)).unwrap(), (key, None));
+        assert!(decode_entry(&[5, 0, b'a']).is_err());
+    }
+
+    #[test]
+    fn test_run_lookups() {
+        let path = temp_run("test_run_lookups.run");
+        let entries = entries();
+        write_run(&path, &entries);
+
+        let run = Run::open(&path).unwrap();
+        assert_eq!(run.entries(), entries.len() as u64);
+        assert!(!temp_path(&path).exists());
+
+        for (key, value) in &entries {
+            assert_eq!(run.get(key).unwrap().as_ref(), Some(value));
+        }
+        assert_eq!(run.get(&Key::from(5000u64)).unwrap(), None);
+        assert_eq!(run.get(&Key::from("")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_run_range_scans() {
+        let path = temp_run("test_run_range.run");
+        let entries = entries();
+        let run = write_run(&path, &entries);
+
+        let scanned: Vec<RunEntry> = run.range(Bound::Unbounded, Bound::Unbounded).map(Result::unwrap).collect();
+        assert_eq!(scanned, entries);
+
+        let scanned: Vec<RunEntry> = run.range(Bound::Excluded(Key::from(300u64)), Bound::Included(Key::from(903u64)))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(scanned, entries[301..=903].to_vec());
+
+        assert_eq!(run.range(Bound::Included(Key::from(10u64)), Bound::Excluded(Key::from(10u64))).count(), 0);
+    }
+
+    #[test]
+    fn test_bloom_filter_rules_out_absent_keys() {
+        let path = temp_run("test_run_bloom.run");
+        let entries = entries();
+        let run = write_run(&path, &entries);
+
+        for (key, _) in &entries {
+            assert!(run.may_contain(key));
+        }
+        let false_positives = (5000..15000u64).filter(|i| run.may_contain(&Key::from(*i))).count();
+        assert!(false_positives < 200);
+
+        // Without a filter nothing is ruled out
+        let path = temp_run("test_run_no_bloom.run");
+        let unfiltered = write_run_with(&path, &entries, 0.0);
+        assert!(unfiltered.may_contain(&Key::from(5000u64)));
+        assert!(unfiltered.size() < run.size());
+        assert_eq!(unfiltered.get(&Key::from(1u64)).unwrap().as_ref(), Some(&entries[1].1));
+    }
+
+    #[test]
+    fn test_empty_run() {
+        let path = temp_run("test_run_empty.run");
+        let run = write_run(&path, &[]);
+
+        assert_eq!(run.get(&Key::from("a")).unwrap(), None);
+        assert_eq!(run.range(Bound::Unbounded, Bound::Unbounded).count(), 0);
+    }
+
+    #[test]
+    fn test_corrupt_block_is_reported() {
+        let path = temp_run("test_run_corrupt.run");
+        write_run(&path, &entries());
+
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(&[0xff; 16], 100).unwrap();
+
+        let run = Run::open(&path).unwrap();
+        let err = run.get(&Key::from(0u64)).unwrap_err();
+        assert!(StoreError::from_io(&err).unwrap().is_corruption());
+    }
+}
@@ -1,8 +1,55 @@
 use core::fmt;
-use std::{fs::{OpenOptions, File}, path::Path, io::{Write, Seek, self, SeekFrom, ErrorKind, Read}, error::Error, collections::{hash_map::DefaultHasher, BTreeMap}, sync::atomic::{AtomicUsize, Ordering} };
+use std::{fs::{OpenOptions, File}, path::Path, io::{Write, Seek, self}, error::Error, ops::{Bound, RangeBounds}, collections::{hash_map::DefaultHasher, BTreeMap} };
 use std::hash::Hasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::page::PageFormat;
+use crate::btree::{prefix_bounds, BTree, Cursor};
+use crate::iter::Iter;
+use crate::codec::{Decode, Encode};
+use crate::file_io::PositionalIo;
+#[cfg(feature = "serde")]
+use crate::codec::Bincode;
+use crate::hash_index::HashIndex;
+use crate::buffer_pool::{BufferPool, PageLatches, PoolStats, DEFAULT_POOL_CAPACITY};
+use crate::keys::{Key, MAX_KEY_SIZE};
+use crate::blob::{BlobReader, BlobSource, BlobWriter};
+use crate::page::{PageFormat, PageType};
+use crate::snapshot::{Snapshot, SnapshotState};
+use crate::superblock::{Superblock, SUPERBLOCK_LOCATION};
+use crate::transaction::Transaction;
+use crate::values::Value;
+use crate::wal::{wal_path, Wal};
+
+// Length prefix of the key at the start of every data record
+const KEY_LEN_SIZE: usize = 2;
+
+const PAGE_MAP_ENTRY_SIZE: usize = 16;
+
+const RECORD_ID_SIZE: usize = 8 + 2;
+
+const VALUE_INLINE: u8 = 0;
+const VALUE_OVERFLOW: u8 = 1;
+
+// Set on the value kind byte of records that expire, whose expiry time
+// follows the kind byte as milliseconds since the Unix epoch
+const VALUE_EXPIRES: u8 = 0x80;
+const EXPIRY_SIZE: usize = 8;
+
+// Value length + head page id
+const OVERFLOW_REF_SIZE: usize = 8 + 8;
+
+const PAGE_SIZE: u64 = 4096;
+
+// Tells stores apart, so a snapshot can check it is read through its own
+static NEXT_STORE_ID: AtomicU64 = AtomicU64::new(0);
+
+// Log size past which a commit also checkpoints, syncing the data file so
+// the log can start over
+const CHECKPOINT_WAL_SIZE: u64 = 4 << 20;
+
+type KeyValue = (Key, Value<Vec<u8>>);
 
 pub trait SyncFile: Write + Seek {
     fn sync_all(&self);
@@ -10,14 +57,60 @@ pub trait SyncFile: Write + Seek {
     fn sync_data(&self) -> Result<(), io::Error>;
 } 
 
+// Page access used by structures built out of store pages, such as the
+// B+tree index. Nothing done through a pager is committed by itself.
+pub trait Pager {
+    fn allocate(&mut self) -> Result<PageId, io::Error>;
+
+    fn read(&self, id: &PageId) -> Result<PageFormat, io::Error>;
+
+    fn write(&mut self, id: &PageId, page: &PageFormat) -> Result<(), io::Error>;
+
+    fn free(&mut self, id: PageId) -> Result<(), io::Error>;
+} 
+
 pub struct Store {
+    id: u64,
     file: File,
+    // End of the file including pages allocated since the last commit
+    file_len: u64,
     page_map: PageMap,
     num_pages: u32, 
+    index: BTree,
+    // Kept next to the B+tree for stores opened with a hash index
+    hash_index: Option<HashIndex>,
+    heap_tail: Option<PageId>,
+    blob_head: Option<PageId>,
+    map_pages: Vec<u64>,
+    next_page_id: u64,
+    free_head: u64,
+    pool: Mutex<BufferPool>,
+    pending: Mutex<BTreeMap<u64, PageFormat>>,
+    latches: PageLatches,
+    metadata_dirty: bool,
+    wal: Wal,
+    snapshots: Vec<Weak<SnapshotState>>,
+}
+
+pub struct StoreOptions {
+    pub pool_capacity: usize,
+    // Also index keys by hash, so a lookup reads at most two index pages
+    // however many keys there are, at the cost of updating two indexes on
+    // every write. Scans and snapshots still go through the B+tree.
+    pub hash_index: bool,
 }
 
+impl Default for StoreOptions {
+    fn default() -> Self {
+        StoreOptions {
+            pool_capacity: DEFAULT_POOL_CAPACITY,
+            hash_index: false,
+        } 
+    }
+} 
+
 #[derive(Debug)]
-enum StoreError {
+pub enum StoreError {
     Io(std::io::Error),
     Serialization(String),
     Corruption {msg: String},
@@ -33,36 +126,427 @@ impl From<StoreError> for std::io::Error {
     fn from(error: StoreError) ->  std::io::Error {
         match error {
             StoreError::Io(io_error) => io_error,
-            _ => std::io::Error::new(std::io::ErrorKind::Other, "Store error")
+            StoreError::Serialization(_) => std::io::Error::new(std::io::ErrorKind::InvalidInput, error),
+            StoreError::Corruption { .. } => std::io::Error::new(std::io::ErrorKind::InvalidData, error),
+        } 
+    }
+} 
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "Store io error: {}", err),
+            StoreError::Serialization(msg) => write!(f, "Store serialization error: {}", msg),
+            StoreError::Corruption { msg } => write!(f, "Store corruption: {}", msg),
         } 
     }
 } 
 
+impl Error for StoreError {}
+
+impl StoreError {
+    // Recovers the StoreError carried inside an io::Error returned by Store
+    pub fn from_io(error: &std::io::Error) -> Option<&StoreError> {
+        error.get_ref().and_then(|inner| inner.downcast_ref::<StoreError>())
+    } 
+
+    pub fn is_corruption(&self) -> bool {
+        matches!(self, StoreError::Corruption { .. })
+    } 
+} 
+
 impl Drop for Store {
     fn drop(&mut self) {
+        let _ = self.commit();
+        let _ = self.checkpoint();
         let _ = self.file.flush();
         let _ = self.file.sync_all();
-        self.file.sync_data().unwrap();
+        let _ = self.file.sync_data();
     }
 }
 
 impl Store {
 
     pub fn new(path: &Path) -> Result<Store, std::io::Error> {
+        Self::open(path, StoreOptions::default())
+    } 
+
+    pub fn open(path: &Path, options: StoreOptions) -> Result<Store, std::io::Error> {
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(path)
             .map_err(StoreError::from)?;
 
-        Ok(
-            Store {
-                file,
-                page_map: PageMap::new(),
-                num_pages: 0,
-            }
-        )
+        let wal = Wal::open(&wal_path(path))?;
+
+        let mut store = Store {
+            id: NEXT_STORE_ID.fetch_add(1, Ordering::Relaxed),
+            file,
+            file_len: 0,
+            page_map: PageMap::new(),
+            num_pages: 0,
+            index: BTree::new(),
+            hash_index: None,
+            heap_tail: None,
+            blob_head: None,
+            map_pages: Vec::new(),
+            next_page_id: 0,
+            free_head: 0,
+            pool: Mutex::new(BufferPool::new(options.pool_capacity)),
+            pending: Mutex::new(BTreeMap::new()),
+            latches: PageLatches::default(),
+            metadata_dirty: false,
+            wal,
+            snapshots: Vec::new(),
+        };
+
+        // Bring the data file up to date before reading anything from it
+        store.replay_wal()?;
+        store.file_len = store.file.metadata()?.len();
+
+        let is_new = store.file_len == 0;
+        if is_new {
+            store.file_len = SUPERBLOCK_LOCATION + PAGE_SIZE;
+            store.write_page_at(SUPERBLOCK_LOCATION, &Superblock::new().to_page())?;
+            store.commit()?;
+        } else {
+            store.load_metadata()?;
+        } 
+
+        // A blob was being written when the store went down, its chain is
+        // reachable from nothing else
+        if let Some(head) = store.blob_head.take() {
+            store.release_overflow(head)?;
+            store.metadata_dirty = true;
+            store.commit()?;
+        } 
+
+        // The hash index is built from the B+tree when first asked for and
+        // freed when a store that had one is opened without
+        match (store.hash_index.is_some(), options.hash_index) {
+            (false, true) => store.build_hash_index()?,
+            (true, false) => store.drop_hash_index()?,
+            _ => {}
+        } 
+
+        Ok(store)
+    } 
+
+    fn build_hash_index(&mut self) -> Result<(), io::Error> {
+        let mut hash_index = HashIndex::new();
+
+        let mut cursor = self.index.range(Bound::Unbounded, Bound::Unbounded);
+        while let Some((key, entry)) = cursor.next(self)? {
+            hash_index.insert(self, &key, &entry)?;
+        } 
+
+        self.hash_index = Some(hash_index);
+        self.metadata_dirty = true;
+        self.commit()
+    } 
+
+    fn drop_hash_index(&mut self) -> Result<(), io::Error> {
+        if let Some(mut hash_index) = self.hash_index.take() {
+            hash_index.clear(self)?;
+        } 
+
+        self.metadata_dirty = true;
+        self.commit()
+    } 
+
+    // Commits outstanding changes, including the page map and superblock,
+    // so the store can be reopened
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        self.commit()
+    } 
+
+    // Makes every change since the last commit durable. Page images go to
+    // the write-ahead log and are fsynced before the data file is touched,
+    // so a crash part way through leaves either all or none of them. The
+    // data file itself is only synced at a checkpoint, until then the log
+    // holds everything needed to redo its writes.
+    pub(crate) fn commit(&mut self) -> Result<(), io::Error> {
+        if self.metadata_dirty {
+            self.write_metadata()?;
+        } 
+
+        let mut pages = std::mem::take(&mut *self.pending());
+        for (location, page) in self.pool().take_dirty() {
+            pages.insert(location, page);
+        } 
+
+        if pages.is_empty() {
+            return Ok(());
+        } 
+
+        for (location, page) in &pages {
+            self.wal.append_page(*location, page)?;
+        } 
+        self.wal.append_commit()?;
+        self.wal.sync()?;
+
+        for (location, page) in &pages {
+            self.write_page_to_disk(*location, page)?;
+        } 
+
+        if self.wal.len()? >= CHECKPOINT_WAL_SIZE {
+            self.checkpoint()?;
+        } 
+
+        Ok(())
+    } 
+
+    // Syncs the data file, after which everything logged is in it and the
+    // log can be emptied
+    fn checkpoint(&mut self) -> Result<(), io::Error> {
+        if self.wal.is_empty()? {
+            return Ok(());
+        } 
+
+        self.file.sync_data()?;
+        self.wal.truncate()
+    } 
+
+    // Throws away every change since the last commit and reloads the
+    // in-memory state from the data file. Pages appended since file_len
+    // was recorded are cut off again.
+    pub(crate) fn abort(&mut self, file_len: u64) -> Result<(), io::Error> {
+        self.pool().discard_dirty();
+        self.pool().clear();
+        self.pending().clear();
+
+        if self.file.metadata()?.len() > file_len {
+            self.file.set_len(file_len)?;
+        } 
+        self.file_len = file_len;
+
+        self.page_map = PageMap::new();
+        self.index = BTree::new();
+        self.heap_tail = None;
+        self.blob_head = None;
+        self.map_pages.clear();
+        self.metadata_dirty = false;
+
+        self.load_metadata()
+    } 
+
+    pub fn begin(&mut self) -> Result<Transaction<'_>, io::Error> {
+        // Start from a clean slate so the transaction commits on its own
+        self.commit()?;
+
+        Transaction::new(self)
+    } 
+
+    // Point-in-time view of every key, see Snapshot. Outstanding changes
+    // are committed first so a snapshot only ever holds committed data.
+    pub fn snapshot(&mut self) -> Result<Snapshot, io::Error> {
+        self.commit()?;
+
+        let state = Arc::new(SnapshotState::new(self.id, self.index.clone(), now_millis(), self.file_len));
+        self.snapshots.retain(|snapshot| snapshot.strong_count() > 0);
+        self.snapshots.push(Arc::downgrade(&state));
+
+        Ok(Snapshot::new(state))
+    } 
+
+    // Old page images kept for live snapshots, counting shared ones once
+    pub fn retained_versions(&self) -> usize {
+        let mut pages: Vec<*const PageFormat> = self.snapshots.iter()
+            .filter_map(Weak::upgrade)
+            .flat_map(|snapshot| snapshot.saved_pages())
+            .map(|page| Arc::as_ptr(&page))
+            .collect();
+
+        pages.sort();
+        pages.dedup();
+        pages.len()
+    } 
+
+    // Saves the image of a page about to change for the snapshots that
+    // still read it
+    fn preserve_page(&mut self, location: u64) -> Result<(), io::Error> {
+        if self.snapshots.is_empty() {
+            return Ok(());
+        } 
+        self.snapshots.retain(|snapshot| snapshot.strong_count() > 0);
+
+        let waiting: Vec<Arc<SnapshotState>> = self.snapshots.iter()
+            .filter_map(Weak::upgrade)
+            .filter(|snapshot| snapshot.needs_page(location))
+            .collect();
+        if waiting.is_empty() {
+            return Ok(());
+        } 
+
+        let page = Arc::new(self.read_page_at(location)?);
+        for snapshot in waiting {
+            snapshot.save_page(location, page.clone());
+        } 
+
+        Ok(())
+    } 
+
+    fn replay_wal(&mut self) -> Result<(), io::Error> {
+        let images = self.wal.recover()?;
+        if images.is_empty() {
+            self.wal.truncate()?;
+            return Ok(());
+        } 
+
+        for (location, bytes) in images {
+            self.file.write_all_at(&bytes, location)?;
+        } 
+        self.file.sync_data()?;
+
+        self.wal.truncate()
+    } 
+
+    // Dirty pages evicted before commit are held back from the data file
+    fn write_back(&self, pages: Vec<(u64, PageFormat)>) {
+        let mut pending = self.pending();
+        for (location, page) in pages {
+            pending.insert(location, page);
+        } 
+    } 
+
+    // The page cache is shared by concurrent readers, see Db
+    fn pool(&self) -> MutexGuard<'_, BufferPool> {
+        self.pool.lock().unwrap_or_else(PoisonError::into_inner)
+    } 
+
+    fn pending(&self) -> MutexGuard<'_, BTreeMap<u64, PageFormat>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    } 
+
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool().stats()
+    } 
+
+    // Keeps a page cached until the matching unpin_page
+    pub fn pin_page(&mut self, id: &PageId) -> Result<(), io::Error> {
+        let location = self.get_page_location(id)
+            .ok_or(io::Error::other("Page not allocated"))?;
+
+        self.read_page_at(location)?;
+        self.pool().pin(location);
+
+        Ok(())
+    } 
+
+    pub fn unpin_page(&mut self, id: &PageId) -> bool {
+        match self.get_page_location(id) {
+            Some(location) => self.pool().unpin(location, false),
+            None => false,
+        } 
+    } 
+
+    pub fn close(mut self) -> Result<(), io::Error> {
+        self.flush()?;
+        self.checkpoint()
+    } 
+
+    fn load_metadata(&mut self) -> Result<(), io::Error> {
+        let superblock = Superblock::from_page(&self.read_page_at(SUPERBLOCK_LOCATION)?)?;
+        self.num_pages = superblock.num_pages;
+        self.next_page_id = superblock.next_page_id;
+        self.free_head = superblock.free_head;
+        self.index = BTree::open(decode_page_link(superblock.index_root));
+        self.heap_tail = decode_page_link(superblock.heap_tail);
+        self.blob_head = decode_page_link(superblock.blob_head);
+
+        // Walk the page map chain
+        let mut location = superblock.map_root;
+        while location != 0 {
+            let page = self.read_page_at(location)?;
+            if page.get_page_type() != PageType::PageMap {
+                return Err(StoreError::Corruption { msg: format!("Expected page map at offset {}", location) }.into());
+            } 
+
+            for (_, entry) in page.records() {
+                let (page_id, page_location) = decode_map_entry(entry)?;
+                self.page_map.map_page(page_id, page_location);
+            } 
+
+            self.map_pages.push(location);
+            location = page.get_next_page();
+        } 
+
+        // An empty hash index has no pages, but is still kept up to date
+        let hash_root = decode_page_link(superblock.hash_root);
+        if hash_root.is_some() || self.hash_index.is_some() {
+            self.hash_index = Some(HashIndex::open(&*self, hash_root)?);
+        } 
+
+        Ok(())
+    } 
+
+    fn write_metadata(&mut self) -> Result<(), io::Error> {
+        self.metadata_dirty = false;
+
+        let entries: Vec<Vec<u8>> = self.page_map.mappings
+            .iter()
+            .map(|(id, location)| encode_map_entry(id, *location))
+            .collect();
+
+        // Pack entries into as many page map pages as needed
+        let mut pages = vec![];
+        let mut page = PageFormat::new();
+        for entry in entries {
+            if page.insert_record(&entry).is_none() {
+                pages.push(page);
+                page = PageFormat::new();
+                page.insert_record(&entry);
+            } 
+        } 
+        if page.get_num_slots() > 0 {
+            pages.push(page);
+        } 
+
+        // Reuse the existing chain, growing it at the end of the file
+        while self.map_pages.len() < pages.len() {
+            let location = self.allocate_page_on_disk()?;
+            self.map_pages.push(location);
+        } 
+        while pages.len() < self.map_pages.len() {
+            pages.push(PageFormat::new());
+        } 
+
+        // Only pages whose entries changed get written, and so logged
+        for (i, page) in pages.iter_mut().enumerate() {
+            let next = self.map_pages.get(i + 1).copied().unwrap_or(0);
+            page.set_page_type(PageType::PageMap);
+            page.set_next_page(next);
+
+            self.write_page_if_changed(self.map_pages[i], page)?;
+        } 
+
+        let superblock = Superblock {
+            num_pages: self.num_pages,
+            next_page_id: self.next_page_id,
+            free_head: self.free_head,
+            map_root: self.map_pages.first().copied().unwrap_or(0),
+            index_root: encode_page_link(self.index.root()),
+            heap_tail: encode_page_link(self.heap_tail.as_ref()),
+            blob_head: encode_page_link(self.blob_head.as_ref()),
+            hash_root: encode_page_link(self.hash_index.as_ref().and_then(HashIndex::root)),
+        };
+
+        self.write_page_if_changed(SUPERBLOCK_LOCATION, &superblock.to_page())
+    } 
+
+    fn write_page_if_changed(&mut self, location: u64, page: &PageFormat) -> Result<(), io::Error> {
+        let unchanged = match self.read_page_at(location) {
+            Ok(current) => current.serialize() == page.serialize(),
+            Err(_) => false,
+        };
+        if unchanged {
+            return Ok(());
+        } 
+
+        self.write_page_at(location, page)
     } 
 
     pub fn metadata<E: Error>(&self) -> Result<StoreMetaData, MetaDataError> {
@@ -82,6 +566,15 @@ impl Store {
         &self.file
     } 
 
+    // Length the file will have once outstanding changes are committed
+    pub(crate) fn file_len(&self) -> u64 {
+        self.file_len
+    } 
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    } 
+
     fn map_page(&mut self, page_id: &PageId, location: u64) {
         let id = page_id.clone();
         self.page_map.map_page(id, location);
@@ -95,31 +588,95 @@ impl Store {
         self.num_pages
     } 
 
-    pub fn allocate_page(&mut self) -> PageId {
-        let id = PageId::new();
-        let cloned_id = id.clone();
-        let location = self.allocate_page_on_disk();
-        self.map_page(&cloned_id, location);
+    pub fn is_empty(&self) -> bool {
+        self.num_pages == 0
+    } 
 
-        if let Some(_loc) = self.get_page_location(&id) {
-            self.num_pages += 1;
-            id
-        } else {
-            panic!("Failed to allocate page");
-        } 
+    pub fn allocate_page(&mut self) -> Result<PageId, io::Error> {
+        let location = self.allocate_page_on_disk()?;
+
+        // Ids come from this store's counter, which is saved in the superblock
+        let id = PageId { id: self.next_page_id };
+        self.next_page_id += 1;
+        self.map_page(&id, location);
+        self.num_pages += 1;
+        self.metadata_dirty = true;
+
+        Ok(id)
     } 
 
-    fn allocate_page_on_disk(&mut self) -> u64 {
+    fn allocate_page_on_disk(&mut self) -> Result<u64, io::Error> {
         let page = PageFormat::new();
 
-        let page_location = self.file.metadata().unwrap().len();
+        // Reuse a freed page before growing the file
+        if self.free_head != 0 {
+            let page_location = self.free_head;
+            let free_page = self.read_page_at(page_location)?;
+            if free_page.get_page_type() != PageType::Free {
+                return Err(StoreError::Corruption { msg: format!("Free list points at a page in use at offset {}", page_location) }.into());
+            } 
+
+            self.write_page_at(page_location, &page)?;
+            self.free_head = free_page.get_next_page();
+            self.metadata_dirty = true;
+            return Ok(page_location);
+        } 
 
-        page.write_to_disk(&mut self.file).unwrap();
+        // New pages only reach the file through the log, at commit
+        let page_location = self.file_len;
+        self.write_page_at(page_location, &page)?;
+        self.file_len += PAGE_SIZE;
 
-        page_location
+        Ok(page_location)
     }
 
-    fn serialize_page(&self, page: &mut PageFormat) -> Vec<u8> {
+    // Returns a page to the free list so a later allocate_page can reuse it
+    pub fn free_page(&mut self, id: PageId) -> Result<(), io::Error> {
+        // Forget any records that lived on the page
+        let page = self.read_page(id.clone())?;
+        if page.get_page_type() == PageType::Data {
+            for (_, record) in page.records() {
+                let record = decode_record(record)?;
+                self.index_remove(&record.key)?;
+
+                if let Some(head) = record.value.overflow_head() {
+                    self.release_overflow(head)?;
+                } 
+            } 
+        } 
+
+        self.release_page(id)?;
+        self.commit()
+    } 
+
+    fn release_page(&mut self, id: PageId) -> Result<(), io::Error> {
+        let location = self.get_page_location(&id)
+            .ok_or(io::Error::other("Page not allocated"))?;
+
+        self.preserve_page(location)?;
+        for snapshot in self.snapshots.iter().filter_map(Weak::upgrade) {
+            snapshot.save_location(&id, location);
+        } 
+
+        // Freed pages are chained together through their next page link
+        let mut page = PageFormat::new();
+        page.set_page_type(PageType::Free);
+        page.set_next_page(self.free_head);
+        self.write_page_at(location, &page)?;
+
+        self.free_head = location;
+        self.page_map.unmap_page(&id);
+        self.num_pages -= 1;
+        self.metadata_dirty = true;
+
+        if self.heap_tail.as_ref() == Some(&id) {
+            self.heap_tail = None;
+        } 
+
+        Ok(())
+    } 
+
+    fn serialize_page(&self, page: &PageFormat) -> Vec<u8> {
         page.serialize()
     }
 
@@ -128,279 +685,1973 @@ impl Store {
     }
 
 
-    pub fn read_page(&mut self, id: PageId) -> Result<PageFormat, io::Error> {
+    pub fn read_page(&self, id: PageId) -> Result<PageFormat, io::Error> {
         // Get allocated location
         let location = self.get_page_location(&id)
-            .ok_or(io::Error::new(ErrorKind::Other, "Page not allocated"))?;
+            .ok_or(io::Error::other("Page not allocated"))?;
+
+        self.read_page_at(location)
+    }
+
+    fn read_page_at(&self, location: u64) -> Result<PageFormat, io::Error> {
+        if let Some(page) = self.pool().get(location) {
+            return Ok(page.clone());
+        } 
+
+        if let Some(page) = self.pending().get(&location) {
+            return Ok(page.clone());
+        } 
+
+        // Readers missing the same page share a single read of it
+        self.latches.with_latch(location, || {
+            if let Some(page) = self.pool().peek(location) {
+                return Ok(page.clone());
+            } 
+
+            let page = self.read_page_from_disk(location)?;
+
+            let evicted = self.pool().insert(location, page.clone(), false);
+            self.write_back(evicted);
 
-        // Seek to lcoation 
-        self.file.seek(SeekFrom::Start(location))?;
+            Ok(page)
+        })
+    } 
 
-        // Read page bytes from file 
+    fn read_page_from_disk(&self, location: u64) -> Result<PageFormat, io::Error> {
+        // Positional reads leave the file offset alone, so readers on
+        // other threads can't move it from under each other
         let mut bytes = vec![0; 4096];
-        self.file.read_exact(&mut bytes)?;
+        self.file.read_exact_at(&mut bytes, location)?;
+
+        // Verify checksum before trusting the bytes
+        if !PageFormat::verify_checksum(&bytes) {
+            return Err(StoreError::Corruption { msg: format!("Checksum mismatch for page at offset {}", location) }.into());
+        } 
 
         // Deserialize bytes to page 
-        let page = PageFormat::deserialize(bytes);
+        let page = self.deserialize_page(bytes);
 
         Ok(page)
     }
 
     pub fn write_page(&mut self, page: &PageFormat, id: &PageId) -> Result<(), io::Error> {
+        self.put_page(page, id)?;
+        self.commit()
+    } 
+
+    // Buffers a page write without committing it
+    fn put_page(&mut self, page: &PageFormat, id: &PageId) -> Result<(), io::Error> {
         // Get the allocated location for this page id 
         let location = self.get_page_location(id)
-            .ok_or(io::Error::new(ErrorKind::Other, "Page not allocated"))?;
+            .ok_or(io::Error::other("Page not allocated"))?;
+
+        self.preserve_page(location)?;
+        self.write_page_at(location, page)
+    } 
 
-        // Seek to the location in the file 
-        self.file.seek(SeekFrom::Start(location))?;
+    // Writes land in the buffer pool and reach the file on eviction or flush
+    fn write_page_at(&mut self, location: u64, page: &PageFormat) -> Result<(), io::Error> {
+        let evicted = self.pool().insert(location, page.clone(), true);
+        self.write_back(evicted);
 
-        // Serialize the page to get the bytes 
-        let bytes = page.serialize();
+        Ok(())
+    } 
 
+    fn write_page_to_disk(&mut self, location: u64, page: &PageFormat) -> Result<(), io::Error> {
+        // Serialize the page to get the bytes 
+        let bytes = self.serialize_page(page);
 
         // Write bytes to file 
-        self.file.write_all(&bytes)?;
+        self.file.write_all_at(&bytes, location)?;
 
         Ok(())
     } 
 
-}
+    pub fn insert<T: Encode>(&mut self, key: Key, value: Value<T>) -> Result<(), io::Error> {
+        self.insert_entry(key, &value.get().encode_value(), None)?;
+        self.commit()
+    } 
 
-#[derive(Debug)]
-pub struct MetaDataError {
-    details: String, 
-} 
+    // Inserts a value that reads as absent once ttl has passed
+    pub fn put_with_ttl<T: Encode>(&mut self, key: Key, value: Value<T>, ttl: Duration) -> Result<(), io::Error> {
+        self.insert_entry(key, &value.get().encode_value(), Some(expiry_after(ttl)))?;
+        self.commit()
+    } 
 
-impl fmt::Display for MetaDataError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Metadata error: {}", self.details)
-    }
-} 
+    // Makes an existing key expire ttl from now, returning false if there
+    // is no such key
+    pub fn expire(&mut self, key: &Key, ttl: Duration) -> Result<bool, io::Error> {
+        let found = self.expire_entry(key, expiry_after(ttl))?;
+        self.commit()?;
 
-impl Error for MetaDataError {
-    fn description(&self) -> &str {
-        &self.details
-    }
-} 
+        Ok(found)
+    } 
 
-impl From<std::io::Error> for MetaDataError {
-    fn from(error: std::io::Error) -> Self { 
-        MetaDataError {
-            details: error.to_string()
+    pub(crate) fn insert_entry(&mut self, key: Key, value: &[u8], expires_at: Option<u64>) -> Result<(), io::Error> {
+        if key.len() > MAX_KEY_SIZE {
+            return Err(StoreError::Serialization(format!("Key longer than {} bytes", MAX_KEY_SIZE)).into());
         } 
-    }
-} 
-
 
-pub struct StoreMetaData {
-    capacity: u64,
-    num_entries: u32,
-    signature: u64,
-    version: u32
-} 
+        let stored = self.store_value(record_header_len(key.len(), expires_at), value)?;
+        self.insert_stored(key, &stored, expires_at)
+    } 
 
-impl StoreMetaData {
+    pub(crate) fn expire_entry(&mut self, key: &Key, expires_at: u64) -> Result<bool, io::Error> {
+        let (page, slot) = match self.view().find_live(key)? {
+            Some(found) => found,
+            None => return Ok(false),
+        };
 
-    pub fn new() -> StoreMetaData {
-        StoreMetaData {
-          capacity: 0,
-          num_entries: 0,
-          signature: 0,
-          version: 0  
-        }
-    }
+        // The value stays where it is, only the record around it changes
+        let record = read_indexed_record(&page, slot, key)?;
+        self.insert_stored(key.clone(), &record.value, Some(expires_at))?;
 
-    pub fn get_capacity(&self) -> u64 {
-        self.capacity
+        Ok(true)
     } 
 
-    pub fn get_entries(&self) -> u32 {
-        self.num_entries
-    } 
+    // Points key at an already stored value, replacing what it held before
+    fn insert_stored(&mut self, key: Key, stored: &StoredValue, expires_at: Option<u64>) -> Result<(), io::Error> {
+        let record = encode_record(&key, stored, expires_at);
 
-    pub fn get_signature(&self) -> u64 {
-        self.signature
-    } 
-    
-    pub fn get_version(&self) -> u32 {
-        self.version
-    } 
+        // Overwrite in place when the new record still fits on the same page
+        if let Some(record_id) = self.view().index_get(&key)? {
+            let mut page = self.read_page(record_id.page_id.clone())?;
 
-    pub fn calculate_signature(&self) -> u64 {
-        let mut hasher = DefaultHasher::new();
+            // A chain the new record still points at must not be freed
+            let old_head = read_indexed_record(&page, record_id.slot, &key)?.value.overflow_head()
+                .filter(|head| stored.overflow_head().as_ref() != Some(head));
 
-        // Hash pieces of metadata using Hasher
+            if page.update_record(record_id.slot, &record) {
+                self.put_page(&page, &record_id.page_id)?;
+
+                if let Some(head) = old_head {
+                    self.release_overflow(head)?;
+                } 
+                return Ok(());
+            } 
+
+            self.index_remove(&key)?;
+            self.remove_record(page, &record_id)?;
+
+            if let Some(head) = old_head {
+                self.release_overflow(head)?;
+            } 
+        } 
+
+        // Try the most recent data page before allocating a new one
+        if let Some(page_id) = self.heap_tail.clone() {
+            let mut page = self.read_page(page_id.clone())?;
+
+            if let Some(slot) = page.insert_record(&record) {
+                self.put_page(&page, &page_id)?;
+                return self.index_insert(&key, &RecordId { page_id, slot });
+            } 
+        } 
+
+        let page_id = self.allocate_page()?;
+        let mut page = PageFormat::new();
+        page.set_page_type(PageType::Data);
+        let slot = page.insert_record(&record)
+            .ok_or(StoreError::Serialization("Record too large for page".to_string()))?;
+        self.put_page(&page, &page_id)?;
+
+        self.heap_tail = Some(page_id.clone());
+        self.metadata_dirty = true;
+
+        self.index_insert(&key, &RecordId { page_id, slot })
+    } 
+
+    pub fn get(&self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        self.view().get(key)
+    } 
+
+    // Decodes the stored bytes as T
+    pub fn get_as<T: Decode>(&self, key: &Key) -> Result<Option<Value<T>>, io::Error> {
+        match self.get(key)? {
+            Some(value) => Ok(Some(Value::new(T::decode_value(value.get())?))),
+            None => Ok(None),
+        } 
+    } 
+
+    // Stores any serde type in bincode's compact binary format
+    #[cfg(feature = "serde")]
+    pub fn insert_serde<T: serde::Serialize>(&mut self, key: Key, value: Value<T>) -> Result<(), io::Error> {
+        let bytes = bincode::serialize(value.get())
+            .map_err(|e| StoreError::Serialization(e.to_string()))?;
+
+        self.insert(key, Value::new(bytes))
+    } 
+
+    #[cfg(feature = "serde")]
+    pub fn get_serde<T: serde::de::DeserializeOwned>(&self, key: &Key) -> Result<Option<Value<T>>, io::Error> {
+        Ok(self.get_as::<Bincode<T>>(key)?.map(|value| Value::new(value.into_inner().0)))
+    } 
+
+    pub fn delete(&mut self, key: &Key) -> Result<bool, io::Error> {
+        let deleted = self.delete_entry(key)?;
+        self.commit()?;
+
+        Ok(deleted)
+    } 
+
+    pub(crate) fn delete_entry(&mut self, key: &Key) -> Result<bool, io::Error> {
+        let record_id = match self.index_remove(key)? {
+            Some(record_id) => record_id,
+            None => return Ok(false),
+        };
+
+        let page = self.read_page(record_id.page_id.clone())?;
+        let old_head = read_indexed_record(&page, record_id.slot, key)?.value.overflow_head();
+
+        self.remove_record(page, &record_id)?;
+
+        if let Some(head) = old_head {
+            self.release_overflow(head)?;
+        } 
+
+        Ok(true)
+    } 
+
+    fn remove_record(&mut self, mut page: PageFormat, record_id: &RecordId) -> Result<(), io::Error> {
+        page.delete_record(record_id.slot);
+
+        // Give empty data pages back to the free list
+        if page.records().is_empty() {
+            self.release_page(record_id.page_id.clone())
+        } else {
+            self.put_page(&page, &record_id.page_id)
+        } 
+    } 
+
+    // Deletes every expired record, giving back its slot space and any
+    // overflow pages, and returns how many there were
+    pub fn sweep_expired(&mut self) -> Result<usize, io::Error> {
+        let now = now_millis();
+        let mut expired = vec![];
+
+        let mut cursor = self.index.range(Bound::Unbounded, Bound::Unbounded);
+        while let Some((_, entry)) = cursor.next(self)? {
+            let record_id = decode_record_id(&entry)?;
+            let page = self.read_page(record_id.page_id)?;
+
+            let record = page.read_record(record_id.slot)
+                .ok_or(StoreError::Corruption { msg: "Index points at a missing record".to_string() })?;
+            let record = decode_record(record)?;
+            if record.is_expired(now) {
+                expired.push(record.key);
+            } 
+        } 
+
+        for key in &expired {
+            self.delete_entry(key)?;
+        } 
+        self.commit()?;
+
+        Ok(expired.len())
+    } 
+
+    // Values too big to share a data page are split across a chain of
+    // overflow pages, leaving only the chain's head in the record
+    fn store_value<'a>(&mut self, header_len: usize, value: &'a [u8]) -> Result<StoredValue<'a>, io::Error> {
+        if header_len + value.len() <= PageFormat::max_record_size() {
+            return Ok(StoredValue::Inline(value));
+        } 
+
+        let chunks: Vec<&[u8]> = value.chunks(PageFormat::max_record_size()).collect();
+        let ids = chunks.iter().map(|_| self.allocate_page()).collect::<Result<Vec<PageId>, _>>()?;
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            self.put_overflow_page(&ids[i], chunk, ids.get(i + 1))?;
+        } 
+
+        Ok(StoredValue::Overflow { len: value.len() as u64, head: ids[0].clone() })
+    } 
+
+    pub(crate) fn put_overflow_page(&mut self, id: &PageId, chunk: &[u8], next: Option<&PageId>) -> Result<(), io::Error> {
+        let mut page = PageFormat::new();
+        page.set_page_type(PageType::Overflow);
+        page.set_next_page(encode_page_link(next));
+        page.insert_record(chunk)
+            .ok_or(StoreError::Serialization("Overflow chunk too large for page".to_string()))?;
+
+        self.put_page(&page, id)
+    } 
+
+    pub(crate) fn release_overflow(&mut self, head: PageId) -> Result<(), io::Error> {
+        let mut next = Some(head);
+        while let Some(id) = next {
+            next = self.read_overflow_chunk(&id)?.1;
+            self.release_page(id)?;
+        } 
+
+        Ok(())
+    } 
+
+    pub(crate) fn read_overflow_chunk(&self, id: &PageId) -> Result<(Vec<u8>, Option<PageId>), io::Error> {
+        self.view().read_overflow_chunk(id)
+    } 
+
+    // Streams a value into the store, replacing key's value on finish
+    pub fn blob_writer(&mut self, key: Key) -> Result<BlobWriter<'_>, io::Error> {
+        self.commit()?;
+
+        Ok(BlobWriter::new(self, key))
+    } 
+
+    // Records the chain a BlobWriter is building, so that a crash before
+    // finish frees it on the next open
+    pub(crate) fn set_blob_head(&mut self, head: Option<PageId>) {
+        self.blob_head = head;
+        self.metadata_dirty = true;
+    } 
+
+    pub(crate) fn finish_blob(&mut self, key: Key, len: u64, head: Option<PageId>) -> Result<(), io::Error> {
+        self.set_blob_head(None);
+        match head {
+            Some(head) => self.insert_stored(key, &StoredValue::Overflow { len, head }, None)?,
+            None => self.insert_stored(key, &StoredValue::Inline(&[]), None)?,
+        } 
+
+        self.commit()
+    } 
+
+    // Streams a value back out without loading it whole
+    pub fn blob_reader(&mut self, key: &Key) -> Result<Option<BlobReader<'_>>, io::Error> {
+        let (page, slot) = match self.view().find_live(key)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let source = match read_indexed_record(&page, slot, key)?.value {
+            StoredValue::Inline(value) => BlobSource::Inline(value.to_vec()),
+            StoredValue::Overflow { len, head } => BlobSource::Chain { len, head },
+        };
+
+        Ok(Some(BlobReader::new(self, source)))
+    } 
+
+    pub fn contains(&self, key: &Key) -> Result<bool, io::Error> {
+        Ok(self.view().find_live(key)?.is_some())
+    } 
+
+    // Every key/value pair in key order
+    pub fn iter(&self) -> Iter<'_> {
+        self.range(..)
+    } 
+
+    pub fn range<R: RangeBounds<Key>>(&self, range: R) -> Iter<'_> {
+        self.view().range(range)
+    } 
+
+    // Keys whose bytes start with prefix
+    pub fn prefix(&self, prefix: &[u8]) -> Iter<'_> {
+        self.view().prefix(prefix)
+    } 
+
+    // Time ordered keys created from start up to but not including end,
+    // to the millisecond. Other keys that happen to sort between the two
+    // bounds are skipped.
+    pub fn created_between(&self, start: SystemTime, end: SystemTime) -> impl DoubleEndedIterator<Item = Result<KeyValue, io::Error>> + '_ {
+        self.range(Key::time_ordered_bound(start)..Key::time_ordered_bound(end))
+            .filter(|entry| match entry {
+                Ok((key, _)) => key.timestamp().is_some(),
+                Err(_) => true,
+            })
+    } 
+
+    fn index_insert(&mut self, key: &Key, record_id: &RecordId) -> Result<(), io::Error> {
+        let mut index = self.index.clone();
+        index.insert(self, key.as_bytes(), &encode_record_id(record_id))?;
+        self.set_index(index);
+
+        if let Some(mut hash_index) = self.hash_index.clone() {
+            hash_index.insert(self, key.as_bytes(), &encode_record_id(record_id))?;
+            self.set_hash_index(hash_index);
+        } 
+
+        Ok(())
+    } 
+
+    fn index_remove(&mut self, key: &Key) -> Result<Option<RecordId>, io::Error> {
+        let mut index = self.index.clone();
+        let entry = index.remove(self, key.as_bytes())?;
+        self.set_index(index);
+
+        if let Some(mut hash_index) = self.hash_index.clone() {
+            hash_index.remove(self, key.as_bytes())?;
+            self.set_hash_index(hash_index);
+        } 
+
+        match entry {
+            Some(entry) => Ok(Some(decode_record_id(&entry)?)),
+            None => Ok(None),
+        } 
+    } 
+
+    // A new root has to reach the superblock
+    fn set_index(&mut self, index: BTree) {
+        if index != self.index {
+            self.index = index;
+            self.metadata_dirty = true;
+        } 
+    } 
+
+    fn set_hash_index(&mut self, hash_index: HashIndex) {
+        if hash_index.root() != self.hash_index.as_ref().and_then(HashIndex::root) {
+            self.metadata_dirty = true;
+        } 
+        self.hash_index = Some(hash_index);
+    } 
+
+    // Reads of committed and uncommitted changes alike
+    fn view(&self) -> PageView<'_> {
+        PageView::new(self, None)
+    } 
+
+}
+
+// Location of a key/value record: the data page and its slot number
+#[derive(Clone)]
+struct RecordId {
+    page_id: PageId,
+    slot: u16,
+}
+
+impl Pager for Store {
+    fn allocate(&mut self) -> Result<PageId, io::Error> {
+        self.allocate_page()
+    } 
+
+    fn read(&self, id: &PageId) -> Result<PageFormat, io::Error> {
+        self.read_page(id.clone())
+    } 
+
+    fn write(&mut self, id: &PageId, page: &PageFormat) -> Result<(), io::Error> {
+        self.put_page(page, id)
+    } 
+
+    fn free(&mut self, id: PageId) -> Result<(), io::Error> {
+        self.release_page(id)
+    } 
+} 
+
+// Read access to a store's pages, either as they are now or as they were
+// when a snapshot was taken. Everything that reads keys goes through one.
+#[derive(Clone, Copy)]
+pub(crate) struct PageView<'a> {
+    store: &'a Store,
+    snapshot: Option<&'a SnapshotState>,
+}
+
+impl<'a> PageView<'a> {
+    pub(crate) fn new(store: &'a Store, snapshot: Option<&'a SnapshotState>) -> Self {
+        PageView { store, snapshot }
+    } 
+
+    fn index(&self) -> &'a BTree {
+        match self.snapshot {
+            Some(snapshot) => snapshot.index(),
+            None => &self.store.index,
+        } 
+    } 
+
+    // Snapshots keep judging expiry at the time they were taken
+    fn now(&self) -> u64 {
+        self.snapshot.map_or_else(now_millis, SnapshotState::taken_at)
+    } 
+
+    fn read_page(&self, id: &PageId) -> Result<PageFormat, io::Error> {
+        let snapshot = match self.snapshot {
+            Some(snapshot) => snapshot,
+            None => return self.store.read_page(id.clone()),
+        };
+
+        let location = snapshot.location(id)
+            .or_else(|| self.store.get_page_location(id))
+            .ok_or(io::Error::other("Page not allocated"))?;
+
+        match snapshot.page(location) {
+            Some(page) => Ok(page),
+            None => self.store.read_page_at(location),
+        } 
+    } 
+
+    pub(crate) fn get(&self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        let (page, slot) = match self.find_live(key)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let record = read_indexed_record(&page, slot, key)?;
+        Ok(Some(Value::new(self.load_value(record.value)?)))
+    } 
+
+    // Page and slot of key's record, unless there is none or it expired
+    pub(crate) fn find_live(&self, key: &Key) -> Result<Option<(PageFormat, u16)>, io::Error> {
+        let record_id = match self.index_get(key)? {
+            Some(record_id) => record_id,
+            None => return Ok(None),
+        };
+
+        let page = self.read_page(&record_id.page_id)?;
+        if read_indexed_record(&page, record_id.slot, key)?.is_expired(self.now()) {
+            return Ok(None);
+        } 
+
+        Ok(Some((page, record_id.slot)))
+    } 
+
+    // Snapshots look keys up in their own B+tree, the hash index is only
+    // ever current
+    fn index_get(&self, key: &Key) -> Result<Option<RecordId>, io::Error> {
+        let entry = match (self.snapshot, &self.store.hash_index) {
+            (None, Some(hash_index)) => hash_index.get(self, key.as_bytes())?,
+            _ => self.index().get(self, key.as_bytes())?,
+        };
+
+        match entry {
+            Some(entry) => Ok(Some(decode_record_id(&entry)?)),
+            None => Ok(None),
+        } 
+    } 
+
+    pub(crate) fn range<R: RangeBounds<Key>>(self, range: R) -> Iter<'a> {
+        let cursor = self.range_cursor(range);
+        Iter::new(self, cursor)
+    } 
+
+    pub(crate) fn prefix(self, prefix: &[u8]) -> Iter<'a> {
+        let cursor = self.prefix_cursor(prefix);
+        Iter::new(self, cursor)
+    } 
+
+    pub(crate) fn range_cursor<R: RangeBounds<Key>>(&self, range: R) -> Cursor {
+        let lower = range.start_bound().map(|key| key.as_bytes().to_vec());
+        let upper = range.end_bound().map(|key| key.as_bytes().to_vec());
+
+        self.index().range(lower, upper)
+    } 
+
+    pub(crate) fn prefix_cursor(&self, prefix: &[u8]) -> Cursor {
+        let (lower, upper) = prefix_bounds(prefix);
+
+        self.index().range(lower, upper)
+    } 
+
+    // Reads the record an index entry points at, or None if it expired
+    pub(crate) fn load_indexed(&self, entry: &[u8]) -> Result<Option<KeyValue>, io::Error> {
+        let record_id = decode_record_id(entry)?;
+        let page = self.read_page(&record_id.page_id)?;
+
+        let record = page.read_record(record_id.slot)
+            .ok_or(StoreError::Corruption { msg: "Index points at a missing record".to_string() })?;
+        let record = decode_record(record)?;
+        if record.is_expired(self.now()) {
+            return Ok(None);
+        } 
+
+        let value = self.load_value(record.value)?;
+        Ok(Some((record.key, Value::new(value))))
+    } 
+
+    fn load_value(&self, stored: StoredValue) -> Result<Vec<u8>, io::Error> {
+        let (len, head) = match stored {
+            StoredValue::Inline(value) => return Ok(value.to_vec()),
+            StoredValue::Overflow { len, head } => (len as usize, head),
+        };
+
+        let mut value = Vec::with_capacity(len);
+        let mut next = Some(head);
+        while let Some(id) = next {
+            let (chunk, link) = self.read_overflow_chunk(&id)?;
+            value.extend_from_slice(&chunk);
+            next = link;
+        } 
+
+        if value.len() != len {
+            return Err(StoreError::Corruption { msg: "Overflow chain length mismatch".to_string() }.into());
+        } 
+
+        Ok(value)
+    } 
+
+    // Bytes held by one overflow page and the page after it in the chain
+    pub(crate) fn read_overflow_chunk(&self, id: &PageId) -> Result<(Vec<u8>, Option<PageId>), io::Error> {
+        let page = self.read_page(id)?;
+        if page.get_page_type() != PageType::Overflow {
+            return Err(StoreError::Corruption { msg: "Overflow chain points at a non-overflow page".to_string() }.into());
+        } 
+
+        let chunk = page.read_record(0).unwrap_or(&[]).to_vec();
+        Ok((chunk, decode_page_link(page.get_next_page())))
+    } 
+} 
+
+// Views only read, the store itself does all the writing
+impl Pager for PageView<'_> {
+    fn allocate(&mut self) -> Result<PageId, io::Error> {
+        Err(io::Error::other("Page views are read only"))
+    } 
+
+    fn read(&self, id: &PageId) -> Result<PageFormat, io::Error> {
+        self.read_page(id)
+    } 
+
+    fn write(&mut self, _id: &PageId, _page: &PageFormat) -> Result<(), io::Error> {
+        Err(io::Error::other("Page views are read only"))
+    } 
+
+    fn free(&mut self, _id: PageId) -> Result<(), io::Error> {
+        Err(io::Error::other("Page views are read only"))
+    } 
+} 
+
+fn encode_record_id(record_id: &RecordId) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(RECORD_ID_SIZE);
+
+    entry.extend_from_slice(&record_id.page_id.as_u64().to_le_bytes());
+    entry.extend_from_slice(&record_id.slot.to_le_bytes());
+
+    entry
+} 
+
+fn decode_record_id(entry: &[u8]) -> Result<RecordId, StoreError> {
+    if entry.len() != RECORD_ID_SIZE {
+        return Err(StoreError::Corruption { msg: "Bad index entry".to_string() });
+    } 
+
+    Ok(RecordId {
+        page_id: PageId::from_u64(u64::from_le_bytes(entry[..8].try_into().unwrap())),
+        slot: u16::from_le_bytes(entry[8..].try_into().unwrap()),
+    })
+} 
+
+// Optional page ids are saved as the id plus one so zero can mean none
+fn encode_page_link(page_id: Option<&PageId>) -> u64 {
+    page_id.map(|id| id.as_u64() + 1).unwrap_or(0)
+} 
+
+fn decode_page_link(link: u64) -> Option<PageId> {
+    match link {
+        0 => None,
+        link => Some(PageId::from_u64(link - 1)),
+    } 
+} 
+
+fn encode_map_entry(page_id: &PageId, location: u64) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(PAGE_MAP_ENTRY_SIZE);
+
+    entry.extend_from_slice(&page_id.id.to_le_bytes());
+    entry.extend_from_slice(&location.to_le_bytes());
+
+    entry
+} 
+
+fn decode_map_entry(entry: &[u8]) -> Result<(PageId, u64), StoreError> {
+    if entry.len() != PAGE_MAP_ENTRY_SIZE {
+        return Err(StoreError::Corruption { msg: "Bad page map entry".to_string() });
+    } 
+
+    let id = u64::from_le_bytes(entry[..8].try_into().unwrap());
+    let location = u64::from_le_bytes(entry[8..].try_into().unwrap());
+
+    Ok((PageId { id }, location))
+} 
+
+// Value part of a data page record, either the bytes themselves or the
+// length and first page of an overflow chain
+enum StoredValue<'a> {
+    Inline(&'a [u8]),
+    Overflow { len: u64, head: PageId },
+}
+
+impl StoredValue<'_> {
+    fn overflow_head(&self) -> Option<PageId> {
+        match self {
+            StoredValue::Inline(_) => None,
+            StoredValue::Overflow { head, .. } => Some(head.clone()),
+        } 
+    } 
+} 
+
+// A data page record, borrowing its inline value from the page
+struct Record<'a> {
+    key: Key,
+    value: StoredValue<'a>,
+    expires_at: Option<u64>,
+}
+
+impl Record<'_> {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    } 
+} 
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+} 
+
+pub(crate) fn expiry_after(ttl: Duration) -> u64 {
+    now_millis().saturating_add(ttl.as_millis() as u64)
+} 
+
+// Bytes a record spends before its value
+fn record_header_len(key_len: usize, expires_at: Option<u64>) -> usize {
+    let expiry_len = if expires_at.is_some() { EXPIRY_SIZE } else { 0 };
+
+    KEY_LEN_SIZE + key_len + 1 + expiry_len
+} 
+
+// Records are the length prefixed key, a value kind byte, the expiry time
+// if the kind byte says there is one, then the inline value or the
+// overflow chain's length and head page id
+fn encode_record(key: &Key, stored: &StoredValue, expires_at: Option<u64>) -> Vec<u8> {
+    let mut record = Vec::with_capacity(record_header_len(key.len(), expires_at) + OVERFLOW_REF_SIZE);
+
+    record.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    record.extend_from_slice(key.as_bytes());
+
+    let kind = match stored {
+        StoredValue::Inline(_) => VALUE_INLINE,
+        StoredValue::Overflow { .. } => VALUE_OVERFLOW,
+    };
+    match expires_at {
+        Some(expires_at) => {
+            record.push(kind | VALUE_EXPIRES);
+            record.extend_from_slice(&expires_at.to_le_bytes());
+        } 
+        None => record.push(kind),
+    } 
+
+    match stored {
+        StoredValue::Inline(value) => record.extend_from_slice(value),
+        StoredValue::Overflow { len, head } => {
+            record.extend_from_slice(&len.to_le_bytes());
+            record.extend_from_slice(&head.as_u64().to_le_bytes());
+        } 
+    } 
+
+    record
+} 
+
+fn decode_record(record: &[u8]) -> Result<Record<'_>, StoreError> {
+    if record.len() < KEY_LEN_SIZE {
+        return Err(StoreError::Corruption { msg: "Record shorter than key".to_string() });
+    } 
+
+    let key_end = KEY_LEN_SIZE + u16::from_le_bytes([record[0], record[1]]) as usize;
+    if record.len() < key_end + 1 {
+        return Err(StoreError::Corruption { msg: "Record shorter than key".to_string() });
+    } 
+
+    let key = Key::from_bytes(&record[KEY_LEN_SIZE..key_end]);
+    let kind = record[key_end];
+
+    let mut body = &record[key_end + 1..];
+    let mut expires_at = None;
+    if kind & VALUE_EXPIRES != 0 {
+        if body.len() < EXPIRY_SIZE {
+            return Err(StoreError::Corruption { msg: "Record shorter than its expiry".to_string() });
+        } 
+
+        expires_at = Some(u64::from_le_bytes(body[..EXPIRY_SIZE].try_into().unwrap()));
+        body = &body[EXPIRY_SIZE..];
+    } 
+
+    let value = match kind & !VALUE_EXPIRES {
+        VALUE_INLINE => StoredValue::Inline(body),
+        VALUE_OVERFLOW if body.len() == OVERFLOW_REF_SIZE => StoredValue::Overflow {
+            len: u64::from_le_bytes(body[..8].try_into().unwrap()),
+            head: PageId::from_u64(u64::from_le_bytes(body[8..].try_into().unwrap())),
+        },
+        _ => return Err(StoreError::Corruption { msg: "Unknown record value kind".to_string() }),
+    };
+
+    Ok(Record { key, value, expires_at })
+} 
+
+// Returns the record in slot, checking it belongs to key
+fn read_indexed_record<'a>(page: &'a PageFormat, slot: u16, key: &Key) -> Result<Record<'a>, StoreError> {
+    let record = page.read_record(slot)
+        .ok_or(StoreError::Corruption { msg: "Indexed record missing from page".to_string() })?;
+
+    let record = decode_record(record)?;
+    if &record.key != key {
+        return Err(StoreError::Corruption { msg: "Indexed slot holds a different key".to_string() });
+    } 
+
+    Ok(record)
+} 
+
+#[derive(Debug)]
+pub struct MetaDataError {
+    details: String, 
+} 
+
+impl fmt::Display for MetaDataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Metadata error: {}", self.details)
+    }
+} 
+
+impl Error for MetaDataError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+} 
+
+impl From<std::io::Error> for MetaDataError {
+    fn from(error: std::io::Error) -> Self { 
+        MetaDataError {
+            details: error.to_string()
+        } 
+    }
+} 
+
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StoreMetaData {
+    capacity: u64,
+    num_entries: u32,
+    signature: u64,
+    version: u32
+} 
+
+impl Default for StoreMetaData {
+    fn default() -> Self {
+        Self::new()
+    }
+} 
+
+impl StoreMetaData {
+
+    pub fn new() -> StoreMetaData {
+        StoreMetaData {
+          capacity: 0,
+          num_entries: 0,
+          signature: 0,
+          version: 0  
+        }
+    }
+
+    pub fn get_capacity(&self) -> u64 {
+        self.capacity
+    } 
+
+    pub fn get_entries(&self) -> u32 {
+        self.num_entries
+    } 
+
+    pub fn get_signature(&self) -> u64 {
+        self.signature
+    } 
+    
+    pub fn get_version(&self) -> u32 {
+        self.version
+    } 
+
+    pub fn calculate_signature(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        // Hash pieces of metadata using Hasher
         hasher.write(&self.capacity.to_ne_bytes());
         hasher.write(&self.num_entries.to_ne_bytes());
         hasher.write(&self.version.to_ne_bytes());
 
-        // Finish and return has as u64
-        hasher.finish()
-    }
-}
+        // Finish and return has as u64
+        hasher.finish()
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct PageMap {
+    mappings: BTreeMap<PageId, u64>
+} 
+
+
+#[derive(Clone, Debug, Eq, PartialOrd, Ord)]
+pub struct PageId {
+    id: u64
+}
+
+impl PartialEq for PageId {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    } 
+}
+
+impl PageId {
+
+    // Raw id, suitable for storing page references inside other pages
+    pub fn as_u64(&self) -> u64 {
+        self.id
+    } 
+
+    pub fn from_u64(id: u64) -> Self {
+        Self { id }
+    } 
+
+    fn clone(&self) -> Self {
+        Self { id: self.id }
+    } 
+} 
+
+impl PageMap {
+    fn new() -> Self {
+        Self {
+            mappings: BTreeMap::new()
+        } 
+    } 
+
+    fn map_page(&mut self, page_id: PageId, location: u64) {
+        self.mappings.insert(page_id, location);
+    } 
+
+    fn get_location(&self, page_id: &PageId) -> Option<u64> {
+        self.mappings.get(page_id).copied()
+    } 
+
+    fn unmap_page(&mut self, page_id: &PageId) -> Option<u64> {
+        self.mappings.remove(page_id)
+    } 
+} 
+
+
+#[cfg(test)]
+#[allow(clippy::needless_borrow, clippy::unnecessary_mut_passed)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::SeekFrom;
+
+    #[test] 
+    fn test_open_and_create_store() {
+        let tmp_dir = env::temp_dir();
+        let store_path = tmp_dir.join("test_store_open_and_create_store");
+        let _ = std::fs::remove_file(&store_path);
+        let path = Path::new(&store_path);
+
+        let _store = Store::new(&path).unwrap();
+
+        // Fiel should now exist 
+        assert!(store_path.exists());
+    } 
+
+    #[test]
+    fn test_write_and_read_page() {
+        let tmp_dir = env::temp_dir();
+        let store_path = tmp_dir.join("test_store_write_and_read_page");
+        let _ = std::fs::remove_file(&store_path);
+        let path = Path::new(&store_path);
+
+        let mut store = Store::new(&path).unwrap();
+
+        let id = store.allocate_page().unwrap();
+
+        let mut page = PageFormat::new();
+
+        // Write Page 
+        store.write_page(&mut page, &id).unwrap();
+
+        // read page 
+        let read = store.read_page(id).unwrap();
+
+        assert_eq!(page, read);
+    } 
+
+    #[test]
+    fn test_serialize_page() {
+        // Create store 
+        let tmp_dir = env::temp_dir();
+        let store_path = tmp_dir.join("test_store_serialize_page");
+        let _ = std::fs::remove_file(&store_path);
+        let path = Path::new(&store_path);
+        let store = Store::new(&path).unwrap();
+
+        // Create page 
+        let mut page = PageFormat::new();
+
+        //Serialize Page 
+        let bytes = store.serialize_page(&mut page);
+
+        // Deserialize bytes 
+        let deserialized = store.deserialize_page(bytes);
+
+        // Pages should match 
+        assert_eq!(page, deserialized)
+    } 
+
+    #[test]
+    fn test_metadata() {
+        let tmp_dir = env::temp_dir();
+        let store_path = tmp_dir.join("test_store_metadata");
+        let _ = std::fs::remove_file(&store_path);
+
+        let store = Store::new(&store_path).unwrap();
+
+        let metadata = store.metadata::<MetaDataError>().unwrap();
+
+        // Assert on metadata fields 
+        assert!(metadata.capacity >= 4096);
+        assert_eq!(metadata.num_entries, 0);
+        assert_eq!(metadata.version, 1);
+
+        let calculated_signature = metadata.calculate_signature();
+
+        // Signature shouuld match 
+        assert_eq!(metadata.signature, calculated_signature);
+    } 
+
+    #[test]
+    fn test_map_page_to_location() {
+        
+        let tmp_dir = env::temp_dir();
+        let store_path = tmp_dir.join("test_store_map_page_to_location");
+        let _ = std::fs::remove_file(&store_path);
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let page_id = PageId{id: 0};
+        let location = 0;
+
+        store.map_page(&page_id, location);
+
+        assert_eq!(store.get_page_location(&page_id), Some(location));
+    } 
+
+    #[test]
+    fn test_get_page_location() {
+        let tmp_dir = env::temp_dir();
+        let store_path = tmp_dir.join("test_store_get_page_location");
+        let _ = std::fs::remove_file(&store_path);
+
+        let store = Store::new(&store_path).unwrap();
+
+        let page_id = PageId{id: 1};
+
+        assert!(store.get_page_location(&page_id).is_none());
+    } 
+
+    #[test]
+    fn test_insert_get_delete() {
+        let store_path = env::temp_dir().join("test_store_kv");
+        let _ = std::fs::remove_file(&store_path);
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let key = Key::new();
+        store.insert(key.clone(), Value::new("hello")).unwrap();
+
+        assert!(store.contains(&key).unwrap());
+        assert_eq!(store.get(&key).unwrap().unwrap().get(), b"hello");
+
+        assert!(store.delete(&key).unwrap());
+        assert!(!store.contains(&key).unwrap());
+        assert!(store.get(&key).unwrap().is_none());
+        assert!(!store.delete(&key).unwrap());
+    } 
+
+    #[test]
+    fn test_insert_overwrites_value() {
+        let store_path = env::temp_dir().join("test_store_overwrite");
+        let _ = std::fs::remove_file(&store_path);
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let key = Key::new();
+        store.insert(key.clone(), Value::new("first")).unwrap();
+        store.insert(key.clone(), Value::new("second")).unwrap();
+
+        assert_eq!(store.get(&key).unwrap().unwrap().get(), b"second");
+    } 
+
+    #[test]
+    fn test_insert_spills_to_new_page() {
+        let store_path = env::temp_dir().join("test_store_spill");
+        let _ = std::fs::remove_file(&store_path);
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let keys: Vec<Key> = (0..10).map(|_| Key::new()).collect();
+        for key in &keys {
+            store.insert(key.clone(), Value::new(vec![1u8; 1000])).unwrap();
+        } 
+
+        assert!(store.len() > 1);
+        for key in &keys {
+            assert_eq!(store.get(key).unwrap().unwrap().get(), &vec![1u8; 1000]);
+        } 
+    } 
+
+    #[test]
+    fn test_records_share_a_page() {
+        let store_path = env::temp_dir().join("test_store_share_page");
+        let _ = std::fs::remove_file(&store_path);
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let keys: Vec<Key> = (0..20).map(|_| Key::new()).collect();
+        for key in &keys {
+            store.insert(key.clone(), Value::new("small value")).unwrap();
+        } 
+
+        // One data page plus the index root
+        assert_eq!(store.len(), 2);
+
+        // Growing a value past the page's free space moves it to a new page
+        store.insert(keys[0].clone(), Value::new(vec![9u8; 3900])).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(&keys[0]).unwrap().unwrap().get(), &vec![9u8; 3900]);
+        assert_eq!(store.get(&keys[1]).unwrap().unwrap().get(), b"small value");
+    } 
+
+    #[test]
+    fn test_read_corrupted_page() {
+        let store_path = env::temp_dir().join("test_store_corrupted");
+        let _ = std::fs::remove_file(&store_path);
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let id = store.allocate_page().unwrap();
+        let location = store.get_page_location(&id).unwrap();
+
+        let mut page = PageFormat::new();
+        page.insert_record(b"important").unwrap();
+        store.write_page(&page, &id).unwrap();
+
+        // Push the page to disk and drop it from the cache
+        store.flush().unwrap();
+        store.pool().clear();
+
+        // Flip a byte in the middle of the page on disk
+        store.file.seek(SeekFrom::Start(location + 2000)).unwrap();
+        store.file.write_all(&[0xff]).unwrap();
+
+        let err = store.read_page(id).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(StoreError::from_io(&err).unwrap().is_corruption());
+    } 
+
+    #[test]
+    fn test_reopen_recovers_pages() {
+        let store_path = env::temp_dir().join("test_store_reopen_pages");
+        let _ = std::fs::remove_file(&store_path);
+
+        let mut page = PageFormat::new();
+        page.insert_record(b"persisted").unwrap();
+
+        let id = {
+            let mut store = Store::new(&store_path).unwrap();
+            let id = store.allocate_page().unwrap();
+            store.write_page(&page, &id).unwrap();
+            id
+        };
+
+        let store = Store::new(&store_path).unwrap();
+
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.read_page(id).unwrap(), page);
+    } 
+
+    #[test]
+    fn test_reopen_with_large_page_map() {
+        let store_path = env::temp_dir().join("test_store_reopen_large_map");
+        let _ = std::fs::remove_file(&store_path);
+
+        // More entries than fit in a single page map page
+        let ids: Vec<PageId> = {
+            let mut store = Store::new(&store_path).unwrap();
+            (0..500).map(|_| store.allocate_page().unwrap()).collect()
+        };
+
+        let mut store = Store::new(&store_path).unwrap();
+        assert_eq!(store.len(), 500);
+        assert!(store.map_pages.len() > 1);
+
+        for id in &ids {
+            assert!(store.get_page_location(id).is_some());
+        } 
+
+        // New pages don't reuse ids from the previous run
+        let new_id = store.allocate_page().unwrap();
+        assert!(!ids.contains(&new_id));
+    } 
+
+    #[test]
+    fn test_page_ids_are_per_store() {
+        let first_path = env::temp_dir().join("test_store_ids_first");
+        let second_path = env::temp_dir().join("test_store_ids_second");
+        let _ = std::fs::remove_file(&first_path);
+        let _ = std::fs::remove_file(&second_path);
+
+        let mut first = Store::new(&first_path).unwrap();
+        let mut second = Store::new(&second_path).unwrap();
+
+        // Allocations in one store don't advance the other's ids
+        let a = first.allocate_page().unwrap();
+        let b = second.allocate_page().unwrap();
+        let c = first.allocate_page().unwrap();
+
+        assert_eq!(a.as_u64(), 0);
+        assert_eq!(b.as_u64(), 0);
+        assert_eq!(c.as_u64(), 1);
+    } 
+
+    #[test]
+    fn test_page_ids_continue_after_reopen() {
+        let store_path = env::temp_dir().join("test_store_ids_reopen");
+        let _ = std::fs::remove_file(&store_path);
+
+        {
+            let mut store = Store::new(&store_path).unwrap();
+            store.allocate_page().unwrap();
+            store.allocate_page().unwrap();
+        }
+
+        let mut store = Store::new(&store_path).unwrap();
+        assert_eq!(store.allocate_page().unwrap().as_u64(), 2);
+    } 
+
+    #[test]
+    fn test_page_id_u64_round_trip() {
+        let id = PageId::from_u64(77);
+
+        assert_eq!(id.as_u64(), 77);
+        assert_eq!(PageId::from_u64(id.as_u64()), id);
+    } 
+
+    #[test]
+    fn test_free_page() {
+        let store_path = env::temp_dir().join("test_store_free_page");
+        let _ = std::fs::remove_file(&store_path);
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
-pub struct PageMap {
-    mappings: BTreeMap<PageId, u64>
-} 
+        let mut store = Store::new(&store_path).unwrap();
 
+        let id = store.allocate_page().unwrap();
+        let location = store.get_page_location(&id).unwrap();
 
-#[derive(Clone, Eq, PartialOrd, Ord)]
-pub struct PageId {
-    id: usize
-}
+        store.free_page(id.clone()).unwrap();
 
-impl PartialEq for PageId {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
+        assert_eq!(store.len(), 0);
+        assert!(store.get_page_location(&id).is_none());
+        assert!(store.read_page(id.clone()).is_err());
+        assert!(store.free_page(id).is_err());
+
+        // The freed location is handed out again
+        let reused = store.allocate_page().unwrap();
+        assert_eq!(store.get_page_location(&reused), Some(location));
     } 
-}
 
-static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    #[test]
+    fn test_allocate_from_corrupt_free_list() {
+        let store_path = env::temp_dir().join("test_store_corrupt_free_list");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
 
-impl PageId {
+        let mut store = Store::new(&store_path).unwrap();
+        let id = store.allocate_page().unwrap();
+        let location = store.get_page_location(&id).unwrap();
+        store.free_page(id).unwrap();
 
-    fn new() -> Self {
-        Self { id: NEXT_ID.fetch_add(1, Ordering::Relaxed)}
+        // Something else was written over the head of the free list
+        let mut page = PageFormat::new();
+        page.set_page_type(PageType::Data);
+        store.write_page_at(location, &page).unwrap();
+
+        let err = store.allocate_page().unwrap_err();
+        assert!(StoreError::from_io(&err).unwrap().is_corruption());
+        assert_eq!(store.len(), 0);
     } 
 
-    fn clone(&self) -> Self {
-        Self { id: self.id }
+    #[test]
+    fn test_file_size_flat_under_churn() {
+        let store_path = env::temp_dir().join("test_store_churn");
+        let _ = std::fs::remove_file(&store_path);
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let churn = |store: &mut Store| {
+            let ids: Vec<PageId> = (0..10).map(|_| store.allocate_page().unwrap()).collect();
+            for id in ids {
+                store.free_page(id).unwrap();
+            } 
+        };
+
+        // Let the page map claim its own page first
+        churn(&mut store);
+        churn(&mut store);
+        let size = store.get_file().metadata().unwrap().len();
+
+        for _ in 0..5 {
+            churn(&mut store);
+        } 
+
+        assert_eq!(store.get_file().metadata().unwrap().len(), size);
     } 
-} 
 
-impl PageMap {
-    fn new() -> Self {
-        Self {
-            mappings: BTreeMap::new()
+    #[test]
+    fn test_free_list_survives_reopen() {
+        let store_path = env::temp_dir().join("test_store_free_list_reopen");
+        let _ = std::fs::remove_file(&store_path);
+
+        {
+            let mut store = Store::new(&store_path).unwrap();
+            let keep = store.allocate_page().unwrap();
+            let ids: Vec<PageId> = (0..3).map(|_| store.allocate_page().unwrap()).collect();
+
+            for id in ids {
+                store.free_page(id).unwrap();
+            } 
+            store.write_page(&PageFormat::new(), &keep).unwrap();
+        }
+
+        let mut store = Store::new(&store_path).unwrap();
+        assert_eq!(store.len(), 1);
+
+        // The page map may have taken one freed page, the rest are reused
+        let size = store.get_file().metadata().unwrap().len();
+        store.allocate_page().unwrap();
+        store.allocate_page().unwrap();
+
+        assert_eq!(store.get_file().metadata().unwrap().len(), size);
+    } 
+
+    #[test]
+    fn test_deleting_all_keys_frees_data_page() {
+        let store_path = env::temp_dir().join("test_store_free_data_page");
+        let _ = std::fs::remove_file(&store_path);
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let key = Key::new();
+        store.insert(key.clone(), Value::new("value")).unwrap();
+        assert_eq!(store.len(), 2);
+
+        store.delete(&key).unwrap();
+        assert_eq!(store.len(), 0);
+
+        let size = store.get_file().metadata().unwrap().len();
+        for _ in 0..20 {
+            let key = Key::new();
+            store.insert(key.clone(), Value::new(vec![3u8; 2000])).unwrap();
+            store.delete(&key).unwrap();
         } 
+
+        assert_eq!(store.get_file().metadata().unwrap().len(), size);
     } 
 
-    fn map_page(&mut self, page_id: PageId, location: u64) {
-        self.mappings.insert(page_id, location);
+    #[test]
+    fn test_repeated_reads_hit_buffer_pool() {
+        let store_path = env::temp_dir().join("test_store_pool_hits");
+        let _ = std::fs::remove_file(&store_path);
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let id = store.allocate_page().unwrap();
+        store.flush().unwrap();
+        store.pool().clear();
+
+        let before = store.pool_stats();
+        for _ in 0..5 {
+            store.read_page(id.clone()).unwrap();
+        } 
+        let after = store.pool_stats();
+
+        assert_eq!(after.misses - before.misses, 1);
+        assert_eq!(after.hits - before.hits, 4);
     } 
 
-    fn get_location(&self, page_id: &PageId) -> Option<u64> {
-        self.mappings.get(page_id).copied()
+    #[test]
+    fn test_small_pool_writes_back_evicted_pages() {
+        let store_path = env::temp_dir().join("test_store_small_pool");
+        let _ = std::fs::remove_file(&store_path);
+
+        let options = StoreOptions { pool_capacity: 2, ..Default::default() };
+        let mut store = Store::open(&store_path, options).unwrap();
+
+        let mut ids = vec![];
+        for i in 0..10u8 {
+            let id = store.allocate_page().unwrap();
+            let mut page = PageFormat::new();
+            page.insert_record(&[i; 10]).unwrap();
+            store.write_page(&page, &id).unwrap();
+            ids.push(id);
+        } 
+
+        assert!(store.pool().len() <= 2);
+        assert!(store.pool_stats().write_backs > 0);
+
+        for (i, id) in ids.into_iter().enumerate() {
+            let page = store.read_page(id).unwrap();
+            assert_eq!(page.read_record(0), Some(&[i as u8; 10][..]));
+        } 
     } 
-} 
 
+    #[test]
+    fn test_pinned_page_stays_cached() {
+        let store_path = env::temp_dir().join("test_store_pinned");
+        let _ = std::fs::remove_file(&store_path);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::env;
+        let options = StoreOptions { pool_capacity: 1, ..Default::default() };
+        let mut store = Store::open(&store_path, options).unwrap();
 
-    #[test] 
-    fn test_open_and_create_store() {
-        let tmp_dir = env::temp_dir();
-        let store_path = tmp_dir.join("test_store");
-        let path = Path::new(&store_path);
+        let pinned = store.allocate_page().unwrap();
+        store.pin_page(&pinned).unwrap();
 
-        let _store = Store::new(&path).unwrap();
+        for _ in 0..3 {
+            let id = store.allocate_page().unwrap();
+            store.read_page(id).unwrap();
+        } 
 
-        // Fiel should now exist 
-        assert!(store_path.exists());
+        let location = store.get_page_location(&pinned).unwrap();
+        assert!(store.pool().contains(location));
+
+        assert!(store.unpin_page(&pinned));
+        assert!(!store.unpin_page(&pinned));
     } 
 
     #[test]
-    fn test_write_and_read_page() {
-        let tmp_dir = env::temp_dir();
-        let store_path = tmp_dir.join("test_store");
-        let path = Path::new(&store_path);
+    fn test_wal_is_empty_after_checkpoint() {
+        let store_path = env::temp_dir().join("test_store_wal_empty");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
 
-        let mut store = Store::new(&path).unwrap();
+        let mut store = Store::new(&store_path).unwrap();
+
+        let id = store.allocate_page().unwrap();
+        store.write_page(&PageFormat::new(), &id).unwrap();
+        assert!(store.pending().is_empty());
+        assert!(!store.wal.is_empty().unwrap());
+
+        store.checkpoint().unwrap();
+        assert!(store.wal.is_empty().unwrap());
+    } 
+
+    #[test]
+    fn test_commits_survive_a_crash_before_checkpoint() {
+        let store_path = env::temp_dir().join("test_store_wal_no_checkpoint");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        {
+            let mut store = Store::new(&store_path).unwrap();
+            for i in 0..20u32 {
+                store.insert(Key::from_u64(i as u64), Value::new(vec![i as u8; 100])).unwrap();
+            } 
+            store.delete(&Key::from_u64(3)).unwrap();
+            std::mem::forget(store);
+        } 
+
+        let store = Store::new(&store_path).unwrap();
+        for i in 0..20u32 {
+            let value = store.get(&Key::from_u64(i as u64)).unwrap();
+            if i == 3 {
+                assert!(value.is_none());
+            } else {
+                assert_eq!(value.unwrap().get(), &vec![i as u8; 100]);
+            } 
+        } 
+    } 
+
+    #[test]
+    fn test_commit_writes_only_changed_map_pages() {
+        let store_path = env::temp_dir().join("test_store_map_pages_logged");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
 
-        let id = store.allocate_page();
+        let mut store = Store::new(&store_path).unwrap();
+        for _ in 0..600 {
+            store.allocate_page().unwrap();
+        } 
+        store.flush().unwrap();
+        assert!(store.map_pages.len() > 1);
+        store.checkpoint().unwrap();
 
+        // Rewriting a data page leaves the page map as it was
+        let id = PageId { id: 0 };
         let mut page = PageFormat::new();
+        page.insert_record(b"changed").unwrap();
+        store.write_page(&page, &id).unwrap();
 
-        // Write Page 
-        store.write_page(&mut page, &id).unwrap();
+        let logged: Vec<u64> = store.wal.recover().unwrap().into_iter().map(|(location, _)| location).collect();
+        assert_eq!(logged, vec![store.get_page_location(&id).unwrap()]);
+    } 
 
-        // read page 
-        let read = store.read_page(id).unwrap();
+    #[test]
+    fn test_replay_committed_wal_after_crash() {
+        let store_path = env::temp_dir().join("test_store_wal_replay");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
 
-        assert_eq!(page, read);
+        let mut page = PageFormat::new();
+        page.insert_record(b"logged but not applied").unwrap();
+
+        let id = {
+            let mut store = Store::new(&store_path).unwrap();
+            let id = store.allocate_page().unwrap();
+            store.flush().unwrap();
+
+            // Crash after the log is synced but before the data file is written
+            let location = store.get_page_location(&id).unwrap();
+            store.wal.append_page(location, &page).unwrap();
+            store.wal.append_commit().unwrap();
+            store.wal.sync().unwrap();
+            std::mem::forget(store);
+
+            id
+        };
+
+        let store = Store::new(&store_path).unwrap();
+
+        assert_eq!(store.read_page(id).unwrap(), page);
+        assert!(store.wal.is_empty().unwrap());
     } 
 
     #[test]
-    fn test_serialize_page() {
-        // Create store 
-        let tmp_dir = env::temp_dir();
-        let store_path = tmp_dir.join("test_store");
-        let path = Path::new(&store_path);
-        let store = Store::new(&path).unwrap();
+    fn test_uncommitted_wal_is_discarded() {
+        let store_path = env::temp_dir().join("test_store_wal_uncommitted");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
 
-        // Create page 
         let mut page = PageFormat::new();
+        page.insert_record(b"never committed").unwrap();
 
-        //Serialize Page 
-        let bytes = store.serialize_page(&mut page);
+        let id = {
+            let mut store = Store::new(&store_path).unwrap();
+            let id = store.allocate_page().unwrap();
+            store.flush().unwrap();
 
-        // Deserialize bytes 
-        let deserialized = store.deserialize_page(bytes);
+            let location = store.get_page_location(&id).unwrap();
+            store.wal.append_page(location, &page).unwrap();
+            store.wal.sync().unwrap();
+            std::mem::forget(store);
 
-        // Pages should match 
-        assert_eq!(page, deserialized)
+            id
+        };
+
+        let store = Store::new(&store_path).unwrap();
+
+        assert_eq!(store.read_page(id).unwrap(), PageFormat::new());
     } 
 
     #[test]
-    fn test_metadata() {
-        let tmp_dir = env::temp_dir();
-        let store_path = tmp_dir.join("test_store");
+    fn test_evicted_dirty_pages_wait_for_commit() {
+        let store_path = env::temp_dir().join("test_store_wal_pending");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        let options = StoreOptions { pool_capacity: 1, ..Default::default() };
+        let mut store = Store::open(&store_path, options).unwrap();
+
+        let first = store.allocate_page().unwrap();
+        let second = store.allocate_page().unwrap();
+        store.flush().unwrap();
+
+        let mut page = PageFormat::new();
+        page.insert_record(b"buffered").unwrap();
+        store.put_page(&page, &first).unwrap();
+        store.put_page(&PageFormat::new(), &second).unwrap();
+
+        // First page was evicted but must not reach the data file yet
+        let location = store.get_page_location(&first).unwrap();
+        assert!(store.pending().contains_key(&location));
+        assert_eq!(store.read_page_from_disk(location).unwrap(), PageFormat::new());
+        assert_eq!(store.read_page(first.clone()).unwrap(), page);
+
+        store.flush().unwrap();
+        assert_eq!(store.read_page_from_disk(location).unwrap(), page);
+    } 
+
+    #[test]
+    fn test_reopen_rejects_foreign_file() {
+        let store_path = env::temp_dir().join("test_store_foreign_file");
+        let _ = std::fs::remove_file(wal_path(&store_path));
+        std::fs::write(&store_path, vec![1u8; 4096]).unwrap();
+
+        let err = Store::new(&store_path).err().unwrap();
+        assert!(StoreError::from_io(&err).unwrap().is_corruption());
+    } 
+
+    #[test]
+    fn test_index_survives_reopen() {
+        let store_path = env::temp_dir().join("test_store_index_reopen");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        let keys: Vec<Key> = (0..1000).map(|_| Key::new()).collect();
+        {
+            let mut store = Store::new(&store_path).unwrap();
+            let mut tx = store.begin().unwrap();
+            for key in &keys {
+                tx.put(key.clone(), Value::new(key.as_bytes().to_vec())).unwrap();
+            }
+            tx.commit().unwrap();
+
+            for key in keys.iter().step_by(2) {
+                store.delete(key).unwrap();
+            }
+        }
 
         let store = Store::new(&store_path).unwrap();
+        assert!(store.index.root().is_some());
+
+        for (i, key) in keys.iter().enumerate() {
+            match store.get(key).unwrap() {
+                Some(value) => {
+                    assert_eq!(i % 2, 1);
+                    assert_eq!(value.get(), key.as_bytes());
+                }
+                None => assert_eq!(i % 2, 0),
+            }
+        }
+    }
 
-        let metadata = store.metadata::<MetaDataError>().unwrap();
+    fn long_key(i: u64) -> Key {
+        Key::from(format!("{:0>100}", i))
+    } 
 
-        // Assert on metadata fields 
-        assert!(metadata.capacity >= 4096);
-        assert_eq!(metadata.num_entries, 0);
-        assert_eq!(metadata.version, 1);
+    // Pages read by a lookup, the record's data page included
+    fn pages_read(store: &Store, key: &Key) -> u64 {
+        let before = store.pool_stats();
+        assert!(store.get(key).unwrap().is_some());
+        let after = store.pool_stats();
 
-        let calculated_signature = metadata.calculate_signature();
+        after.hits + after.misses - before.hits - before.misses
+    } 
 
-        // Signature shouuld match 
-        assert_eq!(metadata.signature, calculated_signature);
+    #[test]
+    fn test_hash_index_lookup_reads_two_index_pages() {
+        let store_path = env::temp_dir().join("test_store_hash_index_lookup");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        let hashed = || StoreOptions { hash_index: true, ..Default::default() };
+        {
+            let mut store = Store::open(&store_path, hashed()).unwrap();
+            let mut tx = store.begin().unwrap();
+            for i in 0..5000u64 {
+                tx.put(long_key(i), Value::new(i)).unwrap();
+            } 
+            tx.commit().unwrap();
+        } 
+
+        // Long keys leave the B+tree three levels deep
+        let store = Store::new(&store_path).unwrap();
+        assert!(store.hash_index.is_none());
+        assert!(pages_read(&store, &long_key(1234)) > 3);
+        drop(store);
+
+        let store = Store::open(&store_path, hashed()).unwrap();
+        for i in (0..5000u64).step_by(71) {
+            assert!(pages_read(&store, &long_key(i)) <= 3);
+            assert_eq!(*store.get_as::<u64>(&long_key(i)).unwrap().unwrap().get(), i);
+        } 
     } 
 
     #[test]
-    fn test_map_page_to_location() {
-        
-        let tmp_dir = env::temp_dir();
-        let store_path = tmp_dir.join("test_store");
+    fn test_hash_index_follows_options() {
+        let store_path = env::temp_dir().join("test_store_hash_index_options");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        let hashed = |hash_index| StoreOptions { hash_index, ..Default::default() };
+        {
+            let mut store = Store::open(&store_path, hashed(false)).unwrap();
+            for i in 0..300u64 {
+                store.insert(Key::from(i), Value::new(i)).unwrap();
+            } 
+        } 
+
+        // Built from the B+tree on the first open that asks for it
+        let pages = {
+            let mut store = Store::open(&store_path, hashed(true)).unwrap();
+            assert!(store.hash_index.as_ref().unwrap().root().is_some());
+            assert_eq!(*store.get_as::<u64>(&Key::from(7u64)).unwrap().unwrap().get(), 7);
+
+            store.delete(&Key::from(7u64)).unwrap();
+            store.insert(Key::from(300u64), Value::new(300u64)).unwrap();
+            store.len()
+        };
+
+        // Dropped, with its pages, by one that doesn't, so it can't go
+        // stale while the store is written without it
+        {
+            let mut store = Store::open(&store_path, hashed(false)).unwrap();
+            assert!(store.hash_index.is_none());
+            assert!(store.len() < pages);
+            store.delete(&Key::from(8u64)).unwrap();
+        } 
+
+        let store = Store::open(&store_path, hashed(true)).unwrap();
+        assert!(store.get(&Key::from(7u64)).unwrap().is_none());
+        assert!(store.get(&Key::from(8u64)).unwrap().is_none());
+        assert_eq!(*store.get_as::<u64>(&Key::from(300u64)).unwrap().unwrap().get(), 300);
+        assert!(store.contains(&Key::from(9u64)).unwrap());
+    } 
+
+    #[test]
+    fn test_application_keys() {
+        let store_path = env::temp_dir().join("test_store_application_keys");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        {
+            let mut store = Store::new(&store_path).unwrap();
+            for i in 0..30u32 {
+                store.insert(Key::from(format!("user:{}", i)), Value::new(i)).unwrap();
+            } 
+            store.insert(Key::from(7u64), Value::new("seven")).unwrap();
+            store.insert(Key::from(""), Value::new("empty")).unwrap();
+        } 
 
         let mut store = Store::new(&store_path).unwrap();
 
-        let page_id = PageId{id: 0};
-        let location = 0;
+        assert_eq!(*store.get_as::<u32>(&Key::from("user:12")).unwrap().unwrap().get(), 12);
+        assert_eq!(store.get(&Key::from(7u64)).unwrap().unwrap().get(), b"seven");
+        assert_eq!(store.get(&Key::from("")).unwrap().unwrap().get(), b"empty");
 
-        store.map_page(&page_id, location);
+        let users: Vec<String> = store.prefix(b"user:2").map(|entry| entry.unwrap().0.to_string()).collect();
+        let expected: Vec<String> = std::iter::once(2).chain(20..30).map(|i| format!("user:{}", i)).collect();
+        assert_eq!(users, expected);
 
-        assert_eq!(store.get_page_location(&page_id), Some(location));
+        let range: Vec<Key> = store.range(Key::from("user:10")..Key::from("user:13")).map(|entry| entry.unwrap().0).collect();
+        assert_eq!(range, vec![Key::from("user:10"), Key::from("user:11"), Key::from("user:12")]);
+
+        let err = store.insert(Key::from(vec![1u8; MAX_KEY_SIZE + 1]), Value::new("too long")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        store.insert(Key::from(vec![1u8; MAX_KEY_SIZE]), Value::new(vec![2u8; 5000])).unwrap();
     } 
 
     #[test]
-    fn test_get_page_location() {
-        let tmp_dir = env::temp_dir();
-        let store_path = tmp_dir.join("test_store");
+    fn test_typed_values_round_trip() {
+        let store_path = env::temp_dir().join("test_store_typed_values");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let count = Key::new();
+        let user = Key::new();
+        let tags = Key::new();
+
+        store.insert(count.clone(), Value::new(42u64)).unwrap();
+        store.insert(user.clone(), Value::new((String::from("ada"), 36u8, Some(true)))).unwrap();
+        store.insert(tags.clone(), Value::new(vec![String::from("a"), String::from("b")])).unwrap();
+
+        assert_eq!(*store.get_as::<u64>(&count).unwrap().unwrap().get(), 42);
+        assert_eq!(store.get_as::<(String, u8, Option<bool>)>(&user).unwrap().unwrap().get(), &(String::from("ada"), 36, Some(true)));
+        assert_eq!(store.get_as::<Vec<String>>(&tags).unwrap().unwrap().get(), &vec![String::from("a"), String::from("b")]);
+        assert!(store.get_as::<u64>(&Key::new()).unwrap().is_none());
+
+        // Strings are stored as their raw bytes
+        store.insert(count.clone(), Value::new("plain")).unwrap();
+        assert_eq!(store.get(&count).unwrap().unwrap().get(), b"plain");
+        assert_eq!(store.get_as::<String>(&count).unwrap().unwrap().get(), "plain");
+
+        let err = store.get_as::<u64>(&count).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    } 
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_values_round_trip() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Order {
+            id: u64,
+            owner: Key,
+            items: Vec<(String, u32)>,
+            note: Option<String>,
+        }
+
+        let store_path = env::temp_dir().join("test_store_serde_values");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let key = Key::new();
+        let order = Order {
+            id: 7,
+            owner: Key::new(),
+            items: vec![(String::from("apple"), 3)],
+            note: None,
+        };
+
+        store.insert_serde(key.clone(), Value::new(&order)).unwrap();
+        assert_eq!(store.get_serde::<Order>(&key).unwrap().unwrap().into_inner(), order);
+
+        // Keys, values and metadata serialize themselves too
+        let bytes = bincode::serialize(&Value::new(key.clone())).unwrap();
+        let value: Value<Key> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(value.get(), &key);
+
+        let metadata = StoreMetaData::new();
+        let bytes = bincode::serialize(&metadata).unwrap();
+        let decoded: StoreMetaData = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.get_signature(), metadata.get_signature());
+    } 
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_value_that_fails_to_serialize() {
+        struct Unserializable;
+
+        impl serde::Serialize for Unserializable {
+            fn serialize<S: serde::Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("not today"))
+            } 
+        } 
+
+        let store_path = env::temp_dir().join("test_store_serde_fails");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        let mut store = Store::new(&store_path).unwrap();
+
+        let key = Key::new();
+        let err = store.insert_serde(key.clone(), Value::new(Unserializable)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!store.contains(&key).unwrap());
+    } 
+
+    #[test]
+    fn test_insert_value_larger_than_page() {
+        let store_path = env::temp_dir().join("test_store_too_large");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        let key = Key::new();
+        let value: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+
+        {
+            let mut store = Store::new(&store_path).unwrap();
+            store.insert(key.clone(), Value::new(value.clone())).unwrap();
+
+            assert!(store.len() > 12);
+            assert_eq!(store.get(&key).unwrap().unwrap().get(), &value);
+        } 
 
         let store = Store::new(&store_path).unwrap();
+        assert_eq!(store.get(&key).unwrap().unwrap().get(), &value);
+    } 
 
-        let page_id = PageId{id: 1};
+    #[test]
+    fn test_overflow_chain_freed_on_overwrite_and_delete() {
+        let store_path = env::temp_dir().join("test_store_overflow_free");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
 
-        assert!(store.get_page_location(&page_id).is_none());
+        let mut store = Store::new(&store_path).unwrap();
+
+        let key = Key::new();
+        store.insert(key.clone(), Value::new("small")).unwrap();
+        let pages = store.len();
+
+        store.insert(key.clone(), Value::new(vec![4u8; 20_000])).unwrap();
+        assert!(store.len() > pages);
+
+        // Shrinking the value back gives the chain back
+        store.insert(key.clone(), Value::new("small again")).unwrap();
+        assert_eq!(store.len(), pages);
+
+        store.insert(key.clone(), Value::new(vec![5u8; 20_000])).unwrap();
+        store.insert(key.clone(), Value::new(vec![6u8; 30_000])).unwrap();
+        assert_eq!(store.get(&key).unwrap().unwrap().get(), &vec![6u8; 30_000]);
+
+        store.delete(&key).unwrap();
+        assert_eq!(store.len(), 0);
+    } 
+
+    #[test]
+    fn test_ttl_expires_entries() {
+        let store_path = env::temp_dir().join("test_store_ttl");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        let short = Key::from("short");
+        let long = Key::from("long");
+        let big = Key::from("big");
+        let plain = Key::from("plain");
+
+        {
+            let mut store = Store::new(&store_path).unwrap();
+            store.put_with_ttl(short.clone(), Value::new("gone soon"), Duration::from_millis(500)).unwrap();
+            assert!(store.contains(&short).unwrap());
+
+            store.put_with_ttl(long.clone(), Value::new("stays"), Duration::from_secs(3600)).unwrap();
+            store.insert(big.clone(), Value::new(vec![3u8; 20_000])).unwrap();
+            store.insert(plain.clone(), Value::new("no ttl")).unwrap();
+
+            // Setting an expiry keeps the overflow chain the value lives in
+            assert!(store.expire(&big, Duration::from_millis(500)).unwrap());
+            assert_eq!(store.get(&big).unwrap().unwrap().get(), &vec![3u8; 20_000]);
+            assert!(!store.expire(&Key::from("missing"), Duration::from_secs(1)).unwrap());
+        } 
+
+        std::thread::sleep(Duration::from_millis(600));
+
+        // Expiry times are persisted with the records
+        let mut store = Store::new(&store_path).unwrap();
+        assert!(store.get(&short).unwrap().is_none());
+        assert!(store.get(&big).unwrap().is_none());
+        assert!(!store.contains(&short).unwrap());
+        assert!(store.blob_reader(&big).unwrap().is_none());
+        assert!(!store.expire(&short, Duration::from_secs(1)).unwrap());
+        assert_eq!(store.get(&long).unwrap().unwrap().get(), b"stays");
+
+        let keys: Vec<Key> = store.iter().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(keys, vec![long.clone(), plain.clone()]);
+
+        // Overwriting clears the ttl
+        store.put_with_ttl(plain.clone(), Value::new("brief"), Duration::from_millis(1)).unwrap();
+        store.insert(plain.clone(), Value::new("permanent")).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(store.get(&plain).unwrap().unwrap().get(), b"permanent");
+    } 
+
+    #[test]
+    fn test_sweep_expired_frees_space() {
+        let store_path = env::temp_dir().join("test_store_sweep");
+        let _ = std::fs::remove_file(&store_path);
+        let _ = std::fs::remove_file(wal_path(&store_path));
+
+        let mut store = Store::new(&store_path).unwrap();
+        let kept = Key::from("kept");
+        store.put_with_ttl(kept.clone(), Value::new("kept"), Duration::from_secs(3600)).unwrap();
+        let pages = store.len();
+
+        let mut tx = store.begin().unwrap();
+        for i in 0..200u64 {
+            let ttl = if i < 150 { Duration::ZERO } else { Duration::from_secs(3600) };
+            tx.put_with_ttl(Key::from(i), Value::new(vec![1u8; 200]), ttl).unwrap();
+        } 
+        tx.put_with_ttl(Key::from("big"), Value::new(vec![2u8; 50_000]), Duration::ZERO).unwrap();
+        tx.commit().unwrap();
+        assert!(store.len() > pages + 10);
+
+        assert_eq!(store.sweep_expired().unwrap(), 151);
+
+        let mut tx = store.begin().unwrap();
+        for i in 150..200u64 {
+            assert!(tx.expire(&Key::from(i), Duration::ZERO).unwrap());
+        } 
+        tx.commit().unwrap();
+
+        assert_eq!(store.sweep_expired().unwrap(), 50);
+        assert_eq!(store.len(), pages);
+        assert_eq!(store.sweep_expired().unwrap(), 0);
+        assert_eq!(store.get(&kept).unwrap().unwrap().get(), b"kept");
     } 
 
     #[test]
     fn test_allocate_page() {
         let tmp_dir = env::temp_dir();
-        let store_path = tmp_dir.join("test_store");
+        let store_path = tmp_dir.join("test_store_allocate_page");
+        let _ = std::fs::remove_file(&store_path);
 
         let mut store = Store::new(&store_path).unwrap();
 
-        let page_id = store.allocate_page();
+        let page_id = store.allocate_page().unwrap();
 
         assert!(store.get_page_location(&page_id).is_some());
     } 
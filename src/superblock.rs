@@ -0,0 +1,136 @@
+use crate::page::{PageFormat, PageType};
+use crate::storage_manager::StoreError;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"KVST");
+pub const FORMAT_VERSION: u32 = 9;
+
+// Superblock lives in the page at offset 0 of every store file
+pub const SUPERBLOCK_LOCATION: u64 = 0;
+
+const SUPERBLOCK_SIZE: usize = 4 + 4 + 4 + 8 + 8 + 8 + 8 + 8 + 8 + 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Superblock {
+    pub num_pages: u32,
+    pub next_page_id: u64,
+    pub free_head: u64,
+    pub map_root: u64,
+    // Page ids stored plus one, zero means none
+    pub index_root: u64,
+    pub heap_tail: u64,
+    // Head of an overflow chain still being streamed in, freed on open
+    pub blob_head: u64,
+    // Root of the hash index, for stores opened with one
+    pub hash_root: u64,
+}
+
+impl Default for Superblock {
+    fn default() -> Self {
+        Self::new()
+    }
+} 
+
+impl Superblock {
+    pub fn new() -> Self {
+        Superblock {
+            num_pages: 0,
+            next_page_id: 0,
+            free_head: 0,
+            map_root: 0,
+            index_root: 0,
+            heap_tail: 0,
+            blob_head: 0,
+            hash_root: 0,
+        } 
+    } 
+
+    pub fn to_page(&self) -> PageFormat {
+        let mut data = Vec::with_capacity(SUPERBLOCK_SIZE);
+
+        data.extend_from_slice(&MAGIC.to_le_bytes());
+        data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        data.extend_from_slice(&self.num_pages.to_le_bytes());
+        data.extend_from_slice(&self.next_page_id.to_le_bytes());
+        data.extend_from_slice(&self.free_head.to_le_bytes());
+        data.extend_from_slice(&self.map_root.to_le_bytes());
+        data.extend_from_slice(&self.index_root.to_le_bytes());
+        data.extend_from_slice(&self.heap_tail.to_le_bytes());
+        data.extend_from_slice(&self.blob_head.to_le_bytes());
+        data.extend_from_slice(&self.hash_root.to_le_bytes());
+
+        let mut page = PageFormat::new();
+        page.set_page_type(PageType::Superblock);
+        page.insert_record(&data);
+
+        page
+    } 
+
+    pub fn from_page(page: &PageFormat) -> Result<Self, StoreError> {
+        let data = match page.read_record(0) {
+            Some(data) if page.get_page_type() == PageType::Superblock && data.len() == SUPERBLOCK_SIZE => data,
+            _ => return Err(StoreError::Corruption { msg: "Missing superblock".to_string() }),
+        };
+
+        let magic = u32::from_le_bytes(data[0..4].try_into().unwrap());
+        if magic != MAGIC {
+            return Err(StoreError::Corruption { msg: "Bad superblock magic number".to_string() });
+        } 
+
+        let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(StoreError::Corruption { msg: format!("Unsupported format version {}", version) });
+        } 
+
+        Ok(Superblock {
+            num_pages: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+            next_page_id: u64::from_le_bytes(data[12..20].try_into().unwrap()),
+            free_head: u64::from_le_bytes(data[20..28].try_into().unwrap()),
+            map_root: u64::from_le_bytes(data[28..36].try_into().unwrap()),
+            index_root: u64::from_le_bytes(data[36..44].try_into().unwrap()),
+            heap_tail: u64::from_le_bytes(data[44..52].try_into().unwrap()),
+            blob_head: u64::from_le_bytes(data[52..60].try_into().unwrap()),
+            hash_root: u64::from_le_bytes(data[60..68].try_into().unwrap()),
+        })
+    } 
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_superblock_round_trip() {
+        let superblock = Superblock {
+            num_pages: 42,
+            next_page_id: 50,
+            free_head: 12288,
+            map_root: 8192,
+            index_root: 8,
+            heap_tail: 3,
+            blob_head: 9,
+            hash_root: 11,
+        };
+
+        let page = PageFormat::deserialize(superblock.to_page().serialize());
+
+        assert_eq!(Superblock::from_page(&page).unwrap(), superblock);
+    } 
+
+    #[test]
+    fn test_superblock_rejects_plain_page() {
+        let page = PageFormat::new();
+
+        let err = Superblock::from_page(&page).unwrap_err();
+        assert!(err.is_corruption());
+    } 
+
+    #[test]
+    fn test_superblock_rejects_bad_magic() {
+        let mut page = PageFormat::new();
+        page.set_page_type(PageType::Superblock);
+        page.insert_record(&[0u8; SUPERBLOCK_SIZE]);
+
+        let err = Superblock::from_page(&page).unwrap_err();
+        assert!(err.is_corruption());
+    } 
+} 
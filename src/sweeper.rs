@@ -0,0 +1,92 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::db::Db;
+
+// Background thread deleting expired records every interval. Expired
+// records already read as absent, sweeping only gives their space back.
+// Writers wait for each sweep to finish.
+pub struct Sweeper {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Sweeper {
+    pub fn start(db: Arc<Db>, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                // A failed sweep leaves the records for the next one
+                let _ = db.sweep_expired();
+            }
+        });
+
+        Sweeper { stop: Some(stop), handle: Some(handle) }
+    }
+
+    // Waits for a sweep in progress to finish
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        // Dropping the sender wakes the thread up
+        self.stop.take();
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::values::Value;
+    use crate::wal::wal_path;
+    use crate::Key;
+    use std::env;
+    use std::time::Instant;
+
+    #[test]
+    fn test_sweeper_reclaims_expired_records() {
+        let path = env::temp_dir().join("test_sweeper");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(wal_path(&path));
+
+        let db = Arc::new(Db::new(&path).unwrap());
+        let kept = Key::from("kept");
+        db.insert(kept.clone(), Value::new("forever")).unwrap();
+        let pages = db.read(|store| store.len()).unwrap();
+
+        db.transaction(|tx| {
+            for i in 0..200u64 {
+                tx.put_with_ttl(Key::from(i), Value::new(vec![7u8; 100]), Duration::ZERO)?;
+            }
+            Ok(())
+        }).unwrap();
+        assert!(db.read(|store| store.len()).unwrap() > pages);
+
+        // Wait for the sweeps to get through every record, however slow
+        // the machine
+        let sweeper = Sweeper::start(db.clone(), Duration::from_millis(10));
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while db.read(|store| store.len()).unwrap() > pages && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        sweeper.stop();
+
+        assert_eq!(db.read(|store| store.len()).unwrap(), pages);
+        assert_eq!(db.read(|store| store.iter().count()).unwrap(), 1);
+        assert_eq!(db.get(&kept).unwrap().unwrap().get(), b"forever");
+    }
+}
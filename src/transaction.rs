@@ -0,0 +1,222 @@
+use std::io;
+use std::time::Duration;
+
+use crate::codec::{Decode, Encode};
+use crate::keys::Key;
+use crate::storage_manager::{expiry_after, Store};
+#[cfg(feature = "serde")]
+use crate::storage_manager::StoreError;
+use crate::values::Value;
+
+// Groups several key changes so they are applied all together or not at
+// all. Changes go straight to the store's buffered pages and only reach
+// disk as one write-ahead log batch on commit. Dropping a transaction
+// without committing rolls it back.
+pub struct Transaction<'a> {
+    store: &'a mut Store,
+    file_len: u64,
+    finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(store: &'a mut Store) -> Result<Self, io::Error> {
+        let file_len = store.file_len();
+
+        Ok(Transaction {
+            store,
+            file_len,
+            finished: false,
+        })
+    }
+
+    pub fn put<T: Encode>(&mut self, key: Key, value: Value<T>) -> Result<(), io::Error> {
+        self.store.insert_entry(key, &value.get().encode_value(), None)
+    }
+
+    // The ttl counts from this call, not from commit
+    pub fn put_with_ttl<T: Encode>(&mut self, key: Key, value: Value<T>, ttl: Duration) -> Result<(), io::Error> {
+        self.store.insert_entry(key, &value.get().encode_value(), Some(expiry_after(ttl)))
+    }
+
+    pub fn expire(&mut self, key: &Key, ttl: Duration) -> Result<bool, io::Error> {
+        self.store.expire_entry(key, expiry_after(ttl))
+    }
+
+    // Sees this transaction's own uncommitted changes
+    pub fn get(&mut self, key: &Key) -> Result<Option<Value<Vec<u8>>>, io::Error> {
+        self.store.get(key)
+    }
+
+    pub fn get_as<T: Decode>(&mut self, key: &Key) -> Result<Option<Value<T>>, io::Error> {
+        self.store.get_as(key)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn put_serde<T: serde::Serialize>(&mut self, key: Key, value: Value<T>) -> Result<(), io::Error> {
+        let bytes = bincode::serialize(value.get())
+            .map_err(|e| StoreError::Serialization(e.to_string()))?;
+
+        self.put(key, Value::new(bytes))
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn get_serde<T: serde::de::DeserializeOwned>(&mut self, key: &Key) -> Result<Option<Value<T>>, io::Error> {
+        self.store.get_serde(key)
+    }
+
+    pub fn delete(&mut self, key: &Key) -> Result<bool, io::Error> {
+        self.store.delete_entry(key)
+    }
+
+    pub fn commit(mut self) -> Result<(), io::Error> {
+        self.finished = true;
+
+        if let Err(err) = self.store.commit() {
+            self.store.abort(self.file_len)?;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    pub fn rollback(mut self) -> Result<(), io::Error> {
+        self.finished = true;
+        self.store.abort(self.file_len)
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.store.abort(self.file_len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::wal_path;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn temp_store(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(wal_path(&path));
+        path
+    }
+
+    #[test]
+    fn test_commit_applies_all_changes() {
+        let path = temp_store("test_tx_commit");
+        let mut store = Store::new(&path).unwrap();
+
+        let existing = Key::new();
+        store.insert(existing.clone(), Value::new("old")).unwrap();
+
+        let a = Key::new();
+        let b = Key::new();
+
+        let mut tx = store.begin().unwrap();
+        tx.put(a.clone(), Value::new("a")).unwrap();
+        tx.put(b.clone(), Value::new("b")).unwrap();
+        tx.delete(&existing).unwrap();
+
+        // Reads inside the transaction see its own writes
+        assert_eq!(tx.get(&a).unwrap().unwrap().get(), b"a");
+        tx.commit().unwrap();
+
+        assert_eq!(store.get(&a).unwrap().unwrap().get(), b"a");
+        assert_eq!(store.get(&b).unwrap().unwrap().get(), b"b");
+        assert!(!store.contains(&existing).unwrap());
+    }
+
+    #[test]
+    fn test_rollback_discards_changes() {
+        let path = temp_store("test_tx_rollback");
+        let mut store = Store::new(&path).unwrap();
+
+        let existing = Key::new();
+        store.insert(existing.clone(), Value::new("old")).unwrap();
+        let pages = store.len();
+        let size = store.get_file().metadata().unwrap().len();
+
+        let added = Key::new();
+
+        let mut tx = store.begin().unwrap();
+        tx.put(existing.clone(), Value::new("new")).unwrap();
+        tx.put(added.clone(), Value::new(vec![1u8; 3000])).unwrap();
+        tx.put(Key::new(), Value::new(vec![2u8; 3000])).unwrap();
+        tx.rollback().unwrap();
+
+        assert_eq!(store.get(&existing).unwrap().unwrap().get(), b"old");
+        assert!(!store.contains(&added).unwrap());
+        assert_eq!(store.len(), pages);
+        assert_eq!(store.get_file().metadata().unwrap().len(), size);
+    }
+
+    #[test]
+    fn test_dropped_transaction_rolls_back() {
+        let path = temp_store("test_tx_drop");
+        let mut store = Store::new(&path).unwrap();
+
+        let key = Key::new();
+        {
+            let mut tx = store.begin().unwrap();
+            tx.put(key.clone(), Value::new("uncommitted")).unwrap();
+        }
+
+        assert!(!store.contains(&key).unwrap());
+    }
+
+    #[test]
+    fn test_crash_before_commit_loses_whole_transaction() {
+        let path = temp_store("test_tx_crash_before_commit");
+
+        let kept = Key::new();
+        let a = Key::new();
+        let b = Key::new();
+
+        {
+            let mut store = Store::new(&path).unwrap();
+            store.insert(kept.clone(), Value::new("kept")).unwrap();
+
+            let mut tx = store.begin().unwrap();
+            tx.put(a.clone(), Value::new("a")).unwrap();
+            tx.put(b.clone(), Value::new("b")).unwrap();
+
+            // Simulate the process dying before commit
+            std::mem::forget(tx);
+            std::mem::forget(store);
+        }
+
+        let store = Store::new(&path).unwrap();
+
+        assert_eq!(store.get(&kept).unwrap().unwrap().get(), b"kept");
+        assert!(!store.contains(&a).unwrap());
+        assert!(!store.contains(&b).unwrap());
+    }
+
+    #[test]
+    fn test_committed_transaction_survives_reopen() {
+        let path = temp_store("test_tx_reopen");
+
+        let keys: Vec<Key> = (0..5).map(|_| Key::new()).collect();
+
+        {
+            let mut store = Store::new(&path).unwrap();
+            let mut tx = store.begin().unwrap();
+            for key in &keys {
+                tx.put(key.clone(), Value::new(vec![7u8; 1500])).unwrap();
+            }
+            tx.commit().unwrap();
+            std::mem::forget(store);
+        }
+
+        let store = Store::new(&path).unwrap();
+        for key in &keys {
+            assert_eq!(store.get(key).unwrap().unwrap().get(), &vec![7u8; 1500]);
+        }
+    }
+}
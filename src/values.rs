@@ -1,6 +1,7 @@
 use std::fmt::{Debug, Formatter};
 use std::fmt::Result;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Value<T> {
     data: T
 } 
@@ -17,6 +18,10 @@ impl<T> Value<T> {
     pub fn set(&mut self, data: T) {
         self.data = data;
     } 
+
+    pub fn into_inner(self) -> T {
+        self.data
+    } 
 } 
 
 impl<T: Default> Default for Value<T> {
@@ -0,0 +1,321 @@
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::page::PageFormat;
+
+const RECORD_PAGE_IMAGE: u8 = 1;
+const RECORD_COMMIT: u8 = 2;
+const RECORD_ENTRY: u8 = 3;
+
+// lsn + kind + location + payload length
+const RECORD_HEADER_SIZE: usize = 8 + 1 + 8 + 4;
+const RECORD_CHECKSUM_SIZE: usize = 4;
+
+// The log lives next to the store file, e.g. "data.kv" logs to "data.kv.wal"
+pub fn wal_path(store_path: &Path) -> PathBuf {
+    let mut path: OsString = store_path.as_os_str().to_owned();
+    path.push(".wal");
+    PathBuf::from(path)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WalRecord {
+    PageImage { lsn: u64, location: u64, bytes: Vec<u8> },
+    Entry { lsn: u64, bytes: Vec<u8> },
+    Commit { lsn: u64 },
+}
+
+// Append-only redo log of full page images, or of opaque entries for
+// engines that don't write pages in place. A batch of records followed by
+// a commit record is applied as a unit during recovery; anything after the
+// last commit, or after a torn record, is ignored.
+pub struct Wal {
+    file: File,
+    next_lsn: u64,
+}
+
+impl Wal {
+    pub fn open(path: &Path) -> Result<Wal, io::Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut wal = Wal {
+            file,
+            next_lsn: 1,
+        };
+
+        // Continue numbering after whatever is already in the log
+        if let Some(last) = wal.read_records()?.last() {
+            wal.next_lsn = match last {
+                WalRecord::PageImage { lsn, .. } => lsn + 1,
+                WalRecord::Entry { lsn, .. } => lsn + 1,
+                WalRecord::Commit { lsn } => lsn + 1,
+            };
+        }
+
+        Ok(wal)
+    }
+
+    pub fn next_lsn(&self) -> u64 {
+        self.next_lsn
+    }
+
+    pub fn len(&self) -> Result<u64, io::Error> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool, io::Error> {
+        Ok(self.len()? == 0)
+    }
+
+    fn append(&mut self, kind: u8, location: u64, payload: &[u8]) -> Result<u64, io::Error> {
+        let lsn = self.next_lsn;
+        self.next_lsn += 1;
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len() + RECORD_CHECKSUM_SIZE);
+        record.write_u64::<LittleEndian>(lsn)?;
+        record.write_u8(kind)?;
+        record.write_u64::<LittleEndian>(location)?;
+        record.write_u32::<LittleEndian>(payload.len() as u32)?;
+        record.extend_from_slice(payload);
+
+        let checksum = crc32fast::hash(&record);
+        record.write_u32::<LittleEndian>(checksum)?;
+
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&record)?;
+
+        Ok(lsn)
+    }
+
+    pub fn append_page(&mut self, location: u64, page: &PageFormat) -> Result<u64, io::Error> {
+        self.append(RECORD_PAGE_IMAGE, location, &page.serialize())
+    }
+
+    pub fn append_entry(&mut self, entry: &[u8]) -> Result<u64, io::Error> {
+        self.append(RECORD_ENTRY, 0, entry)
+    }
+
+    pub fn append_commit(&mut self) -> Result<u64, io::Error> {
+        self.append(RECORD_COMMIT, 0, &[])
+    }
+
+    pub fn sync(&mut self) -> Result<(), io::Error> {
+        self.file.sync_data()
+    }
+
+    // Empties the log once its pages are safely in the data file
+    pub fn truncate(&mut self) -> Result<(), io::Error> {
+        self.file.set_len(0)?;
+        self.file.sync_data()
+    }
+
+    fn read_records(&mut self) -> Result<Vec<WalRecord>, io::Error> {
+        let mut bytes = vec![];
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut bytes)?;
+
+        let mut records = vec![];
+        let mut offset = 0;
+
+        while offset + RECORD_HEADER_SIZE + RECORD_CHECKSUM_SIZE <= bytes.len() {
+            let mut header = &bytes[offset..offset + RECORD_HEADER_SIZE];
+            let lsn = header.read_u64::<LittleEndian>()?;
+            let kind = header.read_u8()?;
+            let location = header.read_u64::<LittleEndian>()?;
+            let len = header.read_u32::<LittleEndian>()? as usize;
+
+            let end = offset + RECORD_HEADER_SIZE + len;
+            if end + RECORD_CHECKSUM_SIZE > bytes.len() {
+                break;
+            }
+
+            // A bad checksum means the tail of the log was torn by a crash
+            let mut checksum_bytes = &bytes[end..end + RECORD_CHECKSUM_SIZE];
+            let checksum = checksum_bytes.read_u32::<LittleEndian>()?;
+            if checksum != crc32fast::hash(&bytes[offset..end]) {
+                break;
+            }
+
+            let payload = bytes[offset + RECORD_HEADER_SIZE..end].to_vec();
+            match kind {
+                RECORD_PAGE_IMAGE => records.push(WalRecord::PageImage { lsn, location, bytes: payload }),
+                RECORD_ENTRY => records.push(WalRecord::Entry { lsn, bytes: payload }),
+                RECORD_COMMIT => records.push(WalRecord::Commit { lsn }),
+                _ => break,
+            }
+
+            offset = end + RECORD_CHECKSUM_SIZE;
+        }
+
+        Ok(records)
+    }
+
+    fn committed_records(&mut self) -> Result<Vec<WalRecord>, io::Error> {
+        let mut committed = vec![];
+        let mut batch = vec![];
+
+        for record in self.read_records()? {
+            match record {
+                WalRecord::Commit { .. } => committed.append(&mut batch),
+                record => batch.push(record),
+            }
+        }
+
+        Ok(committed)
+    }
+
+    // Page images from committed batches, in log order
+    pub fn recover(&mut self) -> Result<Vec<(u64, Vec<u8>)>, io::Error> {
+        let images = self.committed_records()?
+            .into_iter()
+            .filter_map(|record| match record {
+                WalRecord::PageImage { location, bytes, .. } => Some((location, bytes)),
+                _ => None,
+            })
+            .collect();
+
+        Ok(images)
+    }
+
+    // Entries from committed batches, in log order
+    pub fn recover_entries(&mut self) -> Result<Vec<Vec<u8>>, io::Error> {
+        let entries = self.committed_records()?
+            .into_iter()
+            .filter_map(|record| match record {
+                WalRecord::Entry { bytes, .. } => Some(bytes),
+                _ => None,
+            })
+            .collect();
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_wal(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn page_with(record: &[u8]) -> PageFormat {
+        let mut page = PageFormat::new();
+        page.insert_record(record).unwrap();
+        page
+    }
+
+    #[test]
+    fn test_wal_path() {
+        assert_eq!(wal_path(Path::new("/tmp/data.kv")), PathBuf::from("/tmp/data.kv.wal"));
+    }
+
+    #[test]
+    fn test_recover_committed_pages() {
+        let path = temp_wal("test_wal_committed");
+        let mut wal = Wal::open(&path).unwrap();
+
+        let first = wal.append_page(4096, &page_with(b"a")).unwrap();
+        let second = wal.append_page(8192, &page_with(b"b")).unwrap();
+        wal.append_commit().unwrap();
+        wal.sync().unwrap();
+
+        assert!(second > first);
+
+        let images = wal.recover().unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].0, 4096);
+        assert_eq!(PageFormat::deserialize(images[1].1.clone()), page_with(b"b"));
+    }
+
+    #[test]
+    fn test_uncommitted_pages_are_ignored() {
+        let path = temp_wal("test_wal_uncommitted");
+        let mut wal = Wal::open(&path).unwrap();
+
+        wal.append_page(4096, &page_with(b"a")).unwrap();
+        wal.append_commit().unwrap();
+        wal.append_page(8192, &page_with(b"b")).unwrap();
+
+        let images = wal.recover().unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].0, 4096);
+    }
+
+    #[test]
+    fn test_recover_committed_entries() {
+        let path = temp_wal("test_wal_entries");
+        let mut wal = Wal::open(&path).unwrap();
+
+        wal.append_entry(b"first").unwrap();
+        wal.append_entry(b"second").unwrap();
+        wal.append_commit().unwrap();
+        wal.append_entry(b"uncommitted").unwrap();
+
+        assert_eq!(wal.recover_entries().unwrap(), vec![b"first".to_vec(), b"second".to_vec()]);
+        assert!(wal.recover().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_torn_record_ends_the_log() {
+        let path = temp_wal("test_wal_torn");
+
+        {
+            let mut wal = Wal::open(&path).unwrap();
+            wal.append_page(4096, &page_with(b"a")).unwrap();
+            wal.append_commit().unwrap();
+            wal.append_page(8192, &page_with(b"b")).unwrap();
+            wal.append_commit().unwrap();
+        }
+
+        // Chop the last commit record in half
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 10).unwrap();
+
+        let mut wal = Wal::open(&path).unwrap();
+        let images = wal.recover().unwrap();
+
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].0, 4096);
+    }
+
+    #[test]
+    fn test_lsns_continue_after_reopen() {
+        let path = temp_wal("test_wal_lsn");
+
+        let last = {
+            let mut wal = Wal::open(&path).unwrap();
+            wal.append_page(0, &page_with(b"a")).unwrap();
+            wal.append_commit().unwrap()
+        };
+
+        let wal = Wal::open(&path).unwrap();
+        assert_eq!(wal.next_lsn(), last + 1);
+    }
+
+    #[test]
+    fn test_truncate_empties_log() {
+        let path = temp_wal("test_wal_truncate");
+        let mut wal = Wal::open(&path).unwrap();
+
+        wal.append_page(0, &page_with(b"a")).unwrap();
+        wal.append_commit().unwrap();
+        wal.truncate().unwrap();
+
+        assert!(wal.is_empty().unwrap());
+        assert!(wal.recover().unwrap().is_empty());
+    }
+}